@@ -1,3 +1,4 @@
+use std::time::Duration;
 use clap::{Parser, ArgGroup, value_parser};
 use net_addresses::getaddrinfo::{AddrFamily, SockType, Protocol};
 
@@ -5,7 +6,7 @@ use net_addresses::getaddrinfo::{AddrFamily, SockType, Protocol};
 #[command(
     version = "1.0",
     about = "CLI tool for resolving network addresses and services",
-    group = ArgGroup::new("target").required(true).multiple(true).args(&["host", "service"])
+    group = ArgGroup::new("target").required(true).multiple(true).args(&["host", "service", "interactive"])
 )]
 pub struct CliArgs {
     /// IPv4, IPv6, or domain name (e.g., 8.8.4.4, ::1, example.com)
@@ -35,6 +36,45 @@ pub struct CliArgs {
     /// Verbose output level (0-2)
     #[arg(short = 'v', long = "verbose", name = "LEVEL", value_parser = value_parser!(u8).range(0..=2), default_value = "0")]
     pub verbose: u8,
+
+    /// Re-resolve at the given interval and report changes (e.g., 30, 500ms, 10s, 5m, 1h)
+    #[arg(short = 'w', long = "watch", value_name = "INTERVAL", value_parser = parse_interval)]
+    pub watch: Option<Duration>,
+
+    /// Print watch events as JSON lines
+    #[arg(short = 'j', long = "jsonl", requires = "watch")]
+    pub jsonl: bool,
+
+    /// Start an interactive shell (settings from other options are used as defaults)
+    #[arg(short = 'i', long = "interactive", conflicts_with = "watch")]
+    pub interactive: bool,
+}
+
+/// Parses an interval given as a number with an optional unit suffix (ms, s, m, h).
+/// A number without a suffix is interpreted as seconds.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let split_at: usize = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit): (&str, &str) = s.split_at(split_at);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("invalid interval: {:?}", s))?;
+
+    let interval: Duration = match unit {
+        "ms" => Duration::from_millis(value),
+        "" | "s" => Duration::from_secs(value),
+        "m" => Duration::from_secs(value.saturating_mul(60)),
+        "h" => Duration::from_secs(value.saturating_mul(3600)),
+        _ => Err(format!(
+            "invalid interval unit: {:?} (expected ms, s, m or h)",
+            unit
+        ))?,
+    };
+
+    if interval.is_zero() {
+        Err("interval must be greater than zero".to_string())?;
+    }
+
+    Ok(interval)
 }
 
 #[cfg(test)]
@@ -136,4 +176,62 @@ mod tests {
                 && args.protocol == Protocol::Tcp
         );
     }
+
+    #[test]
+    fn test_cliargs_parses_watch_interval() {
+        // GIVEN
+        let argv: [&str; 6] = ["--", "-H", "example.com", "--watch", "500ms", "--jsonl"];
+        // WHEN
+        let args: CliArgs = CliArgs::parse_from(argv);
+        // THEN
+        assert_eq!(args.watch, Some(Duration::from_millis(500)));
+        assert!(args.jsonl);
+    }
+
+    #[test]
+    fn test_cliargs_fails_with_invalid_watch_interval() {
+        for interval in ["0", "10x", "s", "5d"] {
+            // GIVEN
+            let argv: [&str; 5] = ["--", "-H", "example.com", "--watch", interval];
+            // WHEN
+            let result: Result<CliArgs, Error> = CliArgs::try_parse_from(argv);
+            // THEN
+            assert!(
+                result.is_err_and(|e| e.kind() == ErrorKind::ValueValidation),
+                "{:?}",
+                interval
+            );
+        }
+    }
+
+    #[test]
+    fn test_cliargs_parses_interactive_without_target() {
+        // GIVEN
+        let argv: [&str; 4] = ["--", "-i", "-f", "inet"];
+        // WHEN
+        let args: CliArgs = CliArgs::parse_from(argv);
+        // THEN
+        assert!(args.interactive && args.host.is_none() && args.service.is_none());
+        assert!(args.family == AddrFamily::Inet);
+    }
+
+    #[test]
+    fn test_cliargs_fails_with_interactive_and_watch() {
+        // GIVEN
+        let argv: [&str; 5] = ["--", "-i", "-H", "example.com", "--watch=5s"];
+        // WHEN
+        let result: Result<CliArgs, Error> = CliArgs::try_parse_from(argv);
+        // THEN
+        assert!(result.is_err_and(|e| e.kind() == ErrorKind::ArgumentConflict));
+    }
+
+    #[test]
+    fn test_cliargs_fails_with_jsonl_without_watch() {
+        // GIVEN
+        let argv: [&str; 4] = ["--", "-H", "example.com", "--jsonl"];
+        // WHEN
+        let result: Result<CliArgs, Error> = CliArgs::try_parse_from(argv);
+        // THEN
+        assert!(result.is_err_and(|e| e.kind() == ErrorKind::MissingRequiredArgument));
+    }
 }
@@ -1,17 +1,36 @@
 use std::{ptr, fmt};
 use std::mem::MaybeUninit;
 use std::ffi::{CStr, CString};
-use std::net::SocketAddr;
+use std::slice;
+use std::net::{IpAddr, SocketAddr};
 use std::iter::FusedIterator;
+use std::collections::HashSet;
 use std::io::{self, Error, ErrorKind};
 use socket2::SockAddr;
 use clap::ValueEnum;
 
+use crate::ipnet::AddrClass;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
 use libc::{
     c_int, addrinfo, AF_UNSPEC, AF_INET, AF_INET6, SOCK_STREAM, SOCK_DGRAM, SOCK_RAW,
-    SOCK_SEQPACKET, IPPROTO_TCP, IPPROTO_UDP, IPPROTO_SCTP, IPPROTO_IP,
+    SOCK_SEQPACKET, IPPROTO_TCP, IPPROTO_UDP, IPPROTO_SCTP, IPPROTO_IP, AI_PASSIVE, AI_CANONNAME,
+    AI_NUMERICHOST, AI_V4MAPPED, AI_ALL, AI_ADDRCONFIG, AI_NUMERICSERV,
 };
 
+/// Names of the `ai_flags` bits
+pub const AI_FLAG_NAMES: &[(c_int, &str)] = &[
+    (AI_PASSIVE, "AI_PASSIVE"),
+    (AI_CANONNAME, "AI_CANONNAME"),
+    (AI_NUMERICHOST, "AI_NUMERICHOST"),
+    (AI_V4MAPPED, "AI_V4MAPPED"),
+    (AI_ALL, "AI_ALL"),
+    (AI_ADDRCONFIG, "AI_ADDRCONFIG"),
+    (AI_NUMERICSERV, "AI_NUMERICSERV"),
+];
+
 macro_rules! impl_debug {
     ($enum:ty, $($variant:ident => $debug_name:expr),+ $(,)?) => {
         impl fmt::Debug for $enum {
@@ -38,7 +57,12 @@ macro_rules! impl_display {
 
 /// Address family
 #[repr(i32)]
-#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum AddrFamily {
     #[default]
     Unspecified = AF_UNSPEC,
@@ -73,7 +97,12 @@ impl From<c_int> for AddrFamily {
 
 /// Socket type
 #[repr(i32)]
-#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum SockType {
     #[default]
     Unspecified = 0,
@@ -116,7 +145,12 @@ impl From<c_int> for SockType {
 
 /// Protocol
 #[repr(i32)]
-#[derive(Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum Protocol {
     #[default]
     Unspecified = IPPROTO_IP,
@@ -155,7 +189,9 @@ impl From<c_int> for Protocol {
 
 /// Holds optional hints or preferences for address resolution
 #[derive(Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AddrInfoHints {
+    #[cfg_attr(feature = "serde", serde(with = "flags_serde"))]
     pub flags: i32,
     pub family: AddrFamily,
     pub socktype: SockType,
@@ -193,7 +229,9 @@ impl AddrInfoHints {
 
 /// Consolidates the address info returned by [`getaddrinfo`]
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AddrInfo {
+    #[cfg_attr(feature = "serde", serde(with = "flags_serde"))]
     pub flags: i32,
     pub family: AddrFamily,
     pub socktype: SockType,
@@ -216,6 +254,7 @@ impl fmt::Debug for AddrInfo {
             .field("canonname", &self.canonname.as_deref().unwrap_or("None"))
             .field("domain", &domain)
             .field("service", &service)
+            .field("class", &AddrClass::of(self.socket_addr.ip()))
             .finish()
     }
 }
@@ -288,6 +327,53 @@ impl AddrInfo {
     }
 }
 
+/// (De)serializes `ai_flags` as a list of flag names (e.g., `["AI_PASSIVE", "AI_CANONNAME"]`).
+/// Bits without a known name are kept as a hexadecimal string (e.g., `"0x800"`).
+#[cfg(feature = "serde")]
+mod flags_serde {
+    use super::AI_FLAG_NAMES;
+    use serde::{Serializer, Deserializer, Deserialize};
+    use serde::de::Error as _;
+    use serde::ser::SerializeSeq as _;
+
+    pub fn serialize<S: Serializer>(flags: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        let unknown: i32 = AI_FLAG_NAMES
+            .iter()
+            .fold(*flags, |rest, &(bit, _)| rest & !bit);
+        let names = AI_FLAG_NAMES
+            .iter()
+            .filter(|&&(bit, _)| flags & bit != 0)
+            .map(|&(_, name)| name);
+
+        let mut seq = serializer.serialize_seq(None)?;
+        for name in names {
+            seq.serialize_element(name)?;
+        }
+        if unknown != 0 {
+            seq.serialize_element(&format!("{:#x}", unknown))?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .try_fold(0, |flags, name| {
+                let bit: i32 = match AI_FLAG_NAMES.iter().find(|&&(_, n)| n == name) {
+                    Some(&(bit, _)) => bit,
+                    // Unknown flags are written as the bit pattern, so the sign bit reads as u32
+                    None => name
+                        .strip_prefix("0x")
+                        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                        .map(|bits| bits as i32)
+                        .ok_or_else(|| D::Error::custom(format!("unknown flag: {:?}", name)))?,
+                };
+
+                Ok(flags | bit)
+            })
+    }
+}
+
 /// An iterator over the linked list created by a `getaddrinfo` call
 #[derive(Debug)]
 pub struct AddrInfoIter {
@@ -317,6 +403,134 @@ impl Drop for AddrInfoIter {
     }
 }
 
+/// An owned collection of the address records returned by [`getaddrinfo`].
+///
+/// Unlike [`AddrInfoIter`], it does not hold any pointers into the resolver's memory,
+/// so it can be sent and shared between threads. Records that failed to convert are
+/// kept separately in [`AddrInfoList::errors`], so partial failures stay visible.
+///
+/// Filters treat the `Unspecified` value as "any", matching the semantics of [`AddrInfoHints`].
+#[derive(Debug, Default)]
+pub struct AddrInfoList {
+    records: Vec<AddrInfo>,
+    errors: Vec<io::Error>,
+}
+
+impl AddrInfoList {
+    /// Resolves the names with [`getaddrinfo`] and collects the results.
+    pub fn resolve(
+        host: Option<&str>,
+        service: Option<&str>,
+        hints: Option<AddrInfoHints>,
+    ) -> io::Result<Self> {
+        getaddrinfo(host, service, hints).map(Self::from_iter)
+    }
+
+    pub fn records(&self) -> &[AddrInfo] {
+        &self.records
+    }
+
+    /// Errors of the records that could not be converted into [`AddrInfo`]
+    pub fn errors(&self) -> &[io::Error] {
+        &self.errors
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, AddrInfo> {
+        self.records.iter()
+    }
+
+    /// Splits the list into the records and the errors.
+    pub fn into_parts(self) -> (Vec<AddrInfo>, Vec<io::Error>) {
+        (self.records, self.errors)
+    }
+
+    /// Keeps only the records that match the predicate.
+    pub fn filter(mut self, predicate: impl FnMut(&AddrInfo) -> bool) -> Self {
+        self.records.retain(predicate);
+        self
+    }
+
+    pub fn filter_family(self, family: AddrFamily) -> Self {
+        self.filter(|ai| family == AddrFamily::Unspecified || ai.family == family)
+    }
+
+    pub fn filter_socktype(self, socktype: SockType) -> Self {
+        self.filter(|ai| socktype == SockType::Unspecified || ai.socktype == socktype)
+    }
+
+    pub fn filter_protocol(self, protocol: Protocol) -> Self {
+        self.filter(|ai| protocol == Protocol::Unspecified || ai.protocol == protocol)
+    }
+
+    /// Removes records with a socket address that has already been seen,
+    /// keeping the first record for each address.
+    pub fn dedup_by_addr(mut self) -> Self {
+        let mut seen: HashSet<SocketAddr> = HashSet::new();
+        self.records.retain(|ai| seen.insert(ai.socket_addr));
+        self
+    }
+
+    /// Groups the records by IP address, in the order the addresses first appear.
+    pub fn group_by_addr(&self) -> Vec<(IpAddr, Vec<&AddrInfo>)> {
+        self.records.iter().fold(Vec::new(), |mut groups, ai| {
+            let ip: IpAddr = ai.socket_addr.ip();
+            match groups.iter_mut().find(|(group_ip, _)| *group_ip == ip) {
+                Some((_, group)) => group.push(ai),
+                None => groups.push((ip, vec![ai])),
+            }
+            groups
+        })
+    }
+}
+
+impl FromIterator<io::Result<AddrInfo>> for AddrInfoList {
+    fn from_iter<I: IntoIterator<Item = io::Result<AddrInfo>>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::default(), |mut list, result| {
+            match result {
+                Ok(ai) => list.records.push(ai),
+                Err(e) => list.errors.push(e),
+            }
+            list
+        })
+    }
+}
+
+impl From<AddrInfoIter> for AddrInfoList {
+    fn from(iter: AddrInfoIter) -> Self {
+        iter.collect()
+    }
+}
+
+impl IntoIterator for AddrInfoList {
+    type Item = AddrInfo;
+    type IntoIter = std::vec::IntoIter<AddrInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a AddrInfoList {
+    type Item = &'a AddrInfo;
+    type IntoIter = slice::Iter<'a, AddrInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.records.iter()
+    }
+}
+
 /// Translates the name of a service location and/or a service name and returns
 /// an iterator over the resulting address records.
 ///
@@ -402,7 +616,8 @@ mod tests {
             socket_addr: 127.0.0.1:80, \
             canonname: \"localhost\", \
             domain: \"localhost\", \
-            service: \"http\" }";
+            service: \"http\", \
+            class: Loopback }";
         // WHEN + THEN
         assert_eq!(format!("{:?}", addrinfo), expected_debug_output);
     }
@@ -496,6 +711,185 @@ mod tests {
         assert!(sockaddrs.iter().any(|ai| ai.socket_addr == expected_sa_2));
     }
 
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_addrinfo_serde_round_trip() {
+        // GIVEN
+        let addrinfo: AddrInfo = get_addrinfo();
+        let expected_json: &str = "{\
+            \"flags\":[\"AI_PASSIVE\",\"AI_CANONNAME\"],\
+            \"family\":\"inet\",\
+            \"socktype\":\"stream\",\
+            \"protocol\":\"unspecified\",\
+            \"socket_addr\":\"127.0.0.1:80\",\
+            \"canonname\":\"localhost\"}";
+        // WHEN
+        let json: String = serde_json::to_string(&addrinfo).unwrap();
+        let decoded: AddrInfo = serde_json::from_str(&json).unwrap();
+        // THEN
+        assert_eq!(json, expected_json);
+        assert_eq!(decoded.to_string(), addrinfo.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_addrinfohints_serde_round_trip() {
+        // GIVEN
+        let hints: AddrInfoHints = AddrInfoHints::new(
+            AI_NUMERICSERV | 0x4000 | i32::MIN,
+            AF_INET6,
+            SOCK_SEQPACKET,
+            IPPROTO_SCTP,
+        );
+        let expected_json: &str = "{\
+            \"flags\":[\"AI_NUMERICSERV\",\"0x80004000\"],\
+            \"family\":\"inet6\",\
+            \"socktype\":\"seq-packet\",\
+            \"protocol\":\"sctp\"}";
+        // WHEN
+        let json: String = serde_json::to_string(&hints).unwrap();
+        let decoded: AddrInfoHints = serde_json::from_str(&json).unwrap();
+        // THEN
+        assert_eq!(json, expected_json);
+        assert_eq!(decoded.flags, hints.flags);
+        assert!(
+            decoded.family == hints.family
+                && decoded.socktype == hints.socktype
+                && decoded.protocol == hints.protocol
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_rejects_unknown_values() {
+        let unknown_flag: &str =
+            r#"{"flags":["AI_BOGUS"],"family":"inet","socktype":"stream","protocol":"tcp"}"#;
+        let unknown_family: &str =
+            r#"{"flags":[],"family":"unix","socktype":"stream","protocol":"tcp"}"#;
+
+        assert!(serde_json::from_str::<AddrInfoHints>(unknown_flag).is_err());
+        assert!(serde_json::from_str::<AddrInfoHints>(unknown_family).is_err());
+    }
+
+    // Returns a sample AddrInfoList (with one failed record) for testing purposes
+    fn get_addrinfo_list() -> AddrInfoList {
+        let record =
+            |socket_addr: &str, socktype: SockType, protocol: Protocol| -> io::Result<AddrInfo> {
+                let socket_addr: SocketAddr = socket_addr.parse().unwrap();
+                Ok(AddrInfo {
+                    flags: 0,
+                    family: if socket_addr.is_ipv4() {
+                        AddrFamily::Inet
+                    } else {
+                        AddrFamily::Inet6
+                    },
+                    socktype,
+                    protocol,
+                    socket_addr,
+                    canonname: None,
+                })
+            };
+
+        [
+            record("10.0.0.1:80", SockType::Stream, Protocol::Tcp),
+            record("10.0.0.1:80", SockType::Datagram, Protocol::Udp),
+            Err(Error::new(
+                ErrorKind::Unsupported,
+                "Unsupported socket address family",
+            )),
+            record("[2001:db8::1]:80", SockType::Stream, Protocol::Tcp),
+            record("10.0.0.2:80", SockType::Stream, Protocol::Tcp),
+            record("[2001:db8::1]:80", SockType::Datagram, Protocol::Udp),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn test_addrinfolist_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<AddrInfoList>();
+    }
+
+    #[test]
+    fn test_addrinfolist_keeps_errors_separately() {
+        // WHEN
+        let list: AddrInfoList = get_addrinfo_list();
+        // THEN
+        assert_eq!(list.len(), 5);
+        assert!(list.has_errors());
+        assert_eq!(list.errors()[0].kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_addrinfolist_filters() {
+        // GIVEN
+        let list: AddrInfoList = get_addrinfo_list();
+        // WHEN
+        let filtered: AddrInfoList = list
+            .filter_family(AddrFamily::Inet)
+            .filter_socktype(SockType::Stream)
+            .filter_protocol(Protocol::Unspecified);
+        // THEN
+        assert_eq!(filtered.len(), 2);
+        assert!(filtered
+            .iter()
+            .all(|ai| ai.family == AddrFamily::Inet && ai.socktype == SockType::Stream));
+        assert!(filtered.has_errors());
+    }
+
+    #[test]
+    fn test_addrinfolist_dedup_by_addr() {
+        // GIVEN
+        let list: AddrInfoList = get_addrinfo_list();
+        // WHEN
+        let addrs: Vec<String> = list
+            .dedup_by_addr()
+            .into_iter()
+            .map(|ai| ai.socket_addr.to_string())
+            .collect();
+        // THEN
+        assert_eq!(addrs, ["10.0.0.1:80", "[2001:db8::1]:80", "10.0.0.2:80"]);
+    }
+
+    #[test]
+    fn test_addrinfolist_group_by_addr() {
+        // GIVEN
+        let list: AddrInfoList = get_addrinfo_list();
+        // WHEN
+        let groups: Vec<(IpAddr, Vec<&AddrInfo>)> = list.group_by_addr();
+        // THEN
+        let summary: Vec<(String, usize)> = groups
+            .iter()
+            .map(|(ip, records)| (ip.to_string(), records.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("10.0.0.1".into(), 2),
+                ("2001:db8::1".into(), 2),
+                ("10.0.0.2".into(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_addrinfolist_resolves_numeric_host() {
+        // GIVEN
+        let hints: AddrInfoHints =
+            AddrInfoHints::new(AI_NUMERICHOST, AF_INET, SOCK_STREAM, IPPROTO_TCP);
+        // WHEN
+        let list: AddrInfoList =
+            AddrInfoList::resolve(Some("127.0.0.1"), Some("80"), Some(hints)).unwrap();
+        // THEN
+        assert_eq!(list.len(), 1);
+        assert!(!list.has_errors());
+        assert_eq!(
+            list.records()[0].socket_addr,
+            "127.0.0.1:80".parse().unwrap()
+        );
+    }
+
     #[test]
     fn test_getaddrinfo_missing_host_and_service() {
         // WHEN
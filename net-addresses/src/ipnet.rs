@@ -0,0 +1,723 @@
+use std::fmt;
+use std::str::FromStr;
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Returns a mask with the lowest `host_bits` bits set.
+fn host_mask(host_bits: u8) -> u128 {
+    match host_bits {
+        128.. => u128::MAX,
+        bits => (1 << bits) - 1,
+    }
+}
+
+/// Splits the inclusive range `[start, end]` into the smallest set of prefixes
+/// that cover it exactly. Returns `(first address, prefix length)` pairs.
+fn range_to_prefixes(mut start: u128, end: u128, max_prefix_len: u8) -> Vec<(u128, u8)> {
+    let mut prefixes: Vec<(u128, u8)> = Vec::new();
+
+    loop {
+        // The block must be aligned on its size and must not go past the end of the range
+        let mut host_bits: u8 = (start.trailing_zeros() as u8).min(max_prefix_len);
+        while start.saturating_add(host_mask(host_bits)) > end {
+            host_bits -= 1;
+        }
+        prefixes.push((start, max_prefix_len - host_bits));
+
+        let last: u128 = start + host_mask(host_bits);
+        if last >= end {
+            break prefixes;
+        }
+        start = last + 1;
+    }
+}
+
+/// Merges overlapping and adjacent `(network, last address)` ranges and converts
+/// the result back into the smallest set of prefixes.
+fn aggregate_ranges(mut ranges: Vec<(u128, u128)>, max_prefix_len: u8) -> Vec<(u128, u8)> {
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u128, u128)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= last_end.saturating_add(1) => {
+                *last_end = (*last_end).max(end)
+            }
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+        .into_iter()
+        .flat_map(|(start, end)| range_to_prefixes(start, end, max_prefix_len))
+        .collect()
+}
+
+/// Parses the "<address>/<prefix length>" notation. A bare address is treated as a host prefix.
+fn parse_prefix<A: FromStr>(s: &str, max_prefix_len: u8) -> io::Result<(A, u8)> {
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid IP network: {:?}", s),
+        )
+    };
+    let (addr, prefix_len): (&str, Option<&str>) = match s.split_once('/') {
+        Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+        None => (s, None),
+    };
+
+    let addr: A = addr.parse().map_err(|_| invalid())?;
+    let prefix_len: u8 = match prefix_len {
+        Some(p) if !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit()) => {
+            p.parse().map_err(|_| invalid())?
+        }
+        Some(_) => Err(invalid())?,
+        None => max_prefix_len,
+    };
+    if prefix_len > max_prefix_len {
+        Err(invalid())?;
+    }
+
+    Ok((addr, prefix_len))
+}
+
+macro_rules! impl_ip_net {
+    ($net:ident, $addr:ty, $bits:ty, $max_prefix_len:expr) => {
+        impl $net {
+            pub const MAX_PREFIX_LEN: u8 = $max_prefix_len;
+
+            /// Creates a new network from an address and a prefix length.
+            /// Host bits of `addr` are kept; use [`Self::trunc`] to clear them.
+            pub fn new(addr: $addr, prefix_len: u8) -> io::Result<Self> {
+                if prefix_len > Self::MAX_PREFIX_LEN {
+                    Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid prefix length: {} (max: {})",
+                            prefix_len,
+                            Self::MAX_PREFIX_LEN
+                        ),
+                    ))?;
+                }
+
+                Ok(Self { addr, prefix_len })
+            }
+
+            pub fn addr(&self) -> $addr {
+                self.addr
+            }
+
+            pub fn prefix_len(&self) -> u8 {
+                self.prefix_len
+            }
+
+            fn host_bits(&self) -> u8 {
+                Self::MAX_PREFIX_LEN - self.prefix_len
+            }
+
+            pub fn hostmask(&self) -> $addr {
+                <$addr>::from(host_mask(self.host_bits()) as $bits)
+            }
+
+            pub fn netmask(&self) -> $addr {
+                <$addr>::from(!(host_mask(self.host_bits()) as $bits))
+            }
+
+            /// Returns the first address of the network.
+            pub fn network(&self) -> $addr {
+                <$addr>::from(<$bits>::from(self.addr) & <$bits>::from(self.netmask()))
+            }
+
+            /// Returns the last address of the network.
+            pub fn last_addr(&self) -> $addr {
+                <$addr>::from(<$bits>::from(self.addr) | <$bits>::from(self.hostmask()))
+            }
+
+            /// Returns the same network with the host bits cleared.
+            pub fn trunc(&self) -> Self {
+                Self {
+                    addr: self.network(),
+                    prefix_len: self.prefix_len,
+                }
+            }
+
+            pub fn contains(&self, addr: &$addr) -> bool {
+                (self.network()..=self.last_addr()).contains(addr)
+            }
+
+            /// Returns `true` if `other` is the same network or one of its subnets.
+            pub fn contains_net(&self, other: &Self) -> bool {
+                self.prefix_len <= other.prefix_len && self.contains(&other.addr)
+            }
+
+            /// Returns the network with a prefix one bit shorter, if any.
+            pub fn supernet(&self) -> Option<Self> {
+                let prefix_len: u8 = self.prefix_len.checked_sub(1)?;
+
+                Self::new(self.addr, prefix_len).ok().map(|net| net.trunc())
+            }
+
+            /// Returns an iterator over the subnets of the given prefix length.
+            pub fn subnets(&self, new_prefix_len: u8) -> io::Result<Subnets<Self>> {
+                if new_prefix_len < self.prefix_len || new_prefix_len > Self::MAX_PREFIX_LEN {
+                    Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Invalid subnet prefix length: {} (network: {})",
+                            new_prefix_len, self
+                        ),
+                    ))?;
+                }
+
+                Ok(Subnets {
+                    next: Some(<$bits>::from(self.network()) as u128),
+                    last: <$bits>::from(self.last_addr()) as u128,
+                    prefix_len: new_prefix_len,
+                    _net: std::marker::PhantomData,
+                })
+            }
+
+            /// Merges overlapping and adjacent networks into the smallest equivalent set.
+            pub fn aggregate(networks: &[Self]) -> Vec<Self> {
+                let ranges: Vec<(u128, u128)> = networks
+                    .iter()
+                    .map(|net| {
+                        (
+                            <$bits>::from(net.network()) as u128,
+                            <$bits>::from(net.last_addr()) as u128,
+                        )
+                    })
+                    .collect();
+
+                aggregate_ranges(ranges, Self::MAX_PREFIX_LEN)
+                    .into_iter()
+                    .map(|(addr, prefix_len)| Self::from_bits(addr, prefix_len))
+                    .collect()
+            }
+        }
+
+        impl SubnetBits for $net {
+            fn from_bits(addr: u128, prefix_len: u8) -> Self {
+                Self {
+                    addr: <$addr>::from(addr as $bits),
+                    prefix_len,
+                }
+            }
+
+            fn max_prefix_len() -> u8 {
+                Self::MAX_PREFIX_LEN
+            }
+        }
+
+        impl FromStr for $net {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let (addr, prefix_len): ($addr, u8) = parse_prefix(s, Self::MAX_PREFIX_LEN)?;
+
+                Ok(Self { addr, prefix_len })
+            }
+        }
+
+        impl fmt::Display for $net {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}/{}", self.addr, self.prefix_len)
+            }
+        }
+    };
+}
+
+/// Conversion from the integer form used by [`Subnets`]
+pub trait SubnetBits: Sized {
+    fn from_bits(addr: u128, prefix_len: u8) -> Self;
+    fn max_prefix_len() -> u8;
+}
+
+/// IPv4 network (e.g., 192.168.0.0/16)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ipv4Net {
+    addr: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl_ip_net!(Ipv4Net, Ipv4Addr, u32, 32);
+
+/// IPv6 network (e.g., 2001:db8::/32)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Ipv6Net {
+    addr: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl_ip_net!(Ipv6Net, Ipv6Addr, u128, 128);
+
+/// An iterator over the subnets of a network
+#[derive(Debug, Clone)]
+pub struct Subnets<N> {
+    next: Option<u128>,
+    last: u128,
+    prefix_len: u8,
+    _net: std::marker::PhantomData<N>,
+}
+
+impl<N: SubnetBits> Iterator for Subnets<N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let addr: u128 = self.next?;
+        let subnet_last: u128 = addr + host_mask(N::max_prefix_len() - self.prefix_len);
+        self.next = (subnet_last < self.last).then(|| subnet_last + 1);
+
+        Some(N::from_bits(addr, self.prefix_len))
+    }
+}
+
+impl<N: SubnetBits> std::iter::FusedIterator for Subnets<N> {}
+
+/// IPv4 or IPv6 network
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum IpNet {
+    V4(Ipv4Net),
+    V6(Ipv6Net),
+}
+
+impl IpNet {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> io::Result<Self> {
+        match addr {
+            IpAddr::V4(addr) => Ipv4Net::new(addr, prefix_len).map(Self::V4),
+            IpAddr::V6(addr) => Ipv6Net::new(addr, prefix_len).map(Self::V6),
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        match self {
+            Self::V4(net) => net.addr().into(),
+            Self::V6(net) => net.addr().into(),
+        }
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        match self {
+            Self::V4(net) => net.prefix_len(),
+            Self::V6(net) => net.prefix_len(),
+        }
+    }
+
+    pub fn network(&self) -> IpAddr {
+        match self {
+            Self::V4(net) => net.network().into(),
+            Self::V6(net) => net.network().into(),
+        }
+    }
+
+    pub fn last_addr(&self) -> IpAddr {
+        match self {
+            Self::V4(net) => net.last_addr().into(),
+            Self::V6(net) => net.last_addr().into(),
+        }
+    }
+
+    pub fn trunc(&self) -> Self {
+        match self {
+            Self::V4(net) => Self::V4(net.trunc()),
+            Self::V6(net) => Self::V6(net.trunc()),
+        }
+    }
+
+    /// Returns `true` if the address belongs to the network.
+    /// Addresses of the other family are never contained.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self, addr) {
+            (Self::V4(net), IpAddr::V4(addr)) => net.contains(addr),
+            (Self::V6(net), IpAddr::V6(addr)) => net.contains(addr),
+            _ => false,
+        }
+    }
+
+    pub fn contains_net(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::V4(net), Self::V4(other)) => net.contains_net(other),
+            (Self::V6(net), Self::V6(other)) => net.contains_net(other),
+            _ => false,
+        }
+    }
+
+    pub fn supernet(&self) -> Option<Self> {
+        match self {
+            Self::V4(net) => net.supernet().map(Self::V4),
+            Self::V6(net) => net.supernet().map(Self::V6),
+        }
+    }
+
+    /// Merges overlapping and adjacent networks of each family into the smallest equivalent set.
+    /// IPv4 networks come first in the result.
+    pub fn aggregate(networks: &[Self]) -> Vec<Self> {
+        let (v4, v6): (Vec<Ipv4Net>, Vec<Ipv6Net>) =
+            networks
+                .iter()
+                .fold((Vec::new(), Vec::new()), |(mut v4, mut v6), net| {
+                    match net {
+                        Self::V4(net) => v4.push(*net),
+                        Self::V6(net) => v6.push(*net),
+                    }
+                    (v4, v6)
+                });
+
+        Ipv4Net::aggregate(&v4)
+            .into_iter()
+            .map(Self::V4)
+            .chain(Ipv6Net::aggregate(&v6).into_iter().map(Self::V6))
+            .collect()
+    }
+}
+
+impl From<Ipv4Net> for IpNet {
+    fn from(net: Ipv4Net) -> Self {
+        Self::V4(net)
+    }
+}
+
+impl From<Ipv6Net> for IpNet {
+    fn from(net: Ipv6Net) -> Self {
+        Self::V6(net)
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<Ipv4Net>() {
+            Ok(net) => Ok(Self::V4(net)),
+            Err(_) => s.parse::<Ipv6Net>().map(Self::V6),
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::V4(net) => net.fmt(f),
+            Self::V6(net) => net.fmt(f),
+        }
+    }
+}
+
+/// Builds a network of the address class tables. Evaluated at compile time, so an invalid
+/// prefix length or a network with host bits set fails the build.
+const fn v4_net(octets: [u8; 4], prefix_len: u8) -> Ipv4Net {
+    let addr: Ipv4Addr = Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]);
+    let hostmask: u32 = match u32::MAX.checked_shr(prefix_len as u32) {
+        Some(mask) => mask,
+        None => 0,
+    };
+    assert!(prefix_len <= Ipv4Net::MAX_PREFIX_LEN && addr.to_bits() & hostmask == 0);
+
+    Ipv4Net { addr, prefix_len }
+}
+
+/// IPv6 counterpart of [`v4_net`].
+const fn v6_net(segments: [u16; 8], prefix_len: u8) -> Ipv6Net {
+    let [a, b, c, d, e, f, g, h] = segments;
+    let addr: Ipv6Addr = Ipv6Addr::new(a, b, c, d, e, f, g, h);
+    let hostmask: u128 = match u128::MAX.checked_shr(prefix_len as u32) {
+        Some(mask) => mask,
+        None => 0,
+    };
+    assert!(prefix_len <= Ipv6Net::MAX_PREFIX_LEN && addr.to_bits() & hostmask == 0);
+
+    Ipv6Net { addr, prefix_len }
+}
+
+/// Address class (scope) of an IP address
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AddrClass {
+    Unspecified,
+    Loopback,
+    Private,
+    Cgnat,
+    LinkLocal,
+    Documentation,
+    Multicast,
+    UniqueLocal,
+    Reserved,
+    Global,
+}
+
+impl AddrClass {
+    /// Classifies an IP address. IPv4-mapped IPv6 addresses are classified as IPv4.
+    pub fn of(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(addr) => Self::of_ipv4(addr),
+            IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+                Some(addr) => Self::of_ipv4(addr),
+                None => Self::of_ipv6(addr),
+            },
+        }
+    }
+
+    fn of_ipv4(addr: Ipv4Addr) -> Self {
+        const CLASSES: &[(Ipv4Net, AddrClass)] = &[
+            (v4_net([0, 0, 0, 0], 32), AddrClass::Unspecified),
+            (v4_net([0, 0, 0, 0], 8), AddrClass::Reserved),
+            (v4_net([127, 0, 0, 0], 8), AddrClass::Loopback),
+            (v4_net([10, 0, 0, 0], 8), AddrClass::Private),
+            (v4_net([172, 16, 0, 0], 12), AddrClass::Private),
+            (v4_net([192, 168, 0, 0], 16), AddrClass::Private),
+            (v4_net([100, 64, 0, 0], 10), AddrClass::Cgnat),
+            (v4_net([169, 254, 0, 0], 16), AddrClass::LinkLocal),
+            (v4_net([192, 0, 2, 0], 24), AddrClass::Documentation), // TEST-NET-1
+            (v4_net([198, 51, 100, 0], 24), AddrClass::Documentation), // TEST-NET-2
+            (v4_net([203, 0, 113, 0], 24), AddrClass::Documentation), // TEST-NET-3
+            (v4_net([224, 0, 0, 0], 4), AddrClass::Multicast),
+            (v4_net([240, 0, 0, 0], 4), AddrClass::Reserved), // Including the limited broadcast address
+        ];
+
+        CLASSES
+            .iter()
+            .find(|(net, _)| net.contains(&addr))
+            .map_or(Self::Global, |&(_, class)| class)
+    }
+
+    fn of_ipv6(addr: Ipv6Addr) -> Self {
+        const CLASSES: &[(Ipv6Net, AddrClass)] = &[
+            (v6_net([0, 0, 0, 0, 0, 0, 0, 0], 128), AddrClass::Unspecified),
+            (v6_net([0, 0, 0, 0, 0, 0, 0, 1], 128), AddrClass::Loopback),
+            (v6_net([0xfe80, 0, 0, 0, 0, 0, 0, 0], 10), AddrClass::LinkLocal),
+            (v6_net([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7), AddrClass::UniqueLocal),
+            (v6_net([0x2001, 0xdb8, 0, 0, 0, 0, 0, 0], 32), AddrClass::Documentation),
+            (v6_net([0x3fff, 0, 0, 0, 0, 0, 0, 0], 20), AddrClass::Documentation),
+            (v6_net([0xff00, 0, 0, 0, 0, 0, 0, 0], 8), AddrClass::Multicast),
+        ];
+
+        CLASSES
+            .iter()
+            .find(|(net, _)| net.contains(&addr))
+            .map_or(Self::Global, |&(_, class)| class)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "Unspecified",
+            Self::Loopback => "Loopback",
+            Self::Private => "Private",
+            Self::Cgnat => "CGNAT",
+            Self::LinkLocal => "Link-local",
+            Self::Documentation => "Documentation",
+            Self::Multicast => "Multicast",
+            Self::UniqueLocal => "ULA",
+            Self::Reserved => "Reserved",
+            Self::Global => "Global",
+        }
+    }
+}
+
+impl fmt::Display for AddrClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ipnet_parses_prefixes() {
+        // GIVEN
+        let v4: IpNet = net("192.168.1.17/24");
+        let v6: IpNet = net("2001:db8::1/48");
+        let host: IpNet = net("10.1.2.3");
+        // THEN
+        assert_eq!(v4.prefix_len(), 24);
+        assert_eq!(v4.network(), "192.168.1.0".parse::<IpAddr>().unwrap());
+        assert_eq!(v4.last_addr(), "192.168.1.255".parse::<IpAddr>().unwrap());
+        assert_eq!(v4.to_string(), "192.168.1.17/24");
+        assert_eq!(v4.trunc().to_string(), "192.168.1.0/24");
+        assert_eq!(v6.network(), "2001:db8::".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            v6.last_addr(),
+            "2001:db8:0:ffff:ffff:ffff:ffff:ffff"
+                .parse::<IpAddr>()
+                .unwrap()
+        );
+        assert_eq!(host.prefix_len(), 32);
+    }
+
+    #[test]
+    fn test_ipnet_rejects_invalid_prefixes() {
+        for s in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "10.0.0.0/+8",
+            "10.0.0/8",
+            "example.com/8",
+            "",
+        ] {
+            // WHEN
+            let result: io::Result<IpNet> = s.parse();
+            // THEN
+            assert!(
+                result.is_err_and(|e| e.kind() == ErrorKind::InvalidInput),
+                "{:?}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn test_ipv4net_masks() {
+        // GIVEN
+        let net: Ipv4Net = "172.16.5.4/12".parse().unwrap();
+        // THEN
+        assert_eq!(net.netmask(), Ipv4Addr::new(255, 240, 0, 0));
+        assert_eq!(net.hostmask(), Ipv4Addr::new(0, 15, 255, 255));
+        assert_eq!(net.network(), Ipv4Addr::new(172, 16, 0, 0));
+    }
+
+    #[test]
+    fn test_ipnet_contains() {
+        // GIVEN
+        let v4: IpNet = net("10.0.0.0/8");
+        let v6: IpNet = net("fe80::/10");
+        // THEN
+        assert!(v4.contains(&"10.255.0.1".parse().unwrap()));
+        assert!(!v4.contains(&"11.0.0.0".parse().unwrap()));
+        assert!(!v4.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+        assert!(v6.contains(&"febf::1".parse().unwrap()));
+        assert!(v4.contains_net(&net("10.20.0.0/16")));
+        assert!(!net("10.20.0.0/16").contains_net(&v4));
+        assert!(net("0.0.0.0/0").contains(&"255.255.255.255".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_subnets_iteration() {
+        // GIVEN
+        let net: Ipv4Net = "192.168.0.0/22".parse().unwrap();
+        // WHEN
+        let subnets: Vec<String> = net.subnets(24).unwrap().map(|n| n.to_string()).collect();
+        // THEN
+        assert_eq!(
+            subnets,
+            [
+                "192.168.0.0/24",
+                "192.168.1.0/24",
+                "192.168.2.0/24",
+                "192.168.3.0/24"
+            ]
+        );
+        assert!(net.subnets(21).is_err());
+        assert!(net.subnets(33).is_err());
+    }
+
+    #[test]
+    fn test_subnets_of_whole_address_space() {
+        // GIVEN
+        let net: Ipv6Net = "::/0".parse().unwrap();
+        // WHEN
+        let subnets: Vec<Ipv6Net> = net.subnets(1).unwrap().collect();
+        let same: Vec<Ipv6Net> = net.subnets(0).unwrap().collect();
+        // THEN
+        assert_eq!(subnets.len(), 2);
+        assert_eq!(subnets[1].to_string(), "8000::/1");
+        assert_eq!(same, [net]);
+    }
+
+    #[test]
+    fn test_supernet() {
+        assert_eq!(net("10.1.0.0/16").supernet(), Some(net("10.0.0.0/15")));
+        assert_eq!(net("2001:db8::/32").supernet(), Some(net("2001:db8::/31")));
+        assert_eq!(net("0.0.0.0/0").supernet(), None);
+    }
+
+    #[test]
+    fn test_aggregate_merges_adjacent_and_overlapping_networks() {
+        // GIVEN
+        let networks: Vec<IpNet> = [
+            "10.0.1.0/24",
+            "10.0.0.0/24",
+            "10.0.2.0/23",
+            "10.0.3.128/25",
+            "192.168.0.1/32",
+            "2001:db8:1::/48",
+            "2001:db8::/48",
+        ]
+        .into_iter()
+        .map(net)
+        .collect();
+        // WHEN
+        let aggregated: Vec<String> = IpNet::aggregate(&networks)
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        // THEN
+        assert_eq!(
+            aggregated,
+            ["10.0.0.0/22", "192.168.0.1/32", "2001:db8::/47"]
+        );
+    }
+
+    #[test]
+    fn test_aggregate_splits_unaligned_ranges() {
+        // GIVEN
+        let networks: Vec<Ipv4Net> = [
+            "10.0.1.0/24".parse().unwrap(),
+            "10.0.2.0/24".parse().unwrap(),
+        ]
+        .to_vec();
+        // WHEN
+        let aggregated: Vec<String> = Ipv4Net::aggregate(&networks)
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        // THEN
+        assert_eq!(aggregated, ["10.0.1.0/24", "10.0.2.0/24"]);
+    }
+
+    #[test]
+    fn test_aggregate_whole_address_space() {
+        // GIVEN
+        let networks: Vec<IpNet> = vec![net("::/1"), net("8000::/1")];
+        // WHEN + THEN
+        assert_eq!(IpNet::aggregate(&networks), [net("::/0")]);
+    }
+
+    #[test]
+    fn test_addr_class_of() {
+        let cases: &[(&str, AddrClass)] = &[
+            ("0.0.0.0", AddrClass::Unspecified),
+            ("127.0.0.1", AddrClass::Loopback),
+            ("10.1.2.3", AddrClass::Private),
+            ("172.31.255.255", AddrClass::Private),
+            ("192.168.0.1", AddrClass::Private),
+            ("100.64.0.1", AddrClass::Cgnat),
+            ("169.254.1.1", AddrClass::LinkLocal),
+            ("198.51.100.7", AddrClass::Documentation),
+            ("239.255.255.250", AddrClass::Multicast),
+            ("255.255.255.255", AddrClass::Reserved),
+            ("8.8.8.8", AddrClass::Global),
+            ("::", AddrClass::Unspecified),
+            ("::1", AddrClass::Loopback),
+            ("fe80::1", AddrClass::LinkLocal),
+            ("fd12:3456::1", AddrClass::UniqueLocal),
+            ("2001:db8::1", AddrClass::Documentation),
+            ("ff02::1", AddrClass::Multicast),
+            ("::ffff:192.168.1.1", AddrClass::Private),
+            ("2001:4860:4860::8888", AddrClass::Global),
+        ];
+
+        for &(addr, expected_class) in cases {
+            assert_eq!(
+                AddrClass::of(addr.parse().unwrap()),
+                expected_class,
+                "{}",
+                addr
+            );
+        }
+    }
+}
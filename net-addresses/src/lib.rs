@@ -2,6 +2,8 @@
 #![cfg(target_family = "unix")]
 pub mod getnameinfo;
 pub mod getaddrinfo;
+pub mod ipnet;
+pub mod watch;
 
 pub use getnameinfo::getnameinfo;
 pub use getaddrinfo::getaddrinfo;
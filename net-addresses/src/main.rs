@@ -1,13 +1,17 @@
 #![allow(unused_imports)]
 mod args;
+mod repl;
 
-use std::process;
+use std::{io, process, thread};
+use std::time::{Duration, Instant, SystemTime};
 use std::fmt::{Display, Debug};
 use clap::Parser;
 use args::CliArgs;
+use repl::Repl;
 
 use libc::{AI_PASSIVE, AI_CANONNAME};
 use net_addresses::getaddrinfo::{AddrInfo, AddrInfoHints};
+use net_addresses::watch::{self, Schedule, Watcher, WatchEvent, EventKind};
 
 // Returns a closure that prints items of type `T` in different formats depending on verbosity.
 fn get_printer<T: Display + Debug + 'static>(verbosity: u8) -> impl Fn(&T) {
@@ -19,6 +23,68 @@ fn get_printer<T: Display + Debug + 'static>(verbosity: u8) -> impl Fn(&T) {
     }
 }
 
+// Resolves the names and prints each resulting record.
+fn print_addrinfo(
+    host: Option<&str>,
+    service: Option<&str>,
+    hints: AddrInfoHints,
+    verbosity: u8,
+) -> io::Result<()> {
+    let printer = get_printer(verbosity);
+
+    net_addresses::getaddrinfo(host, service, Some(hints))?.for_each(|ai_result| match ai_result {
+        Ok(ai) => printer(&ai),
+        Err(e) => eprintln!("Error resolving address: {:?}", e),
+    });
+
+    Ok(())
+}
+
+// Resolves the names at every interval and prints the records that were added or removed.
+// The interval is measured from the start of one resolution to the start of the next.
+fn watch(args: &CliArgs, hints: AddrInfoHints, interval: Duration) -> ! {
+    let printer = get_printer(args.verbose);
+    let mut watcher = Watcher::new(args.host.clone(), args.service.clone(), hints);
+    let mut schedule = Schedule::new(Instant::now(), interval);
+
+    loop {
+        let timestamp: SystemTime = SystemTime::now();
+
+        match watcher.poll() {
+            Ok(diff) => {
+                let events = diff
+                    .removed
+                    .iter()
+                    .map(|ai| (EventKind::Removed, ai))
+                    .chain(diff.added.iter().map(|ai| (EventKind::Added, ai)));
+
+                for (kind, addrinfo) in events {
+                    if args.jsonl {
+                        let event = WatchEvent {
+                            timestamp,
+                            kind,
+                            addrinfo,
+                        };
+                        println!("{}", event.to_json());
+                    } else {
+                        print!("[{}] {} ", watch::format_timestamp(timestamp), kind.sign());
+                        printer(addrinfo);
+                    }
+                }
+            }
+            Err(e) if args.jsonl => println!("{}", watch::error_to_json(timestamp, &e)),
+            Err(e) => eprintln!(
+                "[{}] Error resolving address: {:?}",
+                watch::format_timestamp(timestamp),
+                e
+            ),
+        }
+
+        let next: Instant = schedule.next_after(Instant::now());
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     if cfg!(not(target_family = "unix")) {
         eprintln!("This program is intended for Unix-like systems only.");
@@ -27,7 +93,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = dbg!(CliArgs::parse());
 
-    let printer = get_printer(args.verbose);
     let hints = AddrInfoHints {
         flags: if args.canonname { AI_CANONNAME } else { 0 },
         family: args.family,
@@ -35,11 +100,20 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         protocol: args.protocol,
     };
 
-    net_addresses::getaddrinfo(args.host.as_deref(), args.service.as_deref(), Some(hints))?
-        .for_each(|ai_result| match ai_result {
-            Ok(ai) => printer(&ai),
-            Err(e) => eprintln!("Error resolving address: {:?}", e),
-        });
+    if args.interactive {
+        return Ok(Repl::new(&args).run(io::stdin().lock())?);
+    }
+
+    if let Some(interval) = args.watch {
+        watch(&args, hints, interval);
+    }
+
+    print_addrinfo(
+        args.host.as_deref(),
+        args.service.as_deref(),
+        hints,
+        args.verbose,
+    )?;
 
     Ok(())
 }
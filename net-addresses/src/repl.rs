@@ -0,0 +1,373 @@
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, SocketAddr};
+use clap::ValueEnum;
+
+use libc::AI_CANONNAME;
+use net_addresses::getaddrinfo::{AddrFamily, SockType, Protocol, AddrInfoHints};
+use crate::args::CliArgs;
+
+const PROMPT: &str = "net-addresses> ";
+
+const HELP: &str = "\
+Commands:
+  lookup <HOST|-> [SERVICE]           Resolve a host and/or service (use '-' to omit the host)
+  reverse <IP>[:PORT] | <IP> [PORT]   Resolve an address to a host and service name
+  set family <VALUE>                  unspecified, inet, inet6
+  set socktype <VALUE>                unspecified, stream, datagram, raw, seq-packet
+  set protocol <VALUE>                unspecified, tcp, udp, sctp
+  set canonname <on|off>              Resolve canonical names
+  set verbose <0-2>                   Verbose output level
+  show                                Show the current settings
+  history                             Show the command history
+  !<N> | !!                           Repeat history entry N or the last command
+  help                                Show this message
+  quit | exit                         Leave the shell";
+
+/// Settings that persist across queries
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    family: AddrFamily,
+    socktype: SockType,
+    protocol: Protocol,
+    canonname: bool,
+    verbose: u8,
+}
+
+impl Settings {
+    fn hints(&self) -> AddrInfoHints {
+        AddrInfoHints {
+            flags: if self.canonname { AI_CANONNAME } else { 0 },
+            family: self.family,
+            socktype: self.socktype,
+            protocol: self.protocol,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Lookup {
+        host: Option<String>,
+        service: Option<String>,
+    },
+    Reverse(SocketAddr),
+    Set(Setting),
+    Show,
+    History,
+    Help,
+    Quit,
+}
+
+#[derive(Debug, PartialEq)]
+enum Setting {
+    Family(AddrFamily),
+    SockType(SockType),
+    Protocol(Protocol),
+    Canonname(bool),
+    Verbose(u8),
+}
+
+fn parse_value<T: ValueEnum>(value: Option<&str>) -> Result<T, String> {
+    let value: &str = value.ok_or("missing value")?;
+    T::from_str(value, true).map_err(|_| format!("invalid value: {:?}", value))
+}
+
+fn parse_setting(name: Option<&str>, value: Option<&str>) -> Result<Setting, String> {
+    match name {
+        Some("family") => parse_value(value).map(Setting::Family),
+        Some("socktype") => parse_value(value).map(Setting::SockType),
+        Some("protocol") => parse_value(value).map(Setting::Protocol),
+        Some("canonname") => match value {
+            Some("on") => Ok(Setting::Canonname(true)),
+            Some("off") => Ok(Setting::Canonname(false)),
+            _ => Err("expected 'on' or 'off'".to_string()),
+        },
+        Some("verbose") => match value.and_then(|v| v.parse::<u8>().ok()) {
+            Some(level @ 0..=2) => Ok(Setting::Verbose(level)),
+            _ => Err("expected a verbose level between 0 and 2".to_string()),
+        },
+        Some(name) => Err(format!("unknown setting: {:?}", name)),
+        None => Err("missing setting name".to_string()),
+    }
+}
+
+fn parse_reverse(addr: Option<&str>, port: Option<&str>) -> Result<SocketAddr, String> {
+    let addr: &str = addr.ok_or("missing address")?;
+    if let Ok(socket_addr) = addr.parse::<SocketAddr>() {
+        return Ok(socket_addr);
+    }
+
+    let ip: IpAddr = addr
+        .parse()
+        .map_err(|_| format!("invalid address: {:?}", addr))?;
+    let port: u16 = match port {
+        Some(port) => port
+            .parse()
+            .map_err(|_| format!("invalid port: {:?}", port))?,
+        None => 0,
+    };
+
+    Ok(SocketAddr::new(ip, port))
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let command: &str = words.next().unwrap_or_default();
+    let (first, second): (Option<&str>, Option<&str>) = (words.next(), words.next());
+    if words.next().is_some() {
+        Err("too many arguments".to_string())?;
+    }
+
+    match command {
+        "lookup" => {
+            let host: Option<String> = first.filter(|&h| h != "-").map(String::from);
+            let service: Option<String> = second.map(String::from);
+            if host.is_none() && service.is_none() {
+                Err("either host or service must be specified")?;
+            }
+            Ok(Command::Lookup { host, service })
+        }
+        "reverse" => parse_reverse(first, second).map(Command::Reverse),
+        "set" => parse_setting(first, second).map(Command::Set),
+        "show" => Ok(Command::Show),
+        "history" => Ok(Command::History),
+        "help" => Ok(Command::Help),
+        "quit" | "exit" => Ok(Command::Quit),
+        _ => Err(format!(
+            "unknown command: {:?} (type 'help' for a list of commands)",
+            command
+        )),
+    }
+}
+
+/// Interactive shell that keeps the hint settings and the command history between queries.
+pub struct Repl {
+    settings: Settings,
+    history: Vec<String>,
+}
+
+impl Repl {
+    pub fn new(args: &CliArgs) -> Self {
+        Self {
+            settings: Settings {
+                family: args.family,
+                socktype: args.socktype,
+                protocol: args.protocol,
+                canonname: args.canonname,
+                verbose: args.verbose,
+            },
+            history: Vec::new(),
+        }
+    }
+
+    /// Reads commands from the input until `quit` or the end of input.
+    pub fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            print!("{}", PROMPT);
+            io::stdout().flush()?;
+
+            let Some(line) = lines.next().transpose()? else {
+                println!();
+                break Ok(());
+            };
+            let line: String = match self.expand_history(line.trim()) {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+            self.history.push(line.clone());
+
+            match parse_command(&line) {
+                Ok(Command::Quit) => break Ok(()),
+                Ok(command) => self.execute(command),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+    }
+
+    /// Replaces `!!` and `!N` with the corresponding history entry.
+    fn expand_history(&self, line: &str) -> Result<String, String> {
+        let Some(reference) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+        let entry: Option<&String> = match reference {
+            "!" => self.history.last(),
+            n => n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| n.checked_sub(1))
+                .and_then(|i| self.history.get(i)),
+        };
+
+        let entry: &String = entry.ok_or_else(|| format!("no such history entry: {:?}", line))?;
+        println!("{}", entry);
+
+        Ok(entry.clone())
+    }
+
+    fn execute(&mut self, command: Command) {
+        match command {
+            Command::Lookup { host, service } => {
+                let hints: AddrInfoHints = self.settings.hints();
+                if let Err(e) = crate::print_addrinfo(
+                    host.as_deref(),
+                    service.as_deref(),
+                    hints,
+                    self.settings.verbose,
+                ) {
+                    eprintln!("Error: {}", e);
+                }
+            }
+            Command::Reverse(socket_addr) => match net_addresses::getnameinfo(socket_addr, 0) {
+                Ok((host, service)) => {
+                    println!("{} (Host: {}, Service: {})", socket_addr, host, service)
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            },
+            Command::Set(setting) => match setting {
+                Setting::Family(family) => self.settings.family = family,
+                Setting::SockType(socktype) => self.settings.socktype = socktype,
+                Setting::Protocol(protocol) => self.settings.protocol = protocol,
+                Setting::Canonname(canonname) => self.settings.canonname = canonname,
+                Setting::Verbose(verbose) => self.settings.verbose = verbose,
+            },
+            Command::Show => println!(
+                "family: {}, socktype: {}, protocol: {}, canonname: {}, verbose: {}",
+                self.settings.family,
+                self.settings.socktype,
+                self.settings.protocol,
+                if self.settings.canonname { "on" } else { "off" },
+                self.settings.verbose,
+            ),
+            Command::History => self
+                .history
+                .iter()
+                .enumerate()
+                .for_each(|(i, line)| println!("{:>4}  {}", i + 1, line)),
+            Command::Help => println!("{}", HELP),
+            Command::Quit => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn get_repl() -> Repl {
+        Repl::new(&CliArgs::parse_from(["--", "--interactive"]))
+    }
+
+    #[test]
+    fn test_parse_lookup_command() {
+        assert_eq!(
+            parse_command("lookup example.com https"),
+            Ok(Command::Lookup {
+                host: Some("example.com".into()),
+                service: Some("https".into())
+            })
+        );
+        assert_eq!(
+            parse_command("lookup - ssh"),
+            Ok(Command::Lookup {
+                host: None,
+                service: Some("ssh".into())
+            })
+        );
+        assert!(parse_command("lookup").is_err());
+        assert!(parse_command("lookup a b c").is_err());
+    }
+
+    #[test]
+    fn test_parse_reverse_command() {
+        let expected_v4: SocketAddr = "8.8.8.8:53".parse().unwrap();
+        let expected_v6: SocketAddr = "[::1]:0".parse().unwrap();
+
+        assert_eq!(
+            parse_command("reverse 8.8.8.8:53"),
+            Ok(Command::Reverse(expected_v4))
+        );
+        assert_eq!(
+            parse_command("reverse 8.8.8.8 53"),
+            Ok(Command::Reverse(expected_v4))
+        );
+        assert_eq!(
+            parse_command("reverse ::1"),
+            Ok(Command::Reverse(expected_v6))
+        );
+        assert!(parse_command("reverse example.com").is_err());
+        assert!(parse_command("reverse 8.8.8.8 http").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_command() {
+        assert_eq!(
+            parse_command("set family inet6"),
+            Ok(Command::Set(Setting::Family(AddrFamily::Inet6)))
+        );
+        assert_eq!(
+            parse_command("set socktype seq-packet"),
+            Ok(Command::Set(Setting::SockType(SockType::SeqPacket)))
+        );
+        assert_eq!(
+            parse_command("set protocol UDP"),
+            Ok(Command::Set(Setting::Protocol(Protocol::Udp)))
+        );
+        assert_eq!(
+            parse_command("set canonname on"),
+            Ok(Command::Set(Setting::Canonname(true)))
+        );
+        assert_eq!(
+            parse_command("set verbose 2"),
+            Ok(Command::Set(Setting::Verbose(2)))
+        );
+        assert!(parse_command("set verbose 3").is_err());
+        assert!(parse_command("set family unix").is_err());
+        assert!(parse_command("set color on").is_err());
+    }
+
+    #[test]
+    fn test_settings_persist_across_commands() {
+        // GIVEN
+        let mut repl: Repl = get_repl();
+        let input: &[u8] = b"set family inet\nset protocol tcp\nshow\n";
+        // WHEN
+        repl.run(input).unwrap();
+        // THEN
+        let hints: AddrInfoHints = repl.settings.hints();
+        assert!(hints.family == AddrFamily::Inet && hints.protocol == Protocol::Tcp);
+        assert_eq!(
+            repl.history,
+            ["set family inet", "set protocol tcp", "show"]
+        );
+    }
+
+    #[test]
+    fn test_history_expansion() {
+        // GIVEN
+        let mut repl: Repl = get_repl();
+        let input: &[u8] = b"set verbose 1\nset verbose 2\n!1\n!!\n!9\nquit\nset verbose 0\n";
+        // WHEN
+        repl.run(input).unwrap();
+        // THEN
+        assert_eq!(repl.settings.verbose, 1);
+        assert_eq!(
+            repl.history,
+            [
+                "set verbose 1",
+                "set verbose 2",
+                "set verbose 1",
+                "set verbose 1",
+                "quit"
+            ]
+        );
+    }
+}
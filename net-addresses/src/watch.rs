@@ -0,0 +1,372 @@
+use std::io;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+
+use crate::getaddrinfo::{AddrInfo, AddrInfoHints, AddrFamily, SockType, Protocol};
+
+/// Fields that identify a resolved address record across resolutions.
+/// Flags and the canonical name are not part of the identity, since resolvers
+/// attach the canonical name to the first record only and the order may change.
+type AddrInfoKey = (AddrFamily, SockType, Protocol, SocketAddr);
+
+fn key(ai: &AddrInfo) -> AddrInfoKey {
+    (ai.family, ai.socktype, ai.protocol, ai.socket_addr)
+}
+
+/// Difference between two sets of address records
+#[derive(Debug, Clone, Default)]
+pub struct AddrInfoDiff {
+    pub added: Vec<AddrInfo>,
+    pub removed: Vec<AddrInfo>,
+}
+
+impl AddrInfoDiff {
+    /// Computes which records of `current` are missing from `previous` (added)
+    /// and which records of `previous` are missing from `current` (removed).
+    /// The order of the records in the input slices is preserved.
+    pub fn between(previous: &[AddrInfo], current: &[AddrInfo]) -> Self {
+        let previous_keys: HashSet<AddrInfoKey> = previous.iter().map(key).collect();
+        let current_keys: HashSet<AddrInfoKey> = current.iter().map(key).collect();
+
+        let mut seen: HashSet<AddrInfoKey> = HashSet::new();
+        let added: Vec<AddrInfo> = current
+            .iter()
+            .filter(|ai| !previous_keys.contains(&key(ai)) && seen.insert(key(ai)))
+            .cloned()
+            .collect();
+
+        seen.clear();
+        let removed: Vec<AddrInfo> = previous
+            .iter()
+            .filter(|ai| !current_keys.contains(&key(ai)) && seen.insert(key(ai)))
+            .cloned()
+            .collect();
+
+        Self { added, removed }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Repeatedly resolves the same host and service, reporting changes between resolutions.
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    host: Option<String>,
+    service: Option<String>,
+    hints: AddrInfoHints,
+    snapshot: Option<Vec<AddrInfo>>,
+}
+
+impl Watcher {
+    pub fn new(host: Option<String>, service: Option<String>, hints: AddrInfoHints) -> Self {
+        Self {
+            host,
+            service,
+            hints,
+            snapshot: None,
+        }
+    }
+
+    /// Resolves the names again and returns the difference against the previous resolution.
+    ///
+    /// On the first call every resolved record is reported as added. Records that fail
+    /// to convert are skipped. If the resolution itself fails, the previous snapshot is
+    /// kept, so a transient resolver error does not show up as every address being removed.
+    pub fn poll(&mut self) -> io::Result<AddrInfoDiff> {
+        let current: Vec<AddrInfo> = crate::getaddrinfo(
+            self.host.as_deref(),
+            self.service.as_deref(),
+            Some(self.hints),
+        )?
+        .filter_map(Result::ok)
+        .collect();
+
+        let diff: AddrInfoDiff =
+            AddrInfoDiff::between(self.snapshot.as_deref().unwrap_or_default(), &current);
+        self.snapshot = Some(current);
+
+        Ok(diff)
+    }
+
+    /// Returns the records from the last successful resolution.
+    pub fn snapshot(&self) -> &[AddrInfo] {
+        self.snapshot.as_deref().unwrap_or_default()
+    }
+}
+
+/// Fixed-rate schedule of the resolutions: each one is due a whole number of intervals
+/// after the first, however long the previous ones took.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    next: Instant,
+    interval: Duration,
+}
+
+impl Schedule {
+    /// Starts a schedule whose first resolution is due at `start`.
+    pub fn new(start: Instant, interval: Duration) -> Self {
+        Self { next: start, interval }
+    }
+
+    /// Returns when the resolution following the one that was due is due.
+    /// Resolutions that would be due before `now` are skipped, so a slow resolution
+    /// is not followed by a burst of them.
+    pub fn next_after(&mut self, now: Instant) -> Instant {
+        self.next += self.interval;
+        while self.next < now {
+            self.next += self.interval;
+        }
+
+        self.next
+    }
+}
+
+/// Kind of a watch event
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    Added,
+    Removed,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+        }
+    }
+
+    /// Sign used to mark the event in the human-readable output
+    pub fn sign(&self) -> char {
+        match self {
+            Self::Added => '+',
+            Self::Removed => '-',
+        }
+    }
+}
+
+/// A single change observed by the [`Watcher`]
+#[derive(Debug, Clone)]
+pub struct WatchEvent<'a> {
+    pub timestamp: SystemTime,
+    pub kind: EventKind,
+    pub addrinfo: &'a AddrInfo,
+}
+
+impl WatchEvent<'_> {
+    /// Serializes the event as a single-line JSON object:
+    ///
+    /// {"timestamp":"2025-01-01T00:00:00Z","event":"added","address":"127.0.0.1:80",...}
+    pub fn to_json(&self) -> String {
+        let ai: &AddrInfo = self.addrinfo;
+        let canonname: String = ai
+            .canonname
+            .as_deref()
+            .map_or_else(|| "null".to_string(), json_string);
+
+        format!(
+            "{{\"timestamp\":{},\"event\":{},\"address\":{},\"ip\":{},\"port\":{},\
+            \"family\":{},\"socktype\":{},\"protocol\":{},\"canonname\":{}}}",
+            json_string(&format_timestamp(self.timestamp)),
+            json_string(self.kind.as_str()),
+            json_string(&ai.socket_addr.to_string()),
+            json_string(&ai.socket_addr.ip().to_string()),
+            ai.socket_addr.port(),
+            json_string(&value_name(&ai.family)),
+            json_string(&value_name(&ai.socktype)),
+            json_string(&value_name(&ai.protocol)),
+            canonname,
+        )
+    }
+}
+
+/// Serializes a resolution error as a single-line JSON object.
+pub fn error_to_json(timestamp: SystemTime, error: &io::Error) -> String {
+    format!(
+        "{{\"timestamp\":{},\"event\":\"error\",\"message\":{}}}",
+        json_string(&format_timestamp(timestamp)),
+        json_string(&error.to_string()),
+    )
+}
+
+/// Returns the name used for the value on the command line (e.g., "inet6", "stream", "tcp").
+fn value_name(value: &impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(|| "unknown".to_string(), |pv| pv.get_name().to_string())
+}
+
+/// Encodes a string as a JSON string literal (including the surrounding quotes).
+fn json_string(s: &str) -> String {
+    let mut out: String = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+/// Formats a point in time as an RFC 3339 UTC timestamp (e.g., "2025-01-01T00:00:00Z").
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let secs: u64 = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, rem): (i64, u64) = ((secs / 86_400) as i64, secs % 86_400);
+    let (hour, minute, second): (u64, u64, u64) = (rem / 3600, rem % 3600 / 60, rem % 60);
+
+    // Converts days since the epoch to a civil date.
+    // See: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z: i64 = days + 719_468;
+    let era: i64 = z.div_euclid(146_097);
+    let doe: i64 = z - era * 146_097;
+    let yoe: i64 = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy: i64 = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp: i64 = (5 * doy + 2) / 153;
+    let day: i64 = doy - (153 * mp + 2) / 5 + 1;
+    let month: i64 = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year: i64 = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrinfo(socket_addr: &str, socktype: SockType) -> AddrInfo {
+        AddrInfo {
+            flags: 0,
+            family: AddrFamily::Inet,
+            socktype,
+            protocol: Protocol::Unspecified,
+            socket_addr: socket_addr.parse().unwrap(),
+            canonname: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_reports_added_and_removed_records() {
+        // GIVEN
+        let previous: Vec<AddrInfo> = vec![
+            addrinfo("10.0.0.1:80", SockType::Stream),
+            addrinfo("10.0.0.2:80", SockType::Stream),
+        ];
+        let current: Vec<AddrInfo> = vec![
+            addrinfo("10.0.0.2:80", SockType::Stream),
+            addrinfo("10.0.0.3:80", SockType::Stream),
+        ];
+        // WHEN
+        let diff: AddrInfoDiff = AddrInfoDiff::between(&previous, &current);
+        // THEN
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].socket_addr, "10.0.0.3:80".parse().unwrap());
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].socket_addr, "10.0.0.1:80".parse().unwrap());
+    }
+
+    #[test]
+    fn test_diff_ignores_order_and_canonname() {
+        // GIVEN
+        let mut first: AddrInfo = addrinfo("10.0.0.1:80", SockType::Stream);
+        first.canonname = Some("example.com".into());
+        let previous: Vec<AddrInfo> = vec![first, addrinfo("10.0.0.1:80", SockType::Datagram)];
+        let current: Vec<AddrInfo> = vec![
+            addrinfo("10.0.0.1:80", SockType::Datagram),
+            addrinfo("10.0.0.1:80", SockType::Stream),
+        ];
+        // WHEN
+        let diff: AddrInfoDiff = AddrInfoDiff::between(&previous, &current);
+        // THEN
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_diff_distinguishes_socket_types() {
+        // GIVEN
+        let previous: Vec<AddrInfo> = vec![addrinfo("10.0.0.1:80", SockType::Stream)];
+        let current: Vec<AddrInfo> = vec![addrinfo("10.0.0.1:80", SockType::Datagram)];
+        // WHEN
+        let diff: AddrInfoDiff = AddrInfoDiff::between(&previous, &current);
+        // THEN
+        assert_eq!(diff.added[0].socktype, SockType::Datagram);
+        assert_eq!(diff.removed[0].socktype, SockType::Stream);
+    }
+
+    #[test]
+    fn test_watcher_first_poll_reports_everything_as_added() {
+        // GIVEN
+        let hints = AddrInfoHints {
+            flags: 0,
+            family: AddrFamily::Inet,
+            socktype: SockType::Stream,
+            protocol: Protocol::Unspecified,
+        };
+        let mut watcher = Watcher::new(Some("127.0.0.1".into()), Some("80".into()), hints);
+        // WHEN
+        let first: AddrInfoDiff = watcher.poll().unwrap();
+        let second: AddrInfoDiff = watcher.poll().unwrap();
+        // THEN
+        assert_eq!(first.added.len(), 1);
+        assert!(first.removed.is_empty());
+        assert!(second.is_empty());
+        assert_eq!(watcher.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn test_schedule_keeps_fixed_rate_regardless_of_resolution_time() {
+        // GIVEN
+        let start: Instant = Instant::now();
+        let interval: Duration = Duration::from_secs(10);
+        let mut schedule = Schedule::new(start, interval);
+        // WHEN
+        let fast: Instant = schedule.next_after(start + Duration::from_secs(1));
+        let slow: Instant = schedule.next_after(fast + Duration::from_secs(9));
+        let overrun: Instant = schedule.next_after(slow + Duration::from_secs(25));
+        // THEN
+        assert_eq!(fast, start + interval);
+        assert_eq!(slow, start + 2 * interval);
+        // The resolutions due while the slow one was running are skipped
+        assert_eq!(overrun, start + 5 * interval);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format_timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            "2000-02-29T12:34:56Z"
+        );
+    }
+
+    #[test]
+    fn test_watch_event_to_json() {
+        // GIVEN
+        let mut ai: AddrInfo = addrinfo("127.0.0.1:80", SockType::Stream);
+        ai.canonname = Some("local\"host".into());
+        let event = WatchEvent {
+            timestamp: UNIX_EPOCH,
+            kind: EventKind::Removed,
+            addrinfo: &ai,
+        };
+        let expected_json: &str = "{\"timestamp\":\"1970-01-01T00:00:00Z\",\"event\":\"removed\",\
+            \"address\":\"127.0.0.1:80\",\"ip\":\"127.0.0.1\",\"port\":80,\"family\":\"inet\",\
+            \"socktype\":\"stream\",\"protocol\":\"unspecified\",\"canonname\":\"local\\\"host\"}";
+        // WHEN + THEN
+        assert_eq!(event.to_json(), expected_json);
+    }
+}
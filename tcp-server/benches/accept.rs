@@ -0,0 +1,119 @@
+//! Compares the accept strategies of the prefork server.
+//!
+//! For each strategy a prefork server is started in a forked process and a number of client
+//! threads open short echo connections to it for a fixed amount of time. Run with
+//! `cargo bench --bench accept`.
+use std::{io, thread};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use clap::ValueEnum;
+use libc::pid_t;
+
+use tcp_server::accept::{AcceptStrategy, ReusePortAddr};
+use tcp_server::core::PreforkTcpServer;
+use tcp_server::service::DelayedEchoService;
+
+const NUM_CHILDREN: usize = 8;
+const NUM_CLIENTS: usize = 16;
+const WARMUP: Duration = Duration::from_millis(500);
+const DURATION: Duration = Duration::from_secs(3);
+
+fn start_server(strategy: AcceptStrategy) -> io::Result<(SocketAddr, pid_t)> {
+    let socket_addr: SocketAddr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server = match strategy {
+        AcceptStrategy::ReusePort => {
+            PreforkTcpServer::new(ReusePortAddr(socket_addr), DelayedEchoService::new(0), NUM_CHILDREN)?
+        }
+        _ => PreforkTcpServer::new(socket_addr, DelayedEchoService::new(0), NUM_CHILDREN)?,
+    }
+    .with_accept_strategy(strategy)?;
+    let socket_addr: SocketAddr = server.local_addr()?;
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            let code: i32 = server.serve().is_err() as i32;
+            unsafe { libc::_exit(code) }
+        }
+        pid => {
+            // With `SO_REUSEPORT` connections are refused until the children have bound their listeners
+            while echo(socket_addr).is_err() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok((socket_addr, pid))
+        }
+    }
+}
+
+fn stop_server(pid: pid_t) {
+    unsafe {
+        libc::kill(pid, libc::SIGTERM);
+        libc::waitpid(pid, std::ptr::null_mut(), 0);
+    }
+}
+
+fn echo(socket_addr: SocketAddr) -> io::Result<()> {
+    let mut stream: TcpStream = TcpStream::connect(socket_addr)?;
+    stream.write_all(b"ping\n\n")?;
+
+    let mut buf: Vec<u8> = Vec::new();
+    stream.read_to_end(&mut buf)?;
+
+    match buf.as_slice() {
+        b"ping\n" => Ok(()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unexpected echo response",
+        )),
+    }
+}
+
+// Opens connections from all clients until the time runs out and returns the number completed
+fn run_clients(socket_addr: SocketAddr, duration: Duration) -> usize {
+    let deadline: Instant = Instant::now() + duration;
+
+    let handles: Vec<thread::JoinHandle<usize>> = (0..NUM_CLIENTS)
+        .map(|_| {
+            thread::spawn(move || {
+                let mut completed: usize = 0;
+                while Instant::now() < deadline {
+                    match echo(socket_addr) {
+                        Ok(()) => completed += 1,
+                        Err(e) => eprintln!("Connection failed: {}", e),
+                    }
+                }
+                completed
+            })
+        })
+        .collect();
+
+    handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "{} children, {} clients, {:?} per strategy",
+        NUM_CHILDREN, NUM_CLIENTS, DURATION
+    );
+
+    for strategy in AcceptStrategy::value_variants() {
+        let (socket_addr, pid) = start_server(*strategy)?;
+
+        run_clients(socket_addr, WARMUP);
+        let start: Instant = Instant::now();
+        let completed: usize = run_clients(socket_addr, DURATION);
+        let elapsed: Duration = start.elapsed();
+
+        stop_server(pid);
+
+        println!(
+            "{:<10} {:>8} connections {:>10.0} conn/s",
+            format!("{:?}", strategy),
+            completed,
+            completed as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    Ok(())
+}
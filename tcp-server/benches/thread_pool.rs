@@ -0,0 +1,180 @@
+//! Compares the work-stealing thread pool with a pool whose workers share a single channel,
+//! as the thread pool was implemented before.
+//!
+//! Both pools run many short jobs, first submitted from outside the pool and then submitted
+//! by the jobs themselves. Run with `cargo bench --bench thread_pool`.
+use std::thread;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tcp_server::thread_pool::ThreadPool;
+
+const NUM_WORKERS: usize = 8;
+const NUM_JOBS: usize = 1_000_000;
+/// Number of jobs submitted from outside the pool in the nested workload, each of which
+/// submits `NUM_JOBS / NUM_ROOTS` jobs itself
+const NUM_ROOTS: usize = 64;
+const ROUNDS: usize = 5;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+trait Pool: Send + Sync + 'static {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
+
+impl Pool for ThreadPool {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ThreadPool::execute(self, f)
+    }
+}
+
+/// Workers take jobs from one channel behind a mutex
+struct ChannelThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<Mutex<mpsc::Sender<Job>>>,
+}
+
+impl ChannelThreadPool {
+    fn new(size: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx: Arc<Mutex<mpsc::Receiver<Job>>> = Arc::new(Mutex::new(rx));
+        let workers: Vec<thread::JoinHandle<()>> = (0..size)
+            .map(|_| {
+                let rx: Arc<Mutex<mpsc::Receiver<Job>>> = Arc::clone(&rx);
+                thread::spawn(move || loop {
+                    let message = rx.lock().unwrap().recv();
+                    match message {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(Mutex::new(tx)),
+        }
+    }
+}
+
+impl Pool for ChannelThreadPool {
+    fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .send(Box::new(f))
+            .unwrap();
+    }
+}
+
+impl Drop for ChannelThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        self.workers.drain(..).for_each(|worker| worker.join().unwrap());
+    }
+}
+
+/// Counts finished jobs and wakes up the waiting thread after the last one
+struct Counter {
+    done: AtomicUsize,
+    tx: Mutex<mpsc::Sender<()>>,
+}
+
+impl Counter {
+    fn new(tx: mpsc::Sender<()>) -> Arc<Self> {
+        Arc::new(Self {
+            done: AtomicUsize::new(0),
+            tx: Mutex::new(tx),
+        })
+    }
+
+    fn finish(&self) {
+        if self.done.fetch_add(1, Ordering::Relaxed) + 1 == NUM_JOBS {
+            self.tx.lock().unwrap().send(()).unwrap();
+        }
+    }
+}
+
+// Submits all jobs from the calling thread
+fn run_external<P: Pool>(pool: &Arc<P>) -> Duration {
+    let (tx, rx) = mpsc::channel();
+    let counter: Arc<Counter> = Counter::new(tx);
+
+    let start: Instant = Instant::now();
+    for _ in 0..NUM_JOBS {
+        let counter: Arc<Counter> = Arc::clone(&counter);
+        pool.execute(move || counter.finish());
+    }
+    rx.recv().unwrap();
+
+    start.elapsed()
+}
+
+// Submits a few jobs that submit the rest from the workers
+fn run_nested<P: Pool>(pool: &Arc<P>) -> Duration {
+    let (tx, rx) = mpsc::channel();
+    let counter: Arc<Counter> = Counter::new(tx);
+
+    let start: Instant = Instant::now();
+    for _ in 0..NUM_ROOTS {
+        let inner: Arc<P> = Arc::clone(pool);
+        let counter: Arc<Counter> = Arc::clone(&counter);
+        pool.execute(move || {
+            for _ in 0..NUM_JOBS / NUM_ROOTS {
+                let counter: Arc<Counter> = Arc::clone(&counter);
+                inner.execute(move || counter.finish());
+            }
+        });
+    }
+    rx.recv().unwrap();
+
+    start.elapsed()
+}
+
+// Returns the best throughput of several rounds in jobs per second
+fn measure<P: Pool>(pool: &Arc<P>, workload: fn(&Arc<P>) -> Duration) -> f64 {
+    workload(pool);
+
+    (0..ROUNDS)
+        .map(|_| NUM_JOBS as f64 / workload(pool).as_secs_f64())
+        .fold(0.0, f64::max)
+}
+
+fn bench<P: Pool>(name: &str, pool: P) {
+    let pool: Arc<P> = Arc::new(pool);
+
+    println!(
+        "{:<14} {:>12.0} jobs/s external {:>12.0} jobs/s nested",
+        name,
+        measure(&pool, run_external),
+        measure(&pool, run_nested)
+    );
+
+    // The pool must not be dropped by one of its own workers
+    while Arc::strong_count(&pool) > 1 {
+        thread::yield_now();
+    }
+}
+
+fn main() {
+    assert_eq!(NUM_JOBS % NUM_ROOTS, 0);
+    println!(
+        "{} workers, {} jobs per round, best of {} rounds",
+        NUM_WORKERS, NUM_JOBS, ROUNDS
+    );
+
+    bench("channel", ChannelThreadPool::new(NUM_WORKERS));
+    bench("work-stealing", ThreadPool::new(NUM_WORKERS));
+}
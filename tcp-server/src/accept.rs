@@ -0,0 +1,293 @@
+use std::{io, mem, ptr};
+use std::cell::UnsafeCell;
+use std::fs::{File, OpenOptions};
+use std::net::{SocketAddr, TcpListener};
+use std::os::fd::{RawFd, AsRawFd as _, FromRawFd as _};
+use std::os::unix::fs::OpenOptionsExt as _;
+use clap::ValueEnum;
+use libc::{c_int, c_void, socklen_t, LOCK_EX, LOCK_UN, O_TMPFILE, EOWNERDEAD, EINTR};
+use libc::{MAP_ANONYMOUS, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+use crate::listener::{Listener, ToListener};
+
+/// Backlog of the `SO_REUSEPORT` listeners
+const LISTEN_BACKLOG: c_int = 128;
+
+/// How prefork children take turns accepting connections
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum AcceptStrategy {
+    /// Every child waits on the shared listener and races for each connection.
+    /// Simple, but every connection wakes up all idle children (thundering herd).
+    #[default]
+    Shared,
+    /// Only the child holding a process-shared (robust) mutex waits on the listener.
+    Mutex,
+    /// Only the child holding an `flock` on a lock file waits on the listener.
+    Flock,
+    /// Every child has its own `SO_REUSEPORT` listener and the kernel distributes connections
+    /// between them and the shared listener, which must be bound to a [`ReusePortAddr`]
+    /// (or inherited with `SO_REUSEPORT` set). Connections queued on a child's listener are
+    /// reset if that child exits.
+    #[value(name = "reuseport")]
+    ReusePort,
+}
+
+/// Lock that serializes waiting for and accepting connections between prefork children.
+pub(crate) enum AcceptLock {
+    Mutex(ProcessMutex),
+    Flock(File),
+}
+
+impl AcceptLock {
+    /// Creates the lock in the parent, before the children are forked.
+    /// Returns `None` for the strategies that do not serialize accepting.
+    pub(crate) fn new(strategy: AcceptStrategy) -> io::Result<Option<Self>> {
+        match strategy {
+            AcceptStrategy::Mutex => Ok(Some(Self::Mutex(ProcessMutex::new()?))),
+            // An unnamed file, so that nothing is left behind on disk
+            AcceptStrategy::Flock => Ok(Some(Self::Flock(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .custom_flags(O_TMPFILE)
+                    .open(std::env::temp_dir())?,
+            ))),
+            AcceptStrategy::Shared | AcceptStrategy::ReusePort => Ok(None),
+        }
+    }
+
+    /// Returns the lock to be used by a freshly forked child.
+    ///
+    /// `flock` locks belong to the open file description, which is shared with the parent and
+    /// every other child after `fork`, so the child reopens the file to get a description of its own.
+    pub(crate) fn for_child(&self) -> io::Result<Self> {
+        match self {
+            Self::Mutex(mutex) => Ok(Self::Mutex(mutex.clone())),
+            Self::Flock(file) => Ok(Self::Flock(
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(format!("/proc/self/fd/{}", file.as_raw_fd()))?,
+            )),
+        }
+    }
+
+    pub(crate) fn lock(&self) -> io::Result<AcceptLockGuard<'_>> {
+        match self {
+            Self::Mutex(mutex) => mutex.lock()?,
+            Self::Flock(file) => loop {
+                match unsafe { libc::flock(file.as_raw_fd(), LOCK_EX) } {
+                    0 => break,
+                    _ if io::Error::last_os_error().raw_os_error() == Some(EINTR) => continue,
+                    _ => Err(io::Error::last_os_error())?,
+                }
+            },
+        }
+
+        Ok(AcceptLockGuard { lock: self })
+    }
+
+    fn unlock(&self) {
+        match self {
+            Self::Mutex(mutex) => mutex.unlock(),
+            Self::Flock(file) => {
+                unsafe { libc::flock(file.as_raw_fd(), LOCK_UN) };
+            }
+        }
+    }
+}
+
+/// Releases the [`AcceptLock`] when dropped.
+pub(crate) struct AcceptLockGuard<'a> {
+    lock: &'a AcceptLock,
+}
+
+impl Drop for AcceptLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+/// Robust `pthread` mutex in anonymous shared memory. A child that dies while holding the
+/// mutex does not block the others: the next one to lock it takes over.
+///
+/// The mapping is never unmapped, since copies of it live on in every forked child.
+#[derive(Clone)]
+pub(crate) struct ProcessMutex {
+    mutex: *const UnsafeCell<libc::pthread_mutex_t>,
+}
+
+impl ProcessMutex {
+    fn new() -> io::Result<Self> {
+        let addr: *mut c_void = match unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                mem::size_of::<libc::pthread_mutex_t>(),
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        } {
+            MAP_FAILED => Err(io::Error::last_os_error())?,
+            addr => addr,
+        };
+        let mutex = addr as *mut libc::pthread_mutex_t;
+
+        unsafe {
+            let mut attr: libc::pthread_mutexattr_t = mem::zeroed();
+            check(libc::pthread_mutexattr_init(&mut attr))?;
+            check(libc::pthread_mutexattr_setpshared(
+                &mut attr,
+                libc::PTHREAD_PROCESS_SHARED,
+            ))?;
+            check(libc::pthread_mutexattr_setrobust(
+                &mut attr,
+                libc::PTHREAD_MUTEX_ROBUST,
+            ))?;
+            check(libc::pthread_mutex_init(mutex, &attr))?;
+            libc::pthread_mutexattr_destroy(&mut attr);
+        }
+
+        Ok(Self {
+            mutex: mutex as *const UnsafeCell<libc::pthread_mutex_t>,
+        })
+    }
+
+    fn lock(&self) -> io::Result<()> {
+        let mutex: *mut libc::pthread_mutex_t = unsafe { (*self.mutex).get() };
+
+        match unsafe { libc::pthread_mutex_lock(mutex) } {
+            0 => Ok(()),
+            // The previous owner died while holding the mutex; there is no state to repair
+            EOWNERDEAD => check(unsafe { libc::pthread_mutex_consistent(mutex) }),
+            err => Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+
+    fn unlock(&self) {
+        unsafe { libc::pthread_mutex_unlock((*self.mutex).get()) };
+    }
+}
+
+fn check(ret: c_int) -> io::Result<()> {
+    match ret {
+        0 => Ok(()),
+        err => Err(io::Error::from_raw_os_error(err)),
+    }
+}
+
+/// A TCP address to listen on with `SO_REUSEPORT`, as [`AcceptStrategy::ReusePort`] requires
+/// of the listener of the prefork server. The option must be set before the socket is bound.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ReusePortAddr(pub SocketAddr);
+
+impl ToListener for ReusePortAddr {
+    fn bind(self) -> io::Result<Listener> {
+        bind_reuseport(self.0).map(Listener::Tcp)
+    }
+}
+
+/// Binds a non-blocking TCP listener with `SO_REUSEPORT` set before binding, so that more
+/// listeners can bind to the same address.
+pub(crate) fn bind_reuseport(socket_addr: SocketAddr) -> io::Result<TcpListener> {
+    let domain: c_int = match socket_addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    let fd: RawFd = match unsafe {
+        libc::socket(
+            domain,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    } {
+        -1 => Err(io::Error::last_os_error())?,
+        fd => fd,
+    };
+    // SAFETY: `socket` returned a new file descriptor that nothing else owns.
+    // Wrapping it right away closes it on the error paths below.
+    let listener: TcpListener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        let enable: c_int = 1;
+        if unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                &enable as *const c_int as *const c_void,
+                mem::size_of::<c_int>() as socklen_t,
+            )
+        } != 0
+        {
+            Err(io::Error::last_os_error())?;
+        }
+    }
+
+    let (storage, len): (libc::sockaddr_storage, socklen_t) = to_sockaddr(socket_addr);
+    if unsafe { libc::bind(fd, &storage as *const _ as *const libc::sockaddr, len) } != 0 {
+        Err(io::Error::last_os_error())?;
+    }
+    if unsafe { libc::listen(fd, LISTEN_BACKLOG) } != 0 {
+        Err(io::Error::last_os_error())?;
+    }
+
+    Ok(listener)
+}
+
+/// Whether `SO_REUSEPORT` is set on the listener, e.g. one inherited from another process.
+pub(crate) fn has_reuseport(listener: &TcpListener) -> io::Result<bool> {
+    let mut enabled: c_int = 0;
+    let mut len: socklen_t = mem::size_of::<c_int>() as socklen_t;
+
+    if unsafe {
+        libc::getsockopt(
+            listener.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &mut enabled as *mut c_int as *mut c_void,
+            &mut len,
+        )
+    } != 0
+    {
+        Err(io::Error::last_os_error())?;
+    }
+
+    Ok(enabled != 0)
+}
+
+fn to_sockaddr(socket_addr: SocketAddr) -> (libc::sockaddr_storage, socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+    let len: usize = match socket_addr {
+        SocketAddr::V4(addr) => {
+            let sin = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: addr.port().to_be(),
+                sin_addr: libc::in_addr {
+                    s_addr: u32::from(*addr.ip()).to_be(),
+                },
+                sin_zero: [0; 8],
+            };
+            unsafe { ptr::write(&mut storage as *mut _ as *mut libc::sockaddr_in, sin) };
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = libc::sockaddr_in6 {
+                sin6_family: libc::AF_INET6 as libc::sa_family_t,
+                sin6_port: addr.port().to_be(),
+                sin6_flowinfo: addr.flowinfo(),
+                sin6_addr: libc::in6_addr {
+                    s6_addr: addr.ip().octets(),
+                },
+                sin6_scope_id: addr.scope_id(),
+            };
+            unsafe { ptr::write(&mut storage as *mut _ as *mut libc::sockaddr_in6, sin6) };
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    (storage, len as socklen_t)
+}
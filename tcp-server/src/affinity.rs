@@ -0,0 +1,162 @@
+use std::{fs, io, mem};
+use std::str::FromStr;
+use libc::{cpu_set_t, CPU_SET, CPU_ISSET, CPU_SETSIZE};
+
+/// Directory with one `node<N>` entry per NUMA node
+const NUMA_NODES_DIR: &str = "/sys/devices/system/node";
+
+/// How workers and prefork children are pinned to CPUs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinCpus {
+    /// Cycle through the CPUs the process may run on (`round-robin`)
+    RoundRobin,
+    /// Like [`PinCpus::RoundRobin`], but fill the CPUs of one NUMA node before moving on to the
+    /// next, so that neighbouring workers share a node (`numa`)
+    Numa,
+    /// Cycle through the given CPUs, written as a CPU list such as `0,2,4-7`
+    List(Vec<usize>),
+}
+
+impl FromStr for PinCpus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "numa" => Ok(Self::Numa),
+            list => parse_cpu_list(list)
+                .filter(|cpus| !cpus.is_empty())
+                .map(Self::List)
+                .ok_or_else(|| {
+                    format!(
+                        "expected `round-robin`, `numa` or a CPU list such as `0,2,4-7`, got {:?}",
+                        list
+                    )
+                }),
+        }
+    }
+}
+
+/// The CPUs that workers are pinned to, in the order they are handed out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuAffinity {
+    cpus: Vec<usize>,
+}
+
+impl CpuAffinity {
+    /// Resolves `pin` against the CPUs this process may run on.
+    pub fn new(pin: &PinCpus) -> io::Result<Self> {
+        let allowed: Vec<usize> = allowed_cpus()?;
+
+        let cpus: Vec<usize> = match pin {
+            PinCpus::RoundRobin => allowed,
+            PinCpus::Numa => match numa_nodes() {
+                Ok(nodes) => nodes
+                    .into_iter()
+                    .flatten()
+                    .filter(|cpu| allowed.contains(cpu))
+                    .collect(),
+                Err(e) => {
+                    tracing::warn!("Failed to read the NUMA topology, pinning round-robin: {}", e);
+                    allowed
+                }
+            },
+            PinCpus::List(cpus) => match cpus.iter().find(|cpu| !allowed.contains(cpu)) {
+                Some(cpu) => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("CPU {} is not in the allowed set {:?}", cpu, allowed),
+                ))?,
+                None => cpus.clone(),
+            },
+        };
+        if cpus.is_empty() {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "No CPUs to pin to"))?;
+        }
+        tracing::info!(?pin, ?cpus, "CPU affinity");
+
+        Ok(Self { cpus })
+    }
+
+    /// Returns the CPU for the worker with the given index.
+    pub fn cpu(&self, index: usize) -> usize {
+        self.cpus[index % self.cpus.len()]
+    }
+
+    /// Pins the calling thread (and only it) to the CPU for the worker with the given index.
+    /// In a single-threaded process, such as a freshly forked child, this pins the whole process.
+    pub fn pin_current_thread(&self, index: usize) -> io::Result<usize> {
+        let cpu: usize = self.cpu(index);
+        let mut set: cpu_set_t = unsafe { mem::zeroed() };
+        unsafe { CPU_SET(cpu, &mut set) };
+
+        match unsafe { libc::sched_setaffinity(0, mem::size_of::<cpu_set_t>(), &set) } {
+            0 => Ok(cpu),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Returns the CPUs the calling thread may run on.
+pub fn allowed_cpus() -> io::Result<Vec<usize>> {
+    let mut set: cpu_set_t = unsafe { mem::zeroed() };
+
+    if unsafe { libc::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut set) } != 0 {
+        Err(io::Error::last_os_error())?;
+    }
+    Ok((0..CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+/// Reads the CPUs of each NUMA node, ordered by node number.
+fn numa_nodes() -> io::Result<Vec<Vec<usize>>> {
+    let mut nodes: Vec<(usize, Vec<usize>)> = Vec::new();
+
+    for entry in fs::read_dir(NUMA_NODES_DIR)? {
+        let entry: fs::DirEntry = entry?;
+        let Some(node) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|id| id.parse::<usize>().ok())
+        else {
+            continue;
+        };
+
+        let cpulist: String = fs::read_to_string(entry.path().join("cpulist"))?;
+        let cpus: Vec<usize> = parse_cpu_list(cpulist.trim()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid cpulist of node {}", node),
+            )
+        })?;
+        nodes.push((node, cpus));
+    }
+    nodes.sort_unstable_by_key(|(node, _)| *node);
+
+    Ok(nodes.into_iter().map(|(_, cpus)| cpus).collect())
+}
+
+/// Parses a CPU list in the kernel's format, e.g. `0,2,4-7`. An empty string is an empty list.
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    if list.is_empty() {
+        return Some(Vec::new());
+    }
+
+    let mut cpus: Vec<usize> = Vec::new();
+    for range in list.split(',') {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (first.parse::<usize>().ok()?, last.parse::<usize>().ok()?),
+            None => {
+                let cpu: usize = range.parse().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last || last >= CPU_SETSIZE as usize {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+
+    Some(cpus)
+}
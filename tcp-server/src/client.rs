@@ -7,6 +7,9 @@ use clap::{Parser, ValueHint};
 use tracing_subscriber::EnvFilter;
 
 use tcp_server::service::FileTransferClient;
+use tcp_server::listener::UnixAddr;
+#[cfg(feature = "tls")]
+use tcp_server::tls::{self, ClientTlsBuilder};
 use tcp_server::proto::prelude::*;
 
 const PROTOCOL_VERSION: u32 = 1;
@@ -17,6 +20,10 @@ struct Args {
     #[arg(short = 'a', long = "socket_addr", default_value = "127.0.0.1:7878")]
     socket_addr: SocketAddr,
 
+    /// Unix socket to connect to instead, as a path or `@NAME` for the abstract namespace
+    #[arg(short = 'u', long = "unix", value_hint = ValueHint::FilePath, conflicts_with = "socket_addr")]
+    unix: Option<UnixAddr>,
+
     /// File name to request from the server
     #[arg(short = 'f', long = "file")]
     file_name: String,
@@ -28,11 +35,53 @@ struct Args {
     /// Directory to save the downloaded file
     #[arg(short = 'd', long = "dir", value_hint = ValueHint::DirPath, default_value = "downloads")]
     download_dir: PathBuf,
+
+    /// PEM file with the CA certificates to trust; connects over TLS if given
+    #[cfg(feature = "tls")]
+    #[arg(long = "tls-ca", value_hint = ValueHint::FilePath, conflicts_with = "unix")]
+    tls_ca: Option<PathBuf>,
+
+    /// Server name to ask for via SNI and to verify the server certificate against
+    #[cfg(feature = "tls")]
+    #[arg(long = "server-name", default_value = "localhost", requires = "tls_ca")]
+    server_name: String,
+
+    /// PEM file with the client certificate chain for servers that require one
+    #[cfg(feature = "tls")]
+    #[arg(long = "tls-cert", value_hint = ValueHint::FilePath, requires_all = ["tls_key", "tls_ca"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --tls-cert
+    #[cfg(feature = "tls")]
+    #[arg(long = "tls-key", value_hint = ValueHint::FilePath, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+}
+
+fn connect(args: &Args) -> io::Result<FileTransferClient> {
+    #[cfg(feature = "tls")]
+    if let Some(ca) = &args.tls_ca {
+        let mut builder = ClientTlsBuilder::new().root_certificates(tls::load_certificates(ca)?);
+        if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+            builder = builder.certificate(tls::load_certificates(cert)?, tls::load_private_key(key)?);
+        }
+
+        return FileTransferClient::connect_tls(
+            args.socket_addr,
+            PROTOCOL_VERSION,
+            builder.build()?,
+            &args.server_name,
+        );
+    }
+
+    match &args.unix {
+        Some(unix) => FileTransferClient::connect_unix(unix, PROTOCOL_VERSION),
+        None => FileTransferClient::connect(args.socket_addr, PROTOCOL_VERSION),
+    }
 }
 
 fn run_client(args: &Args) -> io::Result<()> {
     let file_path: PathBuf = args.download_dir.join(&args.file_name);
-    let mut client = FileTransferClient::connect(args.socket_addr, PROTOCOL_VERSION)?;
+    let mut client: FileTransferClient = connect(args)?;
 
     let file_response: FileResponse = client.request_file(&args.file_name)?;
     tracing::info!(?file_response, "Received FileResponse from server");
@@ -1,233 +1,1880 @@
-use std::io;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::net::{TcpListener, TcpStream, ToSocketAddrs};
-use std::os::fd::{RawFd, AsRawFd as _};
-use libc::{pid_t, c_int, WNOHANG, SIGTERM, PR_SET_PDEATHSIG};
+use std::{io, thread};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::net::SocketAddr;
+use std::os::fd::{RawFd, AsRawFd as _, FromRawFd as _, OwnedFd};
+use libc::{pid_t, c_int, c_void, WNOHANG, SIGCHLD, SIGKILL, SIGTERM, SIGUSR1, PR_SET_PDEATHSIG, EFD_CLOEXEC};
+use libc::{
+    epoll_event, EPOLLIN, EPOLLOUT, EPOLLEXCLUSIVE, EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_MOD,
+    EPOLL_CTL_DEL,
+};
+use clap::ValueEnum;
 use tracing::instrument;
 
-use crate::service::Service;
-use crate::thread_pool::ThreadPool;
+use crate::service::{
+    Service, NonBlockingService, Interest, ServiceBuilder, Trace, TraceLayer, LogErrors, LogErrorsLayer,
+};
+#[cfg(feature = "tokio")]
+use std::task::Poll;
+#[cfg(feature = "tokio")]
+use crate::service::AsyncService;
+use crate::thread_pool::{self, ThreadPool};
+use crate::affinity::CpuAffinity;
+use crate::shutdown::{self, Shutdown, ConnectionGuard, DEFAULT_GRACE_PERIOD};
+use crate::scoreboard::{Scoreboard, Slot, SlotState};
+use crate::accept::{self, AcceptStrategy, AcceptLock, AcceptLockGuard};
+use crate::restart::{Restart, Successor};
+use crate::systemd::{Heartbeat, Notifier, NotifierThread};
+use crate::listener::{Listener, SocketStream, ToListener};
+#[cfg(feature = "tokio")]
+use crate::listener::UnixAddr;
+use crate::stream::{PeerAddr, Stream as _};
+use crate::limit::{Admit, ConnectionLimiter, ConnectionPermit, LimiterChannel, Rejection, RemoteLimiter};
 
-/// Base TCP server that listens on a given socket address.
+/// Interval between checks for exited children while draining
+const REAP_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Children that exit sooner than this after being forked count as crashed
+const MIN_CHILD_LIFETIME: Duration = Duration::from_secs(5);
+/// Respawn delay after the first crash; doubles with every consecutive crash
+const RESPAWN_BASE_DELAY: Duration = Duration::from_millis(100);
+const RESPAWN_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Interval at which an adaptive prefork pool is resized
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Signal that wakes up an idle prefork child to check whether it has been retired
+const RETIRE_SIGNAL: c_int = SIGUSR1;
+
+/// A service wrapped in the layers every server adds: a span per connection and logged errors.
+/// Errors are reported by the layers, so the servers do not look at the results.
+type Layered<S> = Trace<LogErrors<S>>;
+
+fn layered<S>(service: S) -> Layered<S> {
+    ServiceBuilder::new()
+        .layer(TraceLayer)
+        .layer(LogErrorsLayer)
+        .service(service)
+}
+
+/// Outcome of admitting an accepted connection: its permit, if a limiter applies, or why
+/// it was rejected
+type Admission = Result<Option<ConnectionPermit>, Rejection>;
+
+/// Admits an accepted connection through the limiter, if there is one. Peers without an IP
+/// address, such as Unix socket clients, are not limited.
+fn admit(limiter: Option<&dyn Admit>, peer: &PeerAddr) -> Admission {
+    match (limiter, peer.ip()) {
+        (Some(limiter), Some(ip)) => limiter.admit(ip).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Turns away a connection the limiter rejected. Denied connections are just closed; the
+/// others get the rejection response of the service.
+fn turn_away(service: &impl Service, stream: SocketStream, rejection: Rejection) {
+    if rejection != Rejection::Denied {
+        let _ = service.reject_connection(stream);
+    }
+}
+
+/// Base server that listens on a TCP or Unix socket.
 /// Used as a building block for other server types.
 struct BaseTcpServer {
-    listener: TcpListener,
+    listener: Listener,
+    shutdown: Shutdown,
+    restart: Option<Restart>,
+    notifier: Option<Notifier>,
+    limiter: Option<Arc<ConnectionLimiter>>,
 }
 
 impl BaseTcpServer {
-    fn bind(socket_addr: impl ToSocketAddrs) -> io::Result<Self> {
-        let listener: TcpListener = TcpListener::bind(socket_addr)?;
+    /// Binds to `addr`, or takes it over if it is a [`Listener`] already, e.g. one inherited
+    /// from the server being restarted or passed by systemd socket activation.
+    fn bind(addr: impl ToListener) -> io::Result<Self> {
+        let listener: Listener = addr.bind()?;
+        // Readiness is polled together with the shutdown eventfd, so `accept` must never block
+        listener.set_nonblocking(true)?;
 
-        Ok(Self { listener })
+        Ok(Self {
+            listener,
+            shutdown: Shutdown::new(DEFAULT_GRACE_PERIOD)?,
+            restart: None,
+            notifier: None,
+            limiter: None,
+        })
     }
 
-    fn init(&self) -> io::Result<()> {
-        tracing::info!("Listening on {}...", self.listener.local_addr()?);
+    /// Starts listening for hot restarts and reports readiness. The returned notifier thread
+    /// must be kept until the server stops.
+    fn init(&self) -> io::Result<Option<NotifierThread>> {
+        let status: String = format!("Listening on {}", self.listener.listen_addr()?);
+        tracing::info!("{}...", status);
 
-        Ok(())
+        if let Some(restart) = &self.restart {
+            restart.spawn_watcher(&self.listener, self.shutdown.clone())?;
+        }
+        // The listener is ready, so the previous server (if any) can stop accepting
+        let restarted: bool = match &self.restart {
+            Some(restart) => restart.notify_ready()?,
+            None => false,
+        };
+
+        self.notifier
+            .as_ref()
+            .map(|notifier| notifier.start(&status, restarted, self.shutdown.clone(), self.restart.clone()))
+            .transpose()
+    }
+
+    /// Like [`BaseTcpServer::init`], but for a server that forks: instead of starting threads,
+    /// it returns the housekeeping that the serving loop has to do.
+    fn init_forking(&self) -> io::Result<Housekeeping<'_>> {
+        let status: String = format!("Listening on {}", self.listener.listen_addr()?);
+        tracing::info!("{}...", status);
+
+        // The listener is ready, so the previous server (if any) can stop accepting
+        let restarted: bool = match &self.restart {
+            Some(restart) => restart.notify_ready()?,
+            None => false,
+        };
+        if let Some(notifier) = &self.notifier {
+            notifier.ready(&status, restarted)?;
+        }
+
+        Ok(Housekeeping {
+            server: self,
+            successor: None,
+            next_ping: self.watchdog_interval().map(|interval| Instant::now() + interval),
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Returns how often the systemd watchdog has to be pinged, if it is enabled.
+    fn watchdog_interval(&self) -> Option<Duration> {
+        self.notifier.as_ref().and_then(Notifier::watchdog).map(|interval| interval / 2)
+    }
+
+    /// Returns the heartbeat of the systemd watchdog, if one is set.
+    fn heartbeat(&self) -> Option<Heartbeat> {
+        self.notifier.as_ref().and_then(Notifier::heartbeat)
     }
 
-    #[instrument(name = "server", skip_all)]
     fn run_accept_loop<F>(&self, connection_handler: F) -> io::Result<()>
     where
-        F: Fn(TcpStream),
+        F: Fn(SocketStream, Admission),
     {
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    if let Ok(peer) = stream.peer_addr() {
-                        tracing::info!(peer_addr = ?peer, "Accepted connection");
-                    }
-                    connection_handler(stream);
+        let limiter: Option<&dyn Admit> = self.limiter.as_ref().map(|limiter| limiter as &dyn Admit);
+        let heartbeat: Option<Heartbeat> = self.heartbeat();
+        self.run_accept_loop_until(
+            &[&self.listener],
+            None,
+            limiter,
+            heartbeat.as_ref(),
+            || false,
+            connection_handler,
+        )
+    }
+
+    /// Runs the accept loop on the given listeners until shutdown is requested or `stop`
+    /// returns `true`. `stop` is checked between connections and whenever a signal interrupts
+    /// the wait. If a lock is given, only its holder waits for and accepts a connection.
+    /// Connections are admitted through `limiter` as soon as they are accepted. The loop beats
+    /// `heartbeat` on every iteration, waking up in time for it while idle.
+    #[instrument(name = "server", skip_all)]
+    fn run_accept_loop_until<P, F>(
+        &self,
+        listeners: &[&Listener],
+        lock: Option<&AcceptLock>,
+        limiter: Option<&dyn Admit>,
+        heartbeat: Option<&Heartbeat>,
+        stop: P,
+        connection_handler: F,
+    ) -> io::Result<()>
+    where
+        P: Fn() -> bool,
+        F: Fn(SocketStream, Admission),
+    {
+        let mut fds: Vec<RawFd> = listeners.iter().map(|listener| listener.as_raw_fd()).collect();
+        fds.push(self.shutdown.as_raw_fd());
+
+        loop {
+            if let Some(heartbeat) = heartbeat {
+                heartbeat.beat();
+            }
+            let guard: Option<AcceptLockGuard> = lock.map(AcceptLock::lock).transpose()?;
+            if stop() {
+                tracing::info!("Stopped accepting connections");
+                return Ok(());
+            }
+
+            let timeout: Option<Duration> = heartbeat.map(Heartbeat::interval);
+            let ready: Vec<bool> = match shutdown::poll_readable(&fds, timeout) {
+                Ok(ready) if ready[listeners.len()] => break,
+                Ok(ready) => ready,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let Some(listener) = listeners.iter().zip(ready).find_map(|(listener, ready)| ready.then_some(listener)) else {
+                continue;
+            };
+
+            let accepted: io::Result<(SocketStream, PeerAddr)> = listener.accept();
+            // Let the next child wait for a connection while this one handles it
+            drop(guard);
+
+            match accepted {
+                Ok((stream, peer)) => {
+                    tracing::info!(peer_addr = %peer, "Accepted connection");
+                    connection_handler(stream, admit(limiter, &peer));
                 }
+                // Another process or thread accepted the connection first
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
                 Err(e) => tracing::error!("Failed to establish a connection: {}", e),
             }
         }
+        tracing::info!("Shutdown requested. Stopped accepting connections");
 
         Ok(())
     }
 
+    /// Spawns a thread that waits for shutdown and then drains the tracked connections,
+    /// aborting the ones that outlive the grace period.
+    fn spawn_drainer(&self) -> thread::JoinHandle<()> {
+        let shutdown: Shutdown = self.shutdown.clone();
+
+        thread::spawn(move || {
+            shutdown.wait(None);
+            shutdown.drain_connections(shutdown.deadline());
+        })
+    }
+
     fn close_listener(&self) {
         let listener_fd: RawFd = self.listener.as_raw_fd();
         unsafe { libc::close(listener_fd) };
     }
 }
 
+/// What the restart watcher and the notifier thread do for the other servers, done by the
+/// serving loop of a forking server instead. A child forked while another thread runs inherits
+/// the locks that thread holds (e.g. of the allocator or of stdout) and may deadlock on them,
+/// so the forking servers never start a thread.
+struct Housekeeping<'a> {
+    server: &'a BaseTcpServer,
+    /// New server started by a restart, until it reports whether it is ready
+    successor: Option<Successor>,
+    next_ping: Option<Instant>,
+}
+
+impl Housekeeping<'_> {
+    /// Returns the descriptors the serving loop has to poll for [`Housekeeping::run`].
+    fn fds(&self) -> Vec<RawFd> {
+        match (&self.successor, &self.server.restart) {
+            (Some(successor), _) => vec![successor.as_raw_fd()],
+            (None, Some(restart)) => vec![restart.as_raw_fd()],
+            (None, None) => Vec::new(),
+        }
+    }
+
+    /// Returns how long the serving loop may wait before it has to call [`Housekeeping::run`].
+    fn timeout(&self) -> Option<Duration> {
+        let deadline: Option<Instant> = self.successor.as_ref().map(|successor| successor.deadline);
+        deadline
+            .into_iter()
+            .chain(self.next_ping)
+            .min()
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Carries out a requested restart given which of the descriptors of [`Housekeeping::fds`]
+    /// are `ready`, and pings the watchdog when it is due. Once the new server is ready,
+    /// shutdown is triggered.
+    fn run(&mut self, ready: &[bool]) {
+        let ready: bool = ready.first() == Some(&true);
+        let now: Instant = Instant::now();
+
+        if let Some(restart) = &self.server.restart {
+            match self.successor.take() {
+                Some(successor) if ready || now >= successor.deadline => {
+                    restart.complete(successor, ready, &self.server.shutdown);
+                }
+                Some(successor) => self.successor = Some(successor),
+                None if ready => self.successor = restart.start_successor(&self.server.listener),
+                None => {}
+            }
+        }
+
+        if let (Some(next_ping), Some(notifier)) = (self.next_ping, &self.server.notifier) {
+            if now >= next_ping {
+                notifier.ping_watchdog();
+                self.next_ping = self.server.watchdog_interval().map(|interval| now + interval);
+            }
+        }
+    }
+
+    /// Reports that the server is stopping, once shutdown has been requested.
+    fn stop(self) {
+        if let Some(notifier) = &self.server.notifier {
+            notifier.stopping(self.server.restart.as_ref());
+        }
+    }
+}
+
 /// Iterative TCP server that handles one connection at a time.
 pub struct IterativeTcpServer<S: Service> {
-    service: S,
+    service: Layered<S>,
     server: BaseTcpServer,
 }
 
 impl<S: Service> IterativeTcpServer<S> {
-    pub fn new(socket_addr: impl ToSocketAddrs, service: S) -> io::Result<Self> {
+    pub fn new(addr: impl ToListener, service: S) -> io::Result<Self> {
         Ok(Self {
-            service,
-            server: BaseTcpServer::bind(socket_addr)?,
+            service: layered(service),
+            server: BaseTcpServer::bind(addr)?,
         })
     }
 
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
+
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter as they are accepted. Connections over a limit
+    /// get the rejection response of the service; denied ones are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
     pub fn serve(&self) -> io::Result<()> {
-        self.server.init()?;
+        let _notifier: Option<NotifierThread> = self.server.init()?;
+        let drainer: thread::JoinHandle<()> = self.server.spawn_drainer();
 
-        self.server.run_accept_loop(|stream| {
-            if let Err(e) = self.service.handle_connection(stream) {
-                tracing::error!("Service error: failed to handle connection: {}", e);
-            }
-        })
+        self.server.run_accept_loop(|stream, admission| {
+            let _permit: Option<ConnectionPermit> = match admission {
+                Ok(permit) => permit,
+                Err(rejection) => return turn_away(&self.service, stream, rejection),
+            };
+            let _guard: ConnectionGuard = self.server.shutdown.track(&stream);
+            let _ = self.service.handle_connection(stream);
+        })?;
+
+        drainer.join().expect("Drainer thread panicked");
+        Ok(())
     }
 }
 
+/// What the thread pool server does with a new connection while its queue is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum OverloadPolicy {
+    /// Stop accepting until there is room in the queue
+    #[default]
+    Block,
+    /// Reject the connection with a busy response (see [`Service::reject_connection`])
+    Busy,
+    /// Handle the connection on the accepting thread
+    CallerRuns,
+}
+
 /// Thread pool-based TCP server that handles multiple connections concurrently.
+///
+/// By default connections queue up for the workers without limit. With
+/// [`ThreadPoolTcpServer::with_queue_capacity`] the queue is bounded and the [`OverloadPolicy`]
+/// decides what happens when it is full. With [`ThreadPoolTcpServer::with_max_workers`] the pool
+/// grows while connections queue up and shrinks again when the load drops.
 pub struct ThreadPoolTcpServer<S: Service> {
-    service: Arc<S>,
+    service: Arc<Layered<S>>,
     server: BaseTcpServer,
-    pool: ThreadPool,
+    pool_config: thread_pool::Builder,
+    /// Built from `pool_config` once the server starts serving
+    pool: OnceLock<ThreadPool>,
+    overload_policy: OverloadPolicy,
 }
 
 impl<S: Service> ThreadPoolTcpServer<S> {
-    pub fn new(socket_addr: impl ToSocketAddrs, service: S, num_workers: usize) -> io::Result<Self> {
+    pub fn new(addr: impl ToListener, service: S, num_workers: usize) -> io::Result<Self> {
         Ok(Self {
-            service: Arc::new(service),
-            server: BaseTcpServer::bind(socket_addr)?,
-            pool: ThreadPool::new(num_workers),
+            service: Arc::new(layered(service)),
+            server: BaseTcpServer::bind(addr)?,
+            pool_config: thread_pool::Builder::new(num_workers),
+            pool: OnceLock::new(),
+            overload_policy: OverloadPolicy::default(),
         })
     }
 
+    /// Bounds the queue of connections waiting for a worker and sets what happens when it is full.
+    pub fn with_queue_capacity(mut self, capacity: usize, policy: OverloadPolicy) -> Self {
+        self.pool_config = self.pool_config.queue_capacity(capacity);
+        self.overload_policy = policy;
+        self
+    }
+
+    /// Lets the pool grow up to `max_workers` while connections wait for a worker.
+    /// Workers above `num_workers` retire after being idle for `keep_alive`.
+    pub fn with_max_workers(mut self, max_workers: usize, keep_alive: Duration) -> Self {
+        self.pool_config = self.pool_config.max_workers(max_workers).keep_alive(keep_alive);
+        self
+    }
+
+    /// Pins each worker to a CPU.
+    pub fn with_cpu_affinity(mut self, affinity: CpuAffinity) -> Self {
+        self.pool_config = self.pool_config.pin_cpus(affinity);
+        self
+    }
+
+    /// Returns the number of accepted connections waiting for a worker.
+    pub fn queue_depth(&self) -> usize {
+        self.pool.get().map_or(0, ThreadPool::queue_depth)
+    }
+
+    /// Returns the number of running workers, which is 0 until the server starts serving.
+    pub fn worker_count(&self) -> usize {
+        self.pool.get().map_or(0, ThreadPool::worker_count)
+    }
+
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
+
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter as they are accepted, before they are queued.
+    /// Connections over a limit get the rejection response of the service; denied ones are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
+    /// Serves connections until shutdown is requested. Queued and running connections are
+    /// drained before returning; the workers are joined when the server is dropped.
     pub fn serve(&self) -> io::Result<()> {
-        self.server.init()?;
+        let pool: &ThreadPool = self.pool.get_or_init(|| self.pool_config.clone().build());
+        let _notifier: Option<NotifierThread> = self.server.init()?;
+        let drainer: thread::JoinHandle<()> = self.server.spawn_drainer();
 
-        self.server.run_accept_loop(|stream| {
-            let service: Arc<S> = Arc::clone(&self.service);
+        self.server.run_accept_loop(|stream, admission| {
+            let permit: Option<ConnectionPermit> = match admission {
+                Ok(permit) => permit,
+                Err(rejection) => return turn_away(&*self.service, stream, rejection),
+            };
+            let service: Arc<Layered<S>> = Arc::clone(&self.service);
+            // The accepting thread is the only one queueing jobs, so a queue with room left
+            // cannot fill up before the job is queued
+            let queue_full: bool = pool
+                .queue_capacity()
+                .is_some_and(|capacity| pool.queue_depth() >= capacity);
+            if queue_full {
+                tracing::warn!(policy = ?self.overload_policy, "Connection queue is full");
 
-            self.pool.execute(move || {
-                if let Err(e) = service.handle_connection(stream) {
-                    tracing::error!("Service error: failed to handle connection: {}", e);
+                if self.overload_policy == OverloadPolicy::Busy {
+                    let _ = service.reject_connection(stream);
+                    return;
                 }
-            });
-        })
+            }
+
+            let guard: ConnectionGuard = self.server.shutdown.track(&stream);
+            let job = move || {
+                let _guard: ConnectionGuard = guard;
+                let _permit: Option<ConnectionPermit> = permit;
+                let _ = service.handle_connection(stream);
+            };
+
+            if queue_full && self.overload_policy == OverloadPolicy::CallerRuns {
+                job();
+            } else {
+                // Blocks while the queue is full
+                pool.execute(job);
+                tracing::debug!(queue_depth = pool.queue_depth(), "Queued connection");
+            }
+        })?;
+
+        drainer.join().expect("Drainer thread panicked");
+        Ok(())
     }
 }
 
+/// What the fork-per-connection server does with new connections while `max_children`
+/// children are running
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, ValueEnum)]
+pub enum LimitPolicy {
+    /// Leave connections in the listen backlog until a child exits
+    #[default]
+    Queue,
+    /// Accept connections and reject them with a busy response (see [`Service::reject_connection`])
+    Reject,
+}
+
 /// Fork-per-connection TCP server that forks a new process for each incoming connection.
+///
+/// Children are reaped as soon as they exit, on `SIGCHLD`, so the number of running children
+/// is always known and no zombies are left behind while the server is idle.
 pub struct ForkPerConnectionTcpServer<S: Service> {
-    service: S,
+    service: Layered<S>,
     server: BaseTcpServer,
     max_children: usize,
-    active_children: AtomicUsize,
+    limit_policy: LimitPolicy,
+    children: ChildProcesses,
+    /// Permits of the connections the children handle, released when the child is reaped
+    permits: Mutex<HashMap<pid_t, ConnectionPermit>>,
 }
 
 impl<S: Service> ForkPerConnectionTcpServer<S> {
-    pub fn new(socket_addr: impl ToSocketAddrs, service: S, max_children: usize) -> io::Result<Self> {
+    pub fn new(addr: impl ToListener, service: S, max_children: usize) -> io::Result<Self> {
         Ok(Self {
-            service,
-            server: BaseTcpServer::bind(socket_addr)?,
+            service: layered(service),
+            server: BaseTcpServer::bind(addr)?,
             max_children,
-            active_children: AtomicUsize::new(0),
+            limit_policy: LimitPolicy::default(),
+            children: ChildProcesses::default(),
+            permits: Mutex::new(HashMap::new()),
         })
     }
 
+    /// Sets what happens to new connections while `max_children` children are running.
+    pub fn with_limit_policy(mut self, policy: LimitPolicy) -> Self {
+        self.limit_policy = policy;
+        self
+    }
+
+    /// Returns the number of running children.
+    pub fn active_children(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
+
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter as they are accepted, before a child is forked.
+    /// The permit is held until the child is reaped. Connections over a limit get the rejection
+    /// response of the service; denied ones are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
     pub fn serve(&self) -> io::Result<()> {
-        self.server.init()?;
+        let mut housekeeping: Housekeeping = self.server.init_forking()?;
+        // Installed before forking, so that no exit goes unnoticed
+        let exits: ChildExits = ChildExits::install()?;
 
-        self.server.run_accept_loop(|stream| {
-            self.cleanup_finished_children();
-            self.wait_for_available_slot();
+        let result: io::Result<()> = self.accept_connections(&exits, &mut housekeeping);
+        housekeeping.stop();
 
-            match unsafe { libc::fork() } {
-                0 => {
-                    self.run_child_process(stream);
-                    unsafe { libc::_exit(0) };
-                }
-                pid if pid > 0 => {
-                    self.active_children.fetch_add(1, Ordering::Relaxed);
-                    tracing::info!(%pid, active = self.active_children.load(Ordering::Relaxed), "Forked child");
+        self.children.drain(self.server.shutdown.deadline());
+        self.permits.lock().unwrap().clear();
+        result
+    }
+
+    /// Accepts connections and forks a child for each of them until shutdown is requested,
+    /// reaping the children that exit and doing the housekeeping in the meantime.
+    #[instrument(name = "server", skip_all)]
+    fn accept_connections(&self, exits: &ChildExits, housekeeping: &mut Housekeeping) -> io::Result<()> {
+        let mut at_limit: bool = false;
+
+        loop {
+            if at_limit != (self.active_children() >= self.max_children) {
+                at_limit = !at_limit;
+                match at_limit {
+                    true => {
+                        tracing::warn!(policy = ?self.limit_policy, "Reached the maximum number of children")
+                    }
+                    false => tracing::info!("Below the maximum number of children again"),
                 }
-                _ => tracing::error!("Failed to fork a child process"),
             }
-        })
-    }
 
-    #[instrument(name = "child", skip_all, fields(pid = unsafe { libc::getpid() }))]
-    fn run_child_process(&self, stream: TcpStream) {
-        self.server.close_listener();
+            let housekeeping_fds: Vec<RawFd> = housekeeping.fds();
+            let mut fds: Vec<RawFd> = [self.server.shutdown.as_raw_fd(), exits.as_raw_fd()]
+                .into_iter()
+                .chain(housekeeping_fds.iter().copied())
+                .collect();
+            // While queueing, new connections wait in the listen backlog until a child exits
+            if !at_limit || self.limit_policy == LimitPolicy::Reject {
+                fds.push(self.server.listener.as_raw_fd());
+            }
 
-        if let Err(e) = self.service.handle_connection(stream) {
-            tracing::error!("Service error: failed to handle connection: {}", e);
+            let ready: Vec<bool> = match shutdown::poll_readable(&fds, housekeeping.timeout()) {
+                Ok(ready) => ready,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if ready[0] {
+                break;
+            }
+            let (housekeeping_ready, listener_ready) = ready[2..].split_at(housekeeping_fds.len());
+            housekeeping.run(housekeeping_ready);
+            if ready[1] {
+                exits.clear();
+                self.reap_children();
+            }
+            if listener_ready.first() == Some(&true) {
+                match self.server.listener.accept() {
+                    Ok((stream, peer)) if self.active_children() >= self.max_children => {
+                        tracing::warn!(peer_addr = %peer, "Rejected connection: too many children");
+                        let _ = self.service.reject_connection(stream);
+                    }
+                    Ok((stream, peer)) => {
+                        tracing::info!(peer_addr = %peer, "Accepted connection");
+                        let limiter: Option<&dyn Admit> = self.server.limiter.as_ref().map(|l| l as &dyn Admit);
+                        match admit(limiter, &peer) {
+                            Ok(permit) => self.fork_child(stream, permit),
+                            Err(rejection) => turn_away(&self.service, stream, rejection),
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => tracing::error!("Failed to establish a connection: {}", e),
+                }
+            }
         }
+        tracing::info!("Shutdown requested. Stopped accepting connections");
+
+        Ok(())
     }
 
-    fn cleanup_finished_children(&self) {
-        while let Ok(Some((pid, status))) = wait_child(true) {
-            tracing::info!(%pid, %status, "Child exited");
-            self.active_children.fetch_sub(1, Ordering::Relaxed);
+    fn fork_child(&self, stream: SocketStream, permit: Option<ConnectionPermit>) {
+        match unsafe { libc::fork() } {
+            0 => {
+                self.run_child_process(stream);
+                unsafe { libc::_exit(0) };
+            }
+            -1 => tracing::error!("Failed to fork a child process: {}", io::Error::last_os_error()),
+            pid => {
+                self.children.insert(pid);
+                if let Some(permit) = permit {
+                    self.permits.lock().unwrap().insert(pid, permit);
+                }
+                tracing::info!(%pid, active = self.active_children(), "Forked child");
+            }
         }
     }
 
-    fn wait_for_available_slot(&self) {
-        while self.active_children.load(Ordering::Relaxed) >= self.max_children {
-            tracing::warn!("Reached the maximum number of children. Waiting for a child to exit...");
+    #[instrument(name = "child", skip_all, fields(pid = unsafe { libc::getpid() }))]
+    fn run_child_process(&self, stream: SocketStream) {
+        self.server.close_listener();
+        let _ = self.service.handle_connection(stream);
+    }
 
-            if let Err(e) = wait_child(false) {
-                tracing::error!("Failed to wait for a child: {}", e);
-            } else {
-                self.active_children.fetch_sub(1, Ordering::Relaxed);
+    /// Reaps every child that has exited. Only the children of this server are counted,
+    /// so other processes (e.g. a server started by a hot restart) do not skew the count.
+    fn reap_children(&self) {
+        loop {
+            match wait_child(true) {
+                Ok(Some((pid, status))) => {
+                    if self.children.remove(pid).is_some() {
+                        self.permits.lock().unwrap().remove(&pid);
+                        let active: usize = self.active_children();
+                        tracing::info!(%pid, status = %describe_status(status), active, "Child exited");
+                    }
+                }
+                Ok(None) => break,
+                // No children left
+                Err(e) if e.raw_os_error() == Some(libc::ECHILD) => break,
+                Err(e) => {
+                    tracing::error!("Failed to wait for a child: {}", e);
+                    break;
+                }
             }
         }
     }
 }
 
-/// Prefork TCP server that forks a fixed number of child processes to handle incoming connections.
+/// Prefork TCP server that forks child processes in advance to handle incoming connections.
+///
+/// By default the pool has a fixed size. With [`PreforkTcpServer::with_spare_children`] it grows
+/// and shrinks with the load, based on the idle and busy states the children report through
+/// a shared-memory [`Scoreboard`].
 pub struct PreforkTcpServer<S: Service> {
-    service: S,
+    service: Layered<S>,
     server: BaseTcpServer,
     num_children: usize,
+    spare: Option<SpareChildren>,
+    max_requests_per_child: Option<u64>,
+    accept_strategy: AcceptStrategy,
+    cpu_affinity: Option<CpuAffinity>,
+}
+
+/// A running prefork child, as the parent keeps track of it
+struct PreforkChild {
+    slot: usize,
+    /// Answers the admission requests of the child
+    limiter: Option<LimiterChannel>,
+}
+
+/// Bounds of an adaptive prefork pool
+#[derive(Debug, Copy, Clone)]
+struct SpareChildren {
+    min_spare: usize,
+    max_spare: usize,
+    max_children: usize,
 }
 
 impl<S: Service> PreforkTcpServer<S> {
-    pub fn new(socket_addr: impl ToSocketAddrs, service: S, num_children: usize) -> io::Result<Self> {
+    pub fn new(addr: impl ToListener, service: S, num_children: usize) -> io::Result<Self> {
         Ok(Self {
-            service,
-            server: BaseTcpServer::bind(socket_addr)?,
+            service: layered(service),
+            server: BaseTcpServer::bind(addr)?,
             num_children,
+            spare: None,
+            max_requests_per_child: None,
+            accept_strategy: AcceptStrategy::default(),
+            cpu_affinity: None,
         })
     }
 
+    /// Selects how the children take turns accepting connections. [`AcceptStrategy::ReusePort`]
+    /// requires a TCP listener with `SO_REUSEPORT` set, i.e. one bound to an [`accept::ReusePortAddr`]
+    /// or inherited with the option; with any other listener the children fall back to
+    /// [`AcceptStrategy::Shared`], since the listener is never replaced.
+    pub fn with_accept_strategy(mut self, strategy: AcceptStrategy) -> io::Result<Self> {
+        let supported: bool = match (&self.server.listener, strategy) {
+            (Listener::Tcp(listener), AcceptStrategy::ReusePort) => accept::has_reuseport(listener)?,
+            (_, AcceptStrategy::ReusePort) => false,
+            _ => true,
+        };
+
+        self.accept_strategy = if supported {
+            strategy
+        } else {
+            tracing::warn!("The listener does not have SO_REUSEPORT set. Falling back to a shared listener");
+            AcceptStrategy::Shared
+        };
+        Ok(self)
+    }
+
+    /// Makes the pool adaptive: children are forked while fewer than `min_spare` of them are idle
+    /// and retired while more than `max_spare` are idle, up to `max_children` in total.
+    /// The number of children passed to [`PreforkTcpServer::new`] becomes the minimum pool size.
+    pub fn with_spare_children(mut self, min_spare: usize, max_spare: usize, max_children: usize) -> Self {
+        assert!(
+            min_spare <= max_spare,
+            "min_spare must not be greater than max_spare"
+        );
+        assert!(
+            max_children > 0 && max_children >= self.num_children,
+            "max_children must be greater than 0 and not less than the initial number of children"
+        );
+
+        self.spare = Some(SpareChildren {
+            min_spare,
+            max_spare,
+            max_children,
+        });
+        self
+    }
+
+    /// Replaces each child after it has handled the given number of connections,
+    /// which contains the effect of memory leaks in the service.
+    pub fn with_max_requests_per_child(mut self, max_requests: u64) -> Self {
+        assert!(
+            max_requests > 0,
+            "Maximum number of requests per child must be greater than 0"
+        );
+
+        self.max_requests_per_child = Some(max_requests);
+        self
+    }
+
+    /// Pins each child to a CPU, chosen by the child's scoreboard slot.
+    pub fn with_cpu_affinity(mut self, affinity: CpuAffinity) -> Self {
+        self.cpu_affinity = Some(affinity);
+        self
+    }
+
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
+
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter of this process as the children accept them,
+    /// so the limits hold across all children. The permits are held here until the connection
+    /// is closed or the child exits. Connections over a limit get the rejection response of
+    /// the service; denied ones are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
+    /// Forks the children and supervises them until shutdown. The children share the shutdown
+    /// eventfd, so they stop accepting at the same time and exit once their current connection is done.
     pub fn serve(&self) -> io::Result<()> {
-        self.server.init()?;
+        // Installed before forking, so that no exit goes unnoticed
+        let exits: ChildExits = ChildExits::install()?;
+        let children = ChildProcesses::default();
+        let scoreboard = Scoreboard::new(self.spare.map_or(self.num_children, |s| s.max_children))?;
+        let lock: Option<AcceptLock> = AcceptLock::new(self.accept_strategy)?;
+        let mut running: HashMap<pid_t, PreforkChild> = HashMap::new();
 
-        for _ in 0..self.num_children {
-            match unsafe { libc::fork() } {
-                0 => {
-                    if let Err(e) = self.run_child_process() {
-                        tracing::error!("Child process failed: {}", e);
+        // The initial children are forked before readiness is reported, so that they are
+        // accepting by then. Like every later fork, this happens while no other thread runs.
+        for _ in 0..self.children_to_spawn(0, &scoreboard) {
+            if let Err(e) = self.spawn_child(&children, &scoreboard, &mut running, lock.as_ref()) {
+                tracing::error!("Failed to fork a child process: {}", e);
+                break;
+            }
+        }
+        let mut housekeeping: Housekeeping = self.server.init_forking()?;
+        tracing::info!(strategy = ?self.accept_strategy, "Accept strategy");
+
+        let result: io::Result<()> =
+            self.supervise(&children, &exits, &scoreboard, &mut running, lock.as_ref(), &mut housekeeping);
+        housekeeping.stop();
+
+        tracing::info!("Shutdown requested. Waiting for children to exit");
+        children.drain(self.server.shutdown.deadline());
+
+        result
+    }
+
+    /// Keeps the pool populated until shutdown is requested. Children that exit are reaped on
+    /// `SIGCHLD` and replaced as needed; children that keep crashing are respawned with a delay.
+    /// In between, the admission requests of the children are answered and the housekeeping is done.
+    #[instrument(name = "supervisor", skip_all)]
+    fn supervise(
+        &self,
+        children: &ChildProcesses,
+        exits: &ChildExits,
+        scoreboard: &Scoreboard,
+        running: &mut HashMap<pid_t, PreforkChild>,
+        lock: Option<&AcceptLock>,
+        housekeeping: &mut Housekeeping,
+    ) -> io::Result<()> {
+        let mut backoff = RespawnBackoff::default();
+
+        loop {
+            if backoff.is_ready() {
+                for _ in 0..self.children_to_spawn(children.len(), scoreboard) {
+                    if let Err(e) = self.spawn_child(children, scoreboard, running, lock) {
+                        tracing::error!("Failed to fork a child process: {}", e);
+                        backoff.record_failure();
+                        break;
                     }
-                    unsafe { libc::_exit(0) };
                 }
-                pid if pid > 0 => tracing::info!(%pid, "Forked child process"),
-                _ => tracing::error!("Failed to fork a child process"),
             }
+            if let Some(spare) = self.spare {
+                self.retire_spare_children(spare, scoreboard, running);
+            }
+
+            let timeout: Option<Duration> = match self.spare {
+                // Wake up periodically to resize the pool
+                Some(_) if backoff.is_ready() => Some(MAINTENANCE_INTERVAL),
+                Some(_) => Some(backoff.remaining().min(MAINTENANCE_INTERVAL)),
+                // Wake up for the respawn if some children are still missing
+                None => (children.len() < self.num_children).then(|| backoff.remaining()),
+            };
+            let timeout: Option<Duration> = timeout.into_iter().chain(housekeeping.timeout()).min();
+            let housekeeping_fds: Vec<RawFd> = housekeeping.fds();
+            let channels: Vec<&mut LimiterChannel> =
+                running.values_mut().filter_map(|child| child.limiter.as_mut()).collect();
+            let fds: Vec<RawFd> = [self.server.shutdown.as_raw_fd(), exits.as_raw_fd()]
+                .into_iter()
+                .chain(housekeeping_fds.iter().copied())
+                .chain(channels.iter().map(|channel| channel.as_raw_fd()))
+                .collect();
+            let ready: Vec<bool> = match shutdown::poll_readable(&fds, timeout) {
+                Ok(ready) => ready,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            if ready[0] {
+                return Ok(());
+            }
+            let (housekeeping_ready, channels_ready) = ready[2..].split_at(housekeeping_fds.len());
+            housekeeping.run(housekeeping_ready);
+            for (channel, _) in channels.into_iter().zip(channels_ready).filter(|(_, &ready)| ready) {
+                if let Err(e) = channel.serve() {
+                    tracing::error!("Failed to answer a child's admission request: {}", e);
+                }
+            }
+            if ready[1] {
+                exits.clear();
+                self.reap_children(children, scoreboard, running, &mut backoff);
+            }
+        }
+    }
+
+    /// Returns how many children should be forked to keep the pool at its minimum size
+    /// and, for an adaptive pool, to have at least `min_spare` idle children.
+    fn children_to_spawn(&self, num_children: usize, scoreboard: &Scoreboard) -> usize {
+        let missing: usize = self.num_children.saturating_sub(num_children);
+
+        match self.spare {
+            Some(spare) => {
+                let idle: usize = scoreboard.count(SlotState::Idle);
+                let wanted: usize = missing.max(spare.min_spare.saturating_sub(idle));
+
+                wanted.min(spare.max_children.saturating_sub(num_children))
+            }
+            None => missing,
         }
-        unsafe { libc::pause() }; // Just wait for a signal
+    }
 
-        Ok(())
+    /// Marks idle children above `max_spare` as retiring (never shrinking the pool below its
+    /// minimum size) and wakes up every retiring child, so that it notices and exits.
+    fn retire_spare_children(
+        &self,
+        spare: SpareChildren,
+        scoreboard: &Scoreboard,
+        running: &HashMap<pid_t, PreforkChild>,
+    ) {
+        let idle: usize = scoreboard.count(SlotState::Idle);
+        let busy: usize = scoreboard.count(SlotState::Busy);
+        tracing::debug!(idle, busy, total = running.len(), "Pool status");
+
+        let mut excess: usize = idle
+            .saturating_sub(spare.max_spare)
+            .min(running.len().saturating_sub(self.num_children));
+
+        for (&pid, child) in running {
+            let slot: &Slot = scoreboard.slot(child.slot);
+
+            if excess > 0 && slot.transition(SlotState::Idle, SlotState::Retiring) {
+                tracing::info!(%pid, "Retiring a spare child");
+                excess -= 1;
+            }
+            if slot.state() == SlotState::Retiring {
+                unsafe { libc::kill(pid, RETIRE_SIGNAL) };
+            }
+        }
+    }
+
+    fn spawn_child(
+        &self,
+        children: &ChildProcesses,
+        scoreboard: &Scoreboard,
+        running: &mut HashMap<pid_t, PreforkChild>,
+        lock: Option<&AcceptLock>,
+    ) -> io::Result<pid_t> {
+        let index: usize = scoreboard
+            .free_slot()
+            .ok_or_else(|| io::Error::other("No free scoreboard slot"))?;
+        let (limiter, remote): (Option<LimiterChannel>, Option<RemoteLimiter>) = match &self.server.limiter {
+            Some(limiter) => LimiterChannel::new(limiter, self.server.shutdown.clone())
+                .map(|(channel, remote)| (Some(channel), Some(remote)))?,
+            None => (None, None),
+        };
+        let slot: &Slot = scoreboard.slot(index);
+        // Counted as idle right away, so that the next check does not fork more children than needed
+        slot.assign();
+
+        match unsafe { libc::fork() } {
+            0 => {
+                // Only the parent answers on this end
+                drop(limiter);
+                if let Some(affinity) = &self.cpu_affinity {
+                    if let Err(e) = affinity.pin_current_thread(index) {
+                        tracing::warn!("Failed to pin child to CPU {}: {}", affinity.cpu(index), e);
+                    }
+                }
+                let code: c_int = match self.run_child_process(slot, lock, remote.as_ref()) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        tracing::error!("Child process failed: {}", e);
+                        1
+                    }
+                };
+                unsafe { libc::_exit(code) };
+            }
+            -1 => {
+                slot.release();
+                Err(io::Error::last_os_error())
+            }
+            pid => {
+                children.insert(pid);
+                running.insert(pid, PreforkChild { slot: index, limiter });
+                tracing::info!(%pid, "Forked child process");
+                Ok(pid)
+            }
+        }
+    }
+
+    fn reap_children(
+        &self,
+        children: &ChildProcesses,
+        scoreboard: &Scoreboard,
+        running: &mut HashMap<pid_t, PreforkChild>,
+        backoff: &mut RespawnBackoff,
+    ) {
+        while let Ok(Some((pid, status))) = wait_child(true) {
+            let Some(lifetime) = children.remove(pid) else {
+                continue;
+            };
+            // Dropping the channel releases the permits the child still held
+            let Some(slot) = running.remove(&pid).map(|child| scoreboard.slot(child.slot)) else {
+                continue;
+            };
+            let retired: bool = slot.state() == SlotState::Retiring;
+            slot.release();
+
+            if retired {
+                tracing::info!(%pid, status = %describe_status(status), ?lifetime, "Child retired");
+            } else if lifetime < MIN_CHILD_LIFETIME {
+                backoff.record_failure();
+                tracing::warn!(
+                    %pid,
+                    status = %describe_status(status),
+                    ?lifetime,
+                    delay = ?backoff.remaining(),
+                    "Child exited shortly after start. Delaying respawn"
+                );
+            } else {
+                backoff.reset();
+                tracing::warn!(%pid, status = %describe_status(status), ?lifetime, "Child exited. Respawning");
+            }
+        }
     }
 
     #[instrument(name = "child", skip_all, fields(pid = unsafe { libc::getpid() }))]
-    fn run_child_process(&self) -> io::Result<()> {
-        // Not the most graceful shutdown
+    fn run_child_process(
+        &self,
+        slot: &Slot,
+        lock: Option<&AcceptLock>,
+        limiter: Option<&RemoteLimiter>,
+    ) -> io::Result<()> {
+        // If the parent dies, shut down as if it had been asked to
         if unsafe { libc::prctl(PR_SET_PDEATHSIG, SIGTERM) } != 0 {
             tracing::error!("Failed to set PR_SET_PDEATHSIG: {}", io::Error::last_os_error());
             unsafe { libc::_exit(1) };
         }
+        // The retire signal only needs to interrupt the wait for connections
+        install_signal_handler(RETIRE_SIGNAL, handle_wakeup)?;
+
+        let lock: Option<AcceptLock> = lock.map(AcceptLock::for_child).transpose()?;
+        let own_listener: Option<Listener> = match self.accept_strategy {
+            AcceptStrategy::ReusePort => Some(Listener::Tcp(accept::bind_reuseport(self.server.local_addr()?)?)),
+            _ => None,
+        };
+        // The shared listener is part of the `SO_REUSEPORT` group too, so the children take
+        // turns accepting the connections the kernel assigns to it
+        let listeners: Vec<&Listener> = own_listener.iter().chain([&self.server.listener]).collect();
+        let retiring = || slot.state() == SlotState::Retiring;
+        let limiter: Option<&dyn Admit> = limiter.map(|limiter| limiter as &dyn Admit);
+
+        // The watchdog only follows the supervisor, which notices children that stop serving
+        self.server
+            .run_accept_loop_until(&listeners, lock.as_ref(), limiter, None, retiring, |stream, admission| {
+                let _permit: Option<ConnectionPermit> = match admission {
+                    Ok(permit) => permit,
+                    Err(rejection) => return turn_away(&self.service, stream, rejection),
+                };
+                let busy: bool = slot.transition(SlotState::Idle, SlotState::Busy);
+                let _ = self.service.handle_connection(stream);
+
+                let served: u64 = slot.count_request();
+                if self.max_requests_per_child.is_some_and(|max| served >= max) {
+                    tracing::info!(served, "Reached the maximum number of requests. Retiring");
+                    slot.retire();
+                } else if busy {
+                    slot.transition(SlotState::Busy, SlotState::Idle);
+                }
+            })
+    }
+}
+
+/// Event-driven TCP server that multiplexes connections over `epoll` on non-blocking sockets.
+///
+/// Each reactor is a thread with its own `epoll` instance that accepts connections from the
+/// shared listener (registered with `EPOLLEXCLUSIVE` to avoid thundering-herd wakeups) and
+/// drives them until they are closed. A single reactor runs on the calling thread.
+pub struct EpollTcpServer<S: NonBlockingService> {
+    service: Arc<Layered<S>>,
+    server: BaseTcpServer,
+    num_reactors: usize,
+}
+
+impl<S: NonBlockingService> EpollTcpServer<S> {
+    pub fn new(addr: impl ToListener, service: S, num_reactors: usize) -> io::Result<Self> {
+        assert!(num_reactors > 0, "Number of reactors must be greater than 0");
+
+        Ok(Self {
+            service: Arc::new(layered(service)),
+            server: BaseTcpServer::bind(addr)?,
+            num_reactors,
+        })
+    }
+
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
+
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter as they are accepted. Rejected connections
+    /// are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
+    pub fn serve(&self) -> io::Result<()> {
+        let _notifier: Option<NotifierThread> = self.server.init()?;
+
+        let reactors: Vec<thread::JoinHandle<io::Result<()>>> = (1..self.num_reactors)
+            .map(|id| {
+                let reactor: Reactor<Layered<S>> = self.new_reactor(id)?;
+                Ok(thread::spawn(move || reactor.run()))
+            })
+            .collect::<io::Result<_>>()?;
+
+        self.new_reactor(0)?.run()?;
+
+        reactors
+            .into_iter()
+            .try_for_each(|reactor| reactor.join().expect("Reactor thread panicked"))
+    }
+
+    fn new_reactor(&self, id: usize) -> io::Result<Reactor<Layered<S>>> {
+        Reactor::new(
+            id,
+            self.server.listener.try_clone()?,
+            Arc::clone(&self.service),
+            self.server.shutdown.clone(),
+            self.server.limiter.clone(),
+            self.server.heartbeat(),
+        )
+    }
+}
+
+/// Async TCP server that runs each connection as a task on the tokio runtime.
+///
+/// The listener is bound on construction, so the address is known before the runtime starts;
+/// [`AsyncTcpServer::serve`] must be called from within a tokio runtime.
+#[cfg(feature = "tokio")]
+pub struct AsyncTcpServer<S: AsyncService> {
+    service: Arc<Layered<S>>,
+    server: BaseTcpServer,
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncService> AsyncTcpServer<S> {
+    pub fn new(addr: impl ToListener, service: S) -> io::Result<Self> {
+        Ok(Self {
+            service: Arc::new(layered(service)),
+            server: BaseTcpServer::bind(addr)?,
+        })
+    }
+
+    /// Returns the TCP address the server is listening on. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.server.local_addr()
+    }
+
+    /// Replaces the shutdown handle, e.g. with one that listens for signals.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server.shutdown = shutdown;
+        self
+    }
+
+    /// Returns a handle that can be used to shut the server down.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown.clone()
+    }
 
-        self.server.run_accept_loop(|stream| {
-            if let Err(e) = self.service.handle_connection(stream) {
-                tracing::error!("Service error: failed to handle connection: {}", e);
+    /// Enables hot restart, which hands the listener over to a new server process.
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.server.restart = Some(restart);
+        self
+    }
+
+    /// Reports the state of the server to systemd (see [`Notifier`]).
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.server.notifier = Some(notifier);
+        self
+    }
+
+    /// Admits connections through the limiter as they are accepted. Rejected connections
+    /// are closed.
+    pub fn with_limiter(mut self, limiter: Arc<ConnectionLimiter>) -> Self {
+        self.server.limiter = Some(limiter);
+        self
+    }
+
+    /// Serves connections until shutdown is requested, then waits for the running tasks
+    /// to finish within the grace period and aborts the rest.
+    #[instrument(name = "server", skip_all)]
+    pub async fn serve(&self) -> io::Result<()> {
+        let _notifier: Option<NotifierThread> = self.server.init()?;
+        let listener: AsyncListener = AsyncListener::from_std(self.server.listener.try_clone()?)?;
+        let shutdown = tokio::io::unix::AsyncFd::new(self.server.shutdown.clone())?;
+        let mut tasks: tokio::task::JoinSet<()> = tokio::task::JoinSet::new();
+        let heartbeat: Option<Heartbeat> = self.server.heartbeat();
+        let mut beats: Option<tokio::time::Interval> =
+            heartbeat.as_ref().map(|heartbeat| tokio::time::interval(heartbeat.interval()));
+
+        loop {
+            let accepted = std::future::poll_fn(|cx| {
+                // Beat while idle too, from the same task that accepts the connections
+                if let (Some(heartbeat), Some(beats)) = (&heartbeat, &mut beats) {
+                    while beats.poll_tick(cx).is_ready() {
+                        heartbeat.beat();
+                    }
+                }
+                match shutdown.poll_read_ready(cx) {
+                    Poll::Ready(_) => Poll::Ready(None),
+                    Poll::Pending => listener.poll_accept(cx).map(Some),
+                }
+            })
+            .await;
+
+            match accepted {
+                Some(Ok((stream, peer))) => {
+                    tracing::info!(peer_addr = %peer, "Accepted connection");
+                    let limiter: Option<&dyn Admit> = self.server.limiter.as_ref().map(|l| l as &dyn Admit);
+                    let Ok(permit) = admit(limiter, &peer) else {
+                        continue;
+                    };
+                    let service: Arc<Layered<S>> = Arc::clone(&self.service);
+
+                    match stream {
+                        AsyncSocketStream::Tcp(stream) => tasks.spawn(async move {
+                            let _permit: Option<ConnectionPermit> = permit;
+                            let _ = service.handle_connection(stream).await;
+                        }),
+                        AsyncSocketStream::Unix(stream) => tasks.spawn(async move {
+                            let _permit: Option<ConnectionPermit> = permit;
+                            let _ = service.handle_connection(stream).await;
+                        }),
+                    };
+                }
+                Some(Err(e)) => tracing::error!("Failed to establish a connection: {}", e),
+                None => break,
             }
+            // Forget the tasks that have already finished
+            while tasks.try_join_next().is_some() {}
+        }
+        tracing::info!("Shutdown requested. Stopped accepting connections");
+
+        let drain = async { while tasks.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.server.shutdown.grace_period(), drain)
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                remaining = tasks.len(),
+                "Grace period expired. Aborting connections"
+            );
+            tasks.shutdown().await;
+        }
+
+        Ok(())
+    }
+}
+
+/// A [`Listener`] registered with the tokio runtime
+#[cfg(feature = "tokio")]
+enum AsyncListener {
+    Tcp(tokio::net::TcpListener),
+    Unix(tokio::net::UnixListener),
+}
+
+/// A connection accepted by an [`AsyncListener`]
+#[cfg(feature = "tokio")]
+enum AsyncSocketStream {
+    Tcp(tokio::net::TcpStream),
+    Unix(tokio::net::UnixStream),
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncListener {
+    fn from_std(listener: Listener) -> io::Result<Self> {
+        match listener {
+            Listener::Tcp(listener) => tokio::net::TcpListener::from_std(listener).map(AsyncListener::Tcp),
+            Listener::Unix(listener) => tokio::net::UnixListener::from_std(listener).map(AsyncListener::Unix),
+        }
+    }
+
+    fn poll_accept(
+        &self,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<io::Result<(AsyncSocketStream, PeerAddr)>> {
+        match self {
+            AsyncListener::Tcp(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, addr)| (AsyncSocketStream::Tcp(stream), PeerAddr::Inet(addr))),
+            AsyncListener::Unix(listener) => listener.poll_accept(cx).map_ok(|(stream, addr)| {
+                let addr: std::os::unix::net::SocketAddr = addr.into();
+                let peer: PeerAddr = UnixAddr::from_std(&addr).map_or(PeerAddr::Unnamed, PeerAddr::Unix);
+                (AsyncSocketStream::Unix(stream), peer)
+            }),
+        }
+    }
+}
+
+/// Tokens used for the listener and the shutdown eventfd in `epoll` events.
+/// Connections use their file descriptor.
+const LISTENER_TOKEN: u64 = u64::MAX;
+const SHUTDOWN_TOKEN: u64 = u64::MAX - 1;
+const MAX_EVENTS: usize = 1024;
+
+/// A connection driven by a [`Reactor`]
+struct Connection<T> {
+    stream: SocketStream,
+    state: T,
+    interest: Interest,
+    /// Last deadline reported by the service, also queued in [`Reactor::timers`]
+    deadline: Option<Instant>,
+    /// Released when the connection is closed
+    _permit: Option<ConnectionPermit>,
+}
+
+/// Event loop that owns an `epoll` instance and the connections registered in it.
+struct Reactor<S: NonBlockingService> {
+    id: usize,
+    epoll: Epoll,
+    listener: Listener,
+    service: Arc<S>,
+    shutdown: Shutdown,
+    limiter: Option<Arc<ConnectionLimiter>>,
+    /// Beaten on every iteration of the event loop
+    heartbeat: Option<Heartbeat>,
+    connections: HashMap<RawFd, Connection<S::State>>,
+    /// Connection deadlines, earliest first. Entries for connections that have been closed
+    /// or have moved on to another deadline are skipped when they come up.
+    timers: BinaryHeap<Reverse<(Instant, RawFd)>>,
+    /// Set once shutdown has been requested
+    deadline: Option<Instant>,
+}
+
+impl<S: NonBlockingService> Reactor<S> {
+    fn new(
+        id: usize,
+        listener: Listener,
+        service: Arc<S>,
+        shutdown: Shutdown,
+        limiter: Option<Arc<ConnectionLimiter>>,
+        heartbeat: Option<Heartbeat>,
+    ) -> io::Result<Self> {
+        let epoll: Epoll = Epoll::new()?;
+        epoll.ctl(
+            EPOLL_CTL_ADD,
+            listener.as_raw_fd(),
+            EPOLLIN | EPOLLEXCLUSIVE,
+            LISTENER_TOKEN,
+        )?;
+        epoll.ctl(EPOLL_CTL_ADD, shutdown.as_raw_fd(), EPOLLIN, SHUTDOWN_TOKEN)?;
+
+        Ok(Self {
+            id,
+            epoll,
+            listener,
+            service,
+            shutdown,
+            limiter,
+            heartbeat,
+            connections: HashMap::new(),
+            timers: BinaryHeap::new(),
+            deadline: None,
         })
     }
+
+    #[instrument(name = "reactor", skip_all, fields(id = self.id))]
+    fn run(mut self) -> io::Result<()> {
+        let mut events: Vec<epoll_event> = vec![epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+        tracing::info!("Reactor started");
+
+        while self.deadline.is_none() || !self.connections.is_empty() {
+            if let Some(heartbeat) = &self.heartbeat {
+                heartbeat.beat();
+            }
+            let timeout: Option<Duration> = match self.deadline {
+                Some(deadline) if Instant::now() >= deadline => {
+                    tracing::warn!(
+                        remaining = self.connections.len(),
+                        "Grace period expired. Aborting connections"
+                    );
+                    break;
+                }
+                deadline => {
+                    let next_timer: Option<Instant> = self.timers.peek().map(|Reverse((at, _))| *at);
+                    let timeout: Option<Duration> = deadline
+                        .into_iter()
+                        .chain(next_timer)
+                        .min()
+                        .map(|d| d.saturating_duration_since(Instant::now()));
+                    timeout.into_iter().chain(self.heartbeat.as_ref().map(Heartbeat::interval)).min()
+                }
+            };
+            let num_events: usize = match self.epoll.wait(&mut events, timeout) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            for event in &events[..num_events] {
+                match event.u64 {
+                    LISTENER_TOKEN => self.accept_connections(),
+                    SHUTDOWN_TOKEN => self.stop_accepting()?,
+                    fd => self.resume_connection(fd as RawFd),
+                }
+            }
+            self.expire_connections();
+        }
+        tracing::info!("Reactor stopped");
+
+        Ok(())
+    }
+
+    /// Removes the listener and the shutdown eventfd from `epoll` and starts the grace period.
+    /// The remaining connections are driven until they are closed or the deadline passes.
+    fn stop_accepting(&mut self) -> io::Result<()> {
+        self.epoll.ctl(EPOLL_CTL_DEL, self.listener.as_raw_fd(), 0, 0)?;
+        self.epoll.ctl(EPOLL_CTL_DEL, self.shutdown.as_raw_fd(), 0, 0)?;
+        self.deadline = Some(self.shutdown.deadline());
+
+        tracing::info!(
+            remaining = self.connections.len(),
+            "Shutdown requested. Stopped accepting connections"
+        );
+
+        Ok(())
+    }
+
+    fn accept_connections(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    tracing::info!(peer_addr = %peer, "Accepted connection");
+
+                    let limiter: Option<&dyn Admit> = self.limiter.as_ref().map(|l| l as &dyn Admit);
+                    // A rejection response could block the reactor, so rejected connections are just closed
+                    let Ok(permit) = admit(limiter, &peer) else {
+                        continue;
+                    };
+                    if let Err(e) = self.register(stream, permit) {
+                        tracing::error!("Failed to register a connection: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    tracing::error!("Failed to establish a connection: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn register(&mut self, stream: SocketStream, permit: Option<ConnectionPermit>) -> io::Result<()> {
+        stream.set_nonblocking(true)?;
+
+        let fd: RawFd = stream.as_raw_fd();
+        let state: S::State = self.service.init(&stream);
+        // Start with read interest; the first resume decides what the connection actually waits for
+        self.epoll.ctl(EPOLL_CTL_ADD, fd, EPOLLIN, fd as u64)?;
+        self.connections.insert(
+            fd,
+            Connection {
+                stream,
+                state,
+                interest: Interest::Read,
+                deadline: None,
+                _permit: permit,
+            },
+        );
+        self.resume_connection(fd);
+
+        Ok(())
+    }
+
+    fn resume_connection(&mut self, fd: RawFd) {
+        let Some(conn) = self.connections.get_mut(&fd) else {
+            return;
+        };
+
+        let interest: Interest = self
+            .service
+            .resume(&mut conn.state, &mut conn.stream)
+            .unwrap_or(Interest::Close);
+
+        let result: io::Result<()> = match interest {
+            Interest::Close => return self.deregister(fd),
+            interest if interest == conn.interest => Ok(()),
+            Interest::Read => self.epoll.ctl(EPOLL_CTL_MOD, fd, EPOLLIN, fd as u64),
+            Interest::Write => self.epoll.ctl(EPOLL_CTL_MOD, fd, EPOLLOUT, fd as u64),
+        };
+        conn.interest = interest;
+
+        let deadline: Option<Instant> = self.service.deadline(&conn.state);
+        if deadline != conn.deadline {
+            conn.deadline = deadline;
+            self.timers.extend(deadline.map(|at| Reverse((at, fd))));
+        }
+
+        if let Err(e) = result {
+            tracing::error!("Failed to update the interest of a connection: {}", e);
+            self.deregister(fd);
+        }
+    }
+
+    /// Resumes the connections whose deadline has passed, so that the service can fail them,
+    /// and closes those that are still open afterwards.
+    fn expire_connections(&mut self) {
+        let now: Instant = Instant::now();
+
+        while let Some(&Reverse((at, fd))) = self.timers.peek() {
+            if at > now {
+                break;
+            }
+            self.timers.pop();
+
+            if self
+                .connections
+                .get(&fd)
+                .is_some_and(|conn| conn.deadline == Some(at))
+            {
+                self.resume_connection(fd);
+
+                if self
+                    .connections
+                    .get(&fd)
+                    .is_some_and(|conn| conn.deadline.is_some_and(|d| d <= now))
+                {
+                    tracing::warn!("Connection deadline passed. Closing connection");
+                    self.deregister(fd);
+                }
+            }
+        }
+    }
+
+    fn deregister(&mut self, fd: RawFd) {
+        if let Err(e) = self.epoll.ctl(EPOLL_CTL_DEL, fd, 0, 0) {
+            tracing::warn!("Failed to remove a connection from epoll: {}", e);
+        }
+        self.connections.remove(&fd);
+    }
+}
+
+/// Owned `epoll` instance
+struct Epoll {
+    fd: OwnedFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Self> {
+        match unsafe { libc::epoll_create1(EPOLL_CLOEXEC) } {
+            -1 => Err(io::Error::last_os_error()),
+            // SAFETY: `epoll_create1` returned a new file descriptor that nothing else owns.
+            fd => Ok(Self {
+                fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            }),
+        }
+    }
+
+    fn ctl(&self, op: c_int, fd: RawFd, events: c_int, token: u64) -> io::Result<()> {
+        let mut event = epoll_event {
+            events: events as u32,
+            u64: token,
+        };
+
+        match unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for events until the timeout expires (`None` waits forever).
+    fn wait(&self, events: &mut [epoll_event], timeout: Option<Duration>) -> io::Result<usize> {
+        // Round up, so that a sub-millisecond timeout does not turn into a busy loop
+        let timeout_ms: c_int = timeout.map_or(-1, |t| {
+            t.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int
+        });
+
+        match unsafe {
+            libc::epoll_wait(
+                self.fd.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as c_int,
+                timeout_ms,
+            )
+        } {
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(n as usize),
+        }
+    }
+}
+
+/// Child processes forked by a server with the time they were forked at, tracked so that
+/// they can be killed if they outlive the grace period.
+#[derive(Default)]
+struct ChildProcesses {
+    pids: Mutex<HashMap<pid_t, Instant>>,
+}
+
+impl ChildProcesses {
+    fn insert(&self, pid: pid_t) {
+        self.pids.lock().unwrap().insert(pid, Instant::now());
+    }
+
+    /// Removes the child and returns how long it has been running, if it was tracked.
+    fn remove(&self, pid: pid_t) -> Option<Duration> {
+        self.pids
+            .lock()
+            .unwrap()
+            .remove(&pid)
+            .map(|forked_at| forked_at.elapsed())
+    }
+
+    fn len(&self) -> usize {
+        self.pids.lock().unwrap().len()
+    }
+
+    /// Reaps children until all of them have exited. Children still running
+    /// after the deadline are killed with `SIGKILL`.
+    fn drain(&self, deadline: Instant) {
+        let mut killed: bool = false;
+
+        while self.len() > 0 {
+            match wait_child(true) {
+                Ok(Some((pid, status))) => {
+                    tracing::info!(%pid, status = %describe_status(status), "Child exited");
+                    self.remove(pid);
+                }
+                Ok(None) if killed || Instant::now() < deadline => thread::sleep(REAP_INTERVAL),
+                Ok(None) => {
+                    tracing::warn!(remaining = self.len(), "Grace period expired. Killing children");
+
+                    self.pids.lock().unwrap().keys().for_each(|&pid| {
+                        unsafe { libc::kill(pid, SIGKILL) };
+                    });
+                    killed = true;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to wait for a child: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Descriptor of the eventfd written by the `SIGCHLD` handler (-1 if no handler is installed)
+static CHILD_EXIT_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_child_exit(_: c_int) {
+    let fd: RawFd = CHILD_EXIT_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let value: u64 = 1;
+        // SAFETY: `write` is async-signal-safe and `value` outlives the call.
+        unsafe { libc::write(fd, &value as *const u64 as *const c_void, 8) };
+    }
+}
+
+extern "C" fn handle_wakeup(_: c_int) {}
+
+/// Installs a signal handler. Interrupted system calls are restarted, except for the ones
+/// that never are, such as `poll`.
+fn install_signal_handler(signal: c_int, handler: extern "C" fn(c_int)) -> io::Result<()> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+
+    match unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// Eventfd that becomes readable when a child process exits, written by the `SIGCHLD` handler.
+struct ChildExits {
+    eventfd: OwnedFd,
+}
+
+impl ChildExits {
+    fn install() -> io::Result<Self> {
+        let eventfd: OwnedFd = match unsafe { libc::eventfd(0, EFD_CLOEXEC) } {
+            -1 => Err(io::Error::last_os_error())?,
+            // SAFETY: `eventfd` returned a new file descriptor that nothing else owns.
+            fd => unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        CHILD_EXIT_FD.store(eventfd.as_raw_fd(), Ordering::Relaxed);
+        install_signal_handler(SIGCHLD, handle_child_exit)?;
+
+        Ok(Self { eventfd })
+    }
+
+    /// Resets the eventfd. Must be called before reaping, so that exits that happen
+    /// while reaping make it readable again.
+    fn clear(&self) {
+        let mut value: u64 = 0;
+        unsafe { libc::read(self.as_raw_fd(), &mut value as *mut u64 as *mut c_void, 8) };
+    }
+
+    fn as_raw_fd(&self) -> RawFd {
+        self.eventfd.as_raw_fd()
+    }
+}
+
+/// Delays respawning children that keep crashing shortly after they start.
+struct RespawnBackoff {
+    failures: u32,
+    next_spawn: Instant,
+}
+
+impl Default for RespawnBackoff {
+    fn default() -> Self {
+        Self {
+            failures: 0,
+            next_spawn: Instant::now(),
+        }
+    }
+}
+
+impl RespawnBackoff {
+    fn record_failure(&mut self) {
+        let delay: Duration = RESPAWN_BASE_DELAY
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(RESPAWN_MAX_DELAY);
+
+        self.failures = self.failures.saturating_add(1);
+        self.next_spawn = Instant::now() + delay;
+    }
+
+    fn reset(&mut self) {
+        self.failures = 0;
+        self.next_spawn = Instant::now();
+    }
+
+    fn is_ready(&self) -> bool {
+        Instant::now() >= self.next_spawn
+    }
+
+    fn remaining(&self) -> Duration {
+        self.next_spawn.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Describes a `waitpid` status, e.g. "exited with code 1" or "killed by signal 11".
+fn describe_status(status: c_int) -> String {
+    if libc::WIFEXITED(status) {
+        format!("exited with code {}", libc::WEXITSTATUS(status))
+    } else if libc::WIFSIGNALED(status) {
+        let core_dumped: &str = if libc::WCOREDUMP(status) {
+            " (core dumped)"
+        } else {
+            ""
+        };
+        format!("killed by signal {}{}", libc::WTERMSIG(status), core_dumped)
+    } else {
+        format!("unknown status {}", status)
+    }
 }
 
 fn wait_child(non_blocking: bool) -> io::Result<Option<(pid_t, c_int)>> {
@@ -1,7 +1,18 @@
 #![cfg(target_family = "unix")]
 pub mod core;
+pub mod accept;
 pub mod service;
+pub mod shutdown;
+pub mod restart;
+pub mod systemd;
 pub mod thread_pool;
+pub mod affinity;
+pub mod limit;
+pub mod stream;
+pub mod listener;
+#[cfg(feature = "tls")]
+pub mod tls;
+mod scoreboard;
 pub mod proto {
     include!(concat!(env!("GENERATED_PROTO_DIR"), "/file_transfer.rs"));
 
@@ -0,0 +1,472 @@
+use std::{fmt, io};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, Ipv6Addr};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::os::unix::net::UnixDatagram;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::shutdown::{self, Shutdown};
+
+/// Minimum number of tracked source IPs at which idle entries are dropped
+const PRUNE_THRESHOLD: usize = 1024;
+/// Requests of a prefork child to the limiter of the parent, followed by the IP address
+const ADMIT: u8 = 0;
+const RELEASE: u8 = 1;
+/// Reply of the parent to an admitted connection; rejections are sent as `1 + Rejection as u8`
+const ADMITTED: u8 = 0;
+
+/// A network in CIDR notation, e.g. `10.0.0.0/8` or `fd00::/8`.
+/// A bare address is a network of that address alone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Returns `true` if `ip` is in this network. IPv4-mapped IPv6 addresses match IPv4 networks.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask: u32 = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask: u128 = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = s.split_once('/').map_or((s, None), |(a, p)| (a, Some(p)));
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("expected a network such as `10.0.0.0/8`, got {:?}", s))?;
+        let max_len: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len: u8 = match prefix_len {
+            Some(len) => len
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Token bucket for new connections: `per_second` tokens are added every second,
+/// up to `burst`, and every connection takes one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Limits enforced by a [`ConnectionLimiter`]. `None` and empty lists mean no limit.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Limits {
+    /// Connections handled at the same time
+    pub max_connections: Option<usize>,
+    /// Connections handled at the same time for a single source IP
+    pub max_connections_per_ip: Option<usize>,
+    /// New connections per source IP
+    pub rate: Option<RateLimit>,
+    /// If not empty, only connections from these networks are admitted
+    pub allow: Vec<Cidr>,
+    /// Connections from these networks are never admitted, even if allowed
+    pub deny: Vec<Cidr>,
+}
+
+/// Why a connection was turned away
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Rejection {
+    /// The source IP is denied or not allowed
+    Denied,
+    /// The global connection limit is reached
+    TooManyConnections,
+    /// The connection limit for the source IP is reached
+    TooManyConnectionsFromIp,
+    /// The source IP opens connections too fast
+    RateLimited,
+}
+
+impl Rejection {
+    /// Indexed by the discriminant
+    const ALL: [Rejection; 4] = [
+        Rejection::Denied,
+        Rejection::TooManyConnections,
+        Rejection::TooManyConnectionsFromIp,
+        Rejection::RateLimited,
+    ];
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Denied => "address denied",
+            Self::TooManyConnections => "too many connections",
+            Self::TooManyConnectionsFromIp => "too many connections from address",
+            Self::RateLimited => "connection rate exceeded",
+        })
+    }
+}
+
+/// Numbers of admitted and rejected connections since the limiter was created
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct LimitStats {
+    pub admitted: u64,
+    pub denied: u64,
+    pub too_many_connections: u64,
+    pub too_many_connections_from_ip: u64,
+    pub rate_limited: u64,
+}
+
+impl LimitStats {
+    /// Returns the number of rejected connections for all reasons together.
+    pub fn rejected(&self) -> u64 {
+        self.denied + self.too_many_connections + self.too_many_connections_from_ip + self.rate_limited
+    }
+}
+
+/// Per-source-IP state
+#[derive(Debug)]
+struct Peer {
+    connections: usize,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+/// Decides whether new connections are admitted and keeps track of the admitted ones.
+///
+/// The servers admit connections as they accept them, before they are queued or forked
+/// (see `with_limiter` on each server model). Prefork children ask the limiter of the parent
+/// process, so the limits hold across all workers and child processes.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    limits: Limits,
+    connections: AtomicUsize,
+    peers: Mutex<HashMap<IpAddr, Peer>>,
+    /// Number of tracked source IPs at which idle entries are dropped next. Grows with the
+    /// number of active ones, so that pruning does not run on every connection.
+    prune_at: AtomicUsize,
+    admitted: AtomicU64,
+    /// Indexed by [`Rejection`]
+    rejected: [AtomicU64; 4],
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: Limits) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            connections: AtomicUsize::new(0),
+            peers: Mutex::new(HashMap::new()),
+            prune_at: AtomicUsize::new(PRUNE_THRESHOLD),
+            admitted: AtomicU64::new(0),
+            rejected: Default::default(),
+        })
+    }
+
+    /// Admits a connection from `ip` or tells why not. The connection counts against the
+    /// limits until the returned permit is dropped.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        let ip: IpAddr = ip.to_canonical();
+        let result: Result<ConnectionPermit, Rejection> = self.try_admit(ip);
+
+        match &result {
+            Ok(_) => {
+                self.admitted.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(rejection) => {
+                self.rejected[*rejection as usize].fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(peer = %ip, reason = %rejection, "Rejected connection");
+            }
+        }
+        result
+    }
+
+    /// Returns the number of connections currently admitted.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> LimitStats {
+        let rejected = |rejection: Rejection| self.rejected[rejection as usize].load(Ordering::Relaxed);
+
+        LimitStats {
+            admitted: self.admitted.load(Ordering::Relaxed),
+            denied: rejected(Rejection::Denied),
+            too_many_connections: rejected(Rejection::TooManyConnections),
+            too_many_connections_from_ip: rejected(Rejection::TooManyConnectionsFromIp),
+            rate_limited: rejected(Rejection::RateLimited),
+        }
+    }
+
+    fn try_admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        let limits: &Limits = &self.limits;
+        let allowed: bool = limits.allow.is_empty() || limits.allow.iter().any(|net| net.contains(ip));
+        if !allowed || limits.deny.iter().any(|net| net.contains(ip)) {
+            return Err(Rejection::Denied);
+        }
+
+        let mut peers = self.peers.lock().unwrap();
+        let now: Instant = Instant::now();
+        if peers.len() >= self.prune_at.load(Ordering::Relaxed) {
+            peers.retain(|_, peer| !self.is_idle(peer, now));
+            self.prune_at
+                .store(PRUNE_THRESHOLD.max(2 * peers.len()), Ordering::Relaxed);
+        }
+        let peer: &mut Peer = peers.entry(ip).or_insert_with(|| Peer {
+            connections: 0,
+            tokens: limits.rate.map_or(0.0, |rate| rate.burst as f64),
+            refilled_at: now,
+        });
+
+        if limits
+            .max_connections_per_ip
+            .is_some_and(|max| peer.connections >= max)
+        {
+            return Err(Rejection::TooManyConnectionsFromIp);
+        }
+        if let Some(rate) = limits.rate {
+            let elapsed: Duration = now.duration_since(peer.refilled_at);
+            peer.tokens = (peer.tokens + elapsed.as_secs_f64() * rate.per_second).min(rate.burst as f64);
+            peer.refilled_at = now;
+            if peer.tokens < 1.0 {
+                return Err(Rejection::RateLimited);
+            }
+        }
+        // Checked last and under the lock, so that a rejected connection takes no token
+        // and admitted connections never overshoot the limit
+        if limits
+            .max_connections
+            .is_some_and(|max| self.connections.load(Ordering::Relaxed) >= max)
+        {
+            return Err(Rejection::TooManyConnections);
+        }
+
+        if limits.rate.is_some() {
+            peer.tokens -= 1.0;
+        }
+        peer.connections += 1;
+        self.connections.fetch_add(1, Ordering::Relaxed);
+
+        Ok(ConnectionPermit {
+            ip,
+            holder: PermitHolder::Local(Arc::clone(self)),
+        })
+    }
+
+    /// Returns `true` if forgetting the peer would not change any decision.
+    fn is_idle(&self, peer: &Peer, now: Instant) -> bool {
+        let refilled: bool = self.limits.rate.is_none_or(|rate| {
+            peer.tokens + now.duration_since(peer.refilled_at).as_secs_f64() * rate.per_second
+                >= rate.burst as f64
+        });
+
+        peer.connections == 0 && refilled
+    }
+
+    fn release(&self, ip: IpAddr) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&ip) {
+            peer.connections -= 1;
+        }
+    }
+}
+
+/// An admitted connection. Dropping it frees its place for other connections.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    ip: IpAddr,
+    holder: PermitHolder,
+}
+
+/// Where a [`ConnectionPermit`] is released
+#[derive(Debug)]
+enum PermitHolder {
+    Local(Arc<ConnectionLimiter>),
+    /// The limiter of the parent process, through a [`RemoteLimiter`]
+    Remote(Arc<UnixDatagram>),
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        match &self.holder {
+            PermitHolder::Local(limiter) => limiter.release(self.ip),
+            // If the parent is gone, there is nothing left to release
+            PermitHolder::Remote(socket) => {
+                let _ = socket.send(&encode_request(RELEASE, self.ip));
+            }
+        }
+    }
+}
+
+/// Admits connections on behalf of a server as it accepts them.
+pub(crate) trait Admit {
+    fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, Rejection>;
+}
+
+impl Admit for Arc<ConnectionLimiter> {
+    fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        ConnectionLimiter::admit(self, ip)
+    }
+}
+
+/// The parent's end of the channel over which a prefork child admits its connections through
+/// the [`ConnectionLimiter`] of the parent. Holds the permits of the child, so that they are
+/// released even if the child dies.
+#[derive(Debug)]
+pub(crate) struct LimiterChannel {
+    limiter: Arc<ConnectionLimiter>,
+    socket: UnixDatagram,
+    permits: Vec<ConnectionPermit>,
+}
+
+impl LimiterChannel {
+    /// Creates the channel before a child is forked. The [`RemoteLimiter`] goes to the child,
+    /// which gives up waiting for an answer once `shutdown` is triggered.
+    pub(crate) fn new(limiter: &Arc<ConnectionLimiter>, shutdown: Shutdown) -> io::Result<(Self, RemoteLimiter)> {
+        let (socket, remote) = UnixDatagram::pair()?;
+        socket.set_nonblocking(true)?;
+
+        Ok((
+            Self {
+                limiter: Arc::clone(limiter),
+                socket,
+                permits: Vec::new(),
+            },
+            RemoteLimiter {
+                socket: Arc::new(remote),
+                shutdown,
+            },
+        ))
+    }
+
+    /// Answers the pending requests of the child without blocking.
+    pub(crate) fn serve(&mut self) -> io::Result<()> {
+        let mut request: [u8; 17] = [0; 17];
+
+        loop {
+            let len: usize = match self.socket.recv(&mut request) {
+                Ok(len) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let Some((kind, ip)) = decode_request(&request[..len]) else {
+                tracing::warn!(len, "Ignoring a malformed limiter request");
+                continue;
+            };
+
+            match kind {
+                ADMIT => {
+                    let reply: u8 = match self.limiter.admit(ip) {
+                        Ok(permit) => {
+                            self.permits.push(permit);
+                            ADMITTED
+                        }
+                        Err(rejection) => 1 + rejection as u8,
+                    };
+                    self.socket.send(&[reply])?;
+                }
+                _ => {
+                    if let Some(index) = self.permits.iter().position(|permit| permit.ip == ip) {
+                        self.permits.swap_remove(index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for LimiterChannel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+/// The child's end of a [`LimiterChannel`]
+pub(crate) struct RemoteLimiter {
+    socket: Arc<UnixDatagram>,
+    shutdown: Shutdown,
+}
+
+impl RemoteLimiter {
+    fn request(&self, ip: IpAddr) -> io::Result<Option<Result<ConnectionPermit, Rejection>>> {
+        self.socket.send(&encode_request(ADMIT, ip))?;
+
+        let fds: [RawFd; 2] = [self.socket.as_raw_fd(), self.shutdown.as_raw_fd()];
+        loop {
+            match shutdown::poll_readable(&fds, None) {
+                Ok(ready) if ready[0] => break,
+                // The parent stops answering once it shuts down
+                Ok(_) => return Ok(None),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut reply: [u8; 1] = [0];
+        self.socket.recv(&mut reply)?;
+        Ok(Some(match reply[0] {
+            ADMITTED => Ok(ConnectionPermit {
+                ip,
+                holder: PermitHolder::Remote(Arc::clone(&self.socket)),
+            }),
+            code => Err(Rejection::ALL
+                .get(code as usize - 1)
+                .copied()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown limiter reply"))?),
+        }))
+    }
+}
+
+impl Admit for RemoteLimiter {
+    /// Connections that cannot be admitted because the parent does not answer are rejected.
+    fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        match self.request(ip) {
+            Ok(Some(result)) => result,
+            Ok(None) => Err(Rejection::TooManyConnections),
+            Err(e) => {
+                tracing::error!("Failed to ask the parent process to admit a connection: {}", e);
+                Err(Rejection::TooManyConnections)
+            }
+        }
+    }
+}
+
+/// Encodes a request of a [`RemoteLimiter`]: the kind, followed by the IP address as IPv6.
+fn encode_request(kind: u8, ip: IpAddr) -> [u8; 17] {
+    let ip: Ipv6Addr = match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+
+    let mut request: [u8; 17] = [kind; 17];
+    request[1..].copy_from_slice(&ip.octets());
+    request
+}
+
+fn decode_request(request: &[u8]) -> Option<(u8, IpAddr)> {
+    let (&kind, ip) = request.split_first()?;
+    let ip: [u8; 16] = ip.try_into().ok()?;
+
+    Some((kind, Ipv6Addr::from(ip).to_canonical()))
+}
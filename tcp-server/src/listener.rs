@@ -0,0 +1,342 @@
+use std::{fmt, fs, io, mem};
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
+use std::time::Duration;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::{self, UnixListener, UnixStream};
+use std::os::linux::net::SocketAddrExt as _;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd as _, IntoRawFd as _, OwnedFd, RawFd};
+use libc::{c_int, c_void, socklen_t, AF_INET, AF_INET6, AF_UNIX, SOL_SOCKET, SO_DOMAIN};
+
+use crate::stream::{self, PeerAddr, PeerCred, Stream};
+
+/// The address of a Unix domain socket: a file system path or a name in the Linux abstract
+/// namespace. Abstract names are written with a leading `@`, e.g. `@tcp-server`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnixAddr {
+    Path(PathBuf),
+    /// A name without a file behind it; it disappears when the last socket bound to it is closed
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        UnixAddr::Path(path.into())
+    }
+
+    pub fn abstract_name(name: impl AsRef<[u8]>) -> Self {
+        UnixAddr::Abstract(name.as_ref().to_vec())
+    }
+
+    /// Returns `None` for unnamed sockets, such as most client sockets.
+    pub(crate) fn from_std(addr: &net::SocketAddr) -> Option<Self> {
+        match (addr.as_pathname(), addr.as_abstract_name()) {
+            (Some(path), _) => Some(UnixAddr::path(path)),
+            (None, Some(name)) => Some(UnixAddr::abstract_name(name)),
+            (None, None) => None,
+        }
+    }
+
+    pub(crate) fn to_std(&self) -> io::Result<net::SocketAddr> {
+        match self {
+            UnixAddr::Path(path) => net::SocketAddr::from_pathname(path),
+            UnixAddr::Abstract(name) => net::SocketAddr::from_abstract_name(name),
+        }
+    }
+}
+
+impl FromStr for UnixAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('@') {
+            _ if s.is_empty() || s == "@" => Err("expected a path or `@NAME`".to_string()),
+            Some(name) => Ok(UnixAddr::abstract_name(name)),
+            None => Ok(UnixAddr::path(s)),
+        }
+    }
+}
+
+impl fmt::Display for UnixAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixAddr::Path(path) => write!(f, "{}", path.display()),
+            UnixAddr::Abstract(name) => write!(f, "@{}", String::from_utf8_lossy(name)),
+        }
+    }
+}
+
+/// Addresses a server can listen on: anything that resolves to TCP socket addresses,
+/// a [`UnixAddr`], a [`ListenAddr`] or a bound [`Listener`].
+pub trait ToListener {
+    fn bind(self) -> io::Result<Listener>;
+}
+
+impl<A: ToSocketAddrs> ToListener for A {
+    fn bind(self) -> io::Result<Listener> {
+        TcpListener::bind(self).map(Listener::Tcp)
+    }
+}
+
+/// A stale socket file left behind by a server that is gone is replaced. A path that another
+/// server still listens on is not, so binding to it fails with `AddrInUse`.
+impl ToListener for UnixAddr {
+    fn bind(self) -> io::Result<Listener> {
+        let addr: net::SocketAddr = self.to_std()?;
+
+        match UnixListener::bind_addr(&addr) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => match &self {
+                UnixAddr::Path(path) if is_stale(path, &addr) => {
+                    tracing::warn!(path = %path.display(), "Removing a stale socket file");
+                    fs::remove_file(path)?;
+                    UnixListener::bind_addr(&addr).map(Listener::Unix)
+                }
+                _ => Err(e),
+            },
+            result => result.map(Listener::Unix),
+        }
+    }
+}
+
+/// Whether nothing listens on the socket file at `path` anymore.
+fn is_stale(path: &Path, addr: &net::SocketAddr) -> bool {
+    use std::os::unix::fs::FileTypeExt as _;
+
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+        && UnixStream::connect_addr(addr).is_err_and(|e| e.kind() == io::ErrorKind::ConnectionRefused)
+}
+
+/// A listener that is already bound, e.g. a socket inherited from another process.
+impl ToListener for Listener {
+    fn bind(self) -> io::Result<Listener> {
+        Ok(self)
+    }
+}
+
+/// A TCP or Unix socket address to listen on, e.g. as chosen on the command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(UnixAddr),
+}
+
+impl ToListener for ListenAddr {
+    fn bind(self) -> io::Result<Listener> {
+        match self {
+            ListenAddr::Tcp(addr) => addr.bind(),
+            ListenAddr::Unix(addr) => addr.bind(),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(addr) => write!(f, "{}", addr),
+        }
+    }
+}
+
+/// A listening TCP or Unix stream socket shared by the server models.
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Takes ownership of a listening socket, e.g. one inherited from another process.
+    /// The kind of listener is chosen by the address family of the socket.
+    pub fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        let mut domain: c_int = 0;
+        let mut len: socklen_t = mem::size_of::<c_int>() as socklen_t;
+        if unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                SOL_SOCKET,
+                SO_DOMAIN,
+                &mut domain as *mut c_int as *mut c_void,
+                &mut len,
+            )
+        } != 0
+        {
+            Err(io::Error::last_os_error())?;
+        }
+
+        // SAFETY: The descriptor is owned and refers to a socket of the matching family.
+        match domain {
+            AF_INET | AF_INET6 => Ok(Listener::Tcp(unsafe {
+                TcpListener::from_raw_fd(fd.into_raw_fd())
+            })),
+            AF_UNIX => Ok(Listener::Unix(unsafe {
+                UnixListener::from_raw_fd(fd.into_raw_fd())
+            })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported socket address family {}", domain),
+            )),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<(SocketStream, PeerAddr)> {
+        match self {
+            Listener::Tcp(listener) => listener
+                .accept()
+                .map(|(stream, addr)| (SocketStream::Tcp(stream), PeerAddr::Inet(addr))),
+            Listener::Unix(listener) => listener.accept().map(|(stream, addr)| {
+                let peer: PeerAddr = UnixAddr::from_std(&addr).map_or(PeerAddr::Unnamed, PeerAddr::Unix);
+                (SocketStream::Unix(stream), peer)
+            }),
+        }
+    }
+
+    /// Returns the TCP address of the listener. Fails for Unix sockets.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            Listener::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The listener is not a TCP socket",
+            )),
+        }
+    }
+
+    /// Returns the address of the listener, whatever its kind.
+    pub fn listen_addr(&self) -> io::Result<ListenAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddr::Tcp),
+            Listener::Unix(listener) => UnixAddr::from_std(&listener.local_addr()?)
+                .map(ListenAddr::Unix)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The Unix socket is unnamed")),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Listener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            Listener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Listener::Tcp(listener) => listener.try_clone().map(Listener::Tcp),
+            Listener::Unix(listener) => listener.try_clone().map(Listener::Unix),
+        }
+    }
+}
+
+impl AsFd for Listener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            Listener::Tcp(listener) => listener.as_fd(),
+            Listener::Unix(listener) => listener.as_fd(),
+        }
+    }
+}
+
+impl AsRawFd for Listener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
+
+/// A connection accepted by a [`Listener`], or opened by a client.
+#[derive(Debug)]
+pub enum SocketStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl SocketStream {
+    /// Connects to a Unix socket.
+    pub fn connect_unix(addr: &UnixAddr) -> io::Result<Self> {
+        UnixStream::connect_addr(&addr.to_std()?).map(SocketStream::Unix)
+    }
+}
+
+impl Read for SocketStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.read(buf),
+            SocketStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for SocketStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SocketStream::Tcp(stream) => stream.write(buf),
+            SocketStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.flush(),
+            SocketStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+impl Stream for SocketStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        match self {
+            SocketStream::Tcp(stream) => Stream::peer_addr(stream),
+            SocketStream::Unix(stream) => Stream::peer_addr(stream),
+        }
+    }
+
+    fn peer_cred(&self) -> io::Result<Option<PeerCred>> {
+        match self {
+            SocketStream::Tcp(_) => Ok(None),
+            SocketStream::Unix(stream) => stream::peer_cred(stream.as_fd()).map(Some),
+        }
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.shutdown(how),
+            SocketStream::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.set_read_timeout(timeout),
+            SocketStream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.set_write_timeout(timeout),
+            SocketStream::Unix(stream) => stream.set_write_timeout(timeout),
+        }
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            SocketStream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            SocketStream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl AsFd for SocketStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            SocketStream::Tcp(stream) => stream.as_fd(),
+            SocketStream::Unix(stream) => stream.as_fd(),
+        }
+    }
+}
+
+impl AsRawFd for SocketStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.as_fd().as_raw_fd()
+    }
+}
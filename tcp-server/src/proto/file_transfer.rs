@@ -83,6 +83,7 @@ pub mod file_response {
         pub enum Kind {
             InvalidQuery = 0,
             UnsupportedVersion = 1,
+            ServerBusy = 2,
         }
         impl Kind {
             /// String value of the enum field names used in the ProtoBuf definition.
@@ -93,6 +94,7 @@ pub mod file_response {
                 match self {
                     Self::InvalidQuery => "INVALID_QUERY",
                     Self::UnsupportedVersion => "UNSUPPORTED_VERSION",
+                    Self::ServerBusy => "SERVER_BUSY",
                 }
             }
             /// Creates an enum from field names used in the ProtoBuf definition.
@@ -100,6 +102,7 @@ pub mod file_response {
                 match value {
                     "INVALID_QUERY" => Some(Self::InvalidQuery),
                     "UNSUPPORTED_VERSION" => Some(Self::UnsupportedVersion),
+                    "SERVER_BUSY" => Some(Self::ServerBusy),
                     _ => None,
                 }
             }
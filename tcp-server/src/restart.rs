@@ -0,0 +1,351 @@
+use std::{env, io, thread};
+use std::sync::{Arc, Mutex};
+use std::ffi::OsString;
+use std::time::{Duration, Instant};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::os::unix::process::CommandExt as _;
+use std::os::fd::{RawFd, AsRawFd, FromRawFd as _, OwnedFd};
+use libc::{c_int, c_void, EFD_CLOEXEC, O_CLOEXEC, F_SETFD, SIGHUP, SIGUSR2};
+
+use crate::shutdown::{self, Shutdown};
+use crate::listener::Listener;
+
+/// Default time given to a new server to start up before the restart is abandoned.
+pub const DEFAULT_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Environment variable with the descriptor of the listener inherited from the previous server
+const LISTEN_FD_VAR: &str = "TCP_SERVER_LISTEN_FD";
+/// Environment variable with the write end of the pipe on which the new server reports readiness
+const READY_FD_VAR: &str = "TCP_SERVER_READY_FD";
+
+/// Descriptor of the eventfd written by the signal handler (-1 if no handler is installed)
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+/// Process that installed the handler. Forked children inherit both the handler and the eventfd,
+/// so a signal delivered to them (e.g. to the whole process group) must not request a restart.
+static SIGNAL_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn handle_restart_signal(_: c_int) {
+    let fd: RawFd = SIGNAL_FD.load(Ordering::Relaxed);
+    // SAFETY: `getpid` and `write` are async-signal-safe and `value` outlives the call.
+    if fd >= 0 && unsafe { libc::getpid() } == SIGNAL_PID.load(Ordering::Relaxed) {
+        let value: u64 = 1;
+        unsafe { libc::write(fd, &value as *const u64 as *const c_void, 8) };
+    }
+}
+
+/// Hot restart: replaces the running server with a freshly executed binary without dropping
+/// connections.
+///
+/// When a restart is requested (`SIGHUP`/`SIGUSR2` or [`Restart::trigger`]), the server executes
+/// the binary it was started from with the same arguments and passes it the listening socket as
+/// an inherited descriptor. The listener keeps queueing connections the whole time, so none are
+/// refused. Once the new server reports that it is ready, the old one shuts down: it stops
+/// accepting and drains its in-flight connections within the grace period. If the new server
+/// fails to start, the old one keeps serving.
+#[derive(Clone)]
+pub struct Restart {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    eventfd: OwnedFd,
+    ready_timeout: Duration,
+    handed_over: AtomicBool,
+    /// Pipe on which the previous server waits for this one to become ready
+    ready_pipe: Mutex<Option<OwnedFd>>,
+}
+
+impl Restart {
+    pub fn new(ready_timeout: Duration) -> io::Result<Self> {
+        let fd: RawFd = match unsafe { libc::eventfd(0, EFD_CLOEXEC) } {
+            -1 => Err(io::Error::last_os_error())?,
+            fd => fd,
+        };
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                // SAFETY: `eventfd` returned a new file descriptor that nothing else owns.
+                eventfd: unsafe { OwnedFd::from_raw_fd(fd) },
+                ready_timeout,
+                handed_over: AtomicBool::new(false),
+                ready_pipe: Mutex::new(None),
+            }),
+        })
+    }
+
+    /// Like [`Restart::new`], but if this process was started by a restart, the server reports
+    /// to the previous one once it is ready, through the pipe passed in the environment.
+    /// The pipe belongs to the result, so this is meant to be called once, e.g. in `main`.
+    pub fn from_env(ready_timeout: Duration) -> io::Result<Self> {
+        let restart: Restart = Self::new(ready_timeout)?;
+        if let Some(fd) = fd_var(READY_FD_VAR)? {
+            // SAFETY: The previous server passed the write end of the pipe for this one to own.
+            *restart.inner.ready_pipe.lock().unwrap() = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+
+        Ok(restart)
+    }
+
+    /// Installs `SIGHUP` and `SIGUSR2` handlers that trigger this restart.
+    /// Only one `Restart` can be connected to the signals at a time.
+    pub fn listen_for_signals(&self) -> io::Result<()> {
+        SIGNAL_PID.store(unsafe { libc::getpid() }, Ordering::Relaxed);
+        SIGNAL_FD.store(self.as_raw_fd(), Ordering::Relaxed);
+
+        for signal in [SIGHUP, SIGUSR2] {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = handle_restart_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+
+            if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+                Err(io::Error::last_os_error())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Requests a restart.
+    pub fn trigger(&self) {
+        let value: u64 = 1;
+        unsafe { libc::write(self.as_raw_fd(), &value as *const u64 as *const c_void, 8) };
+    }
+
+    pub fn ready_timeout(&self) -> Duration {
+        self.inner.ready_timeout
+    }
+
+    /// Returns `true` once a new server has taken over the listener.
+    pub fn is_handed_over(&self) -> bool {
+        self.inner.handed_over.load(Ordering::SeqCst)
+    }
+
+    /// Spawns a thread that performs a restart whenever one is requested, handing `listener`
+    /// over to the new server, and triggers `shutdown` once the new server is ready.
+    /// The thread exits when shutdown is requested.
+    pub(crate) fn spawn_watcher(&self, listener: &Listener, shutdown: Shutdown) -> io::Result<()> {
+        let restart: Restart = self.clone();
+        // A descriptor of its own, so that it stays valid for as long as the thread needs it
+        let listener: Listener = listener.try_clone()?;
+
+        thread::spawn(move || loop {
+            match shutdown::poll_readable(&[restart.as_raw_fd(), shutdown.as_raw_fd()], None) {
+                Ok(ready) if ready[1] => return,
+                Ok(ready) if ready[0] => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    tracing::error!("Failed to poll the restart eventfd: {}", e);
+                    return;
+                }
+            }
+
+            let Some(successor) = restart.start_successor(&listener) else {
+                continue;
+            };
+            let timeout: Duration = successor.deadline.saturating_duration_since(Instant::now());
+            let ready: bool =
+                shutdown::poll_readable(&[successor.as_raw_fd()], Some(timeout)).is_ok_and(|ready| ready[0]);
+            if restart.complete(successor, ready, &shutdown) {
+                return;
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Consumes the pending restart requests and starts a new server, handing `listener` over
+    /// to it. Returns `None` if the new server could not be started.
+    pub(crate) fn start_successor(&self, listener: &Listener) -> Option<Successor> {
+        self.reset();
+        tracing::info!("Restart requested. Starting a new server");
+
+        spawn_successor(listener, self.ready_timeout())
+            .inspect_err(|e| tracing::error!("Restart failed, the server keeps running: {}", e))
+            .ok()
+    }
+
+    /// Completes the restart once the pipe of `successor` is readable (`ready`) or its deadline
+    /// has passed, and triggers `shutdown` if the new server is ready. Returns `true` if it is.
+    pub(crate) fn complete(&self, successor: Successor, ready: bool, shutdown: &Shutdown) -> bool {
+        match successor.finish(ready) {
+            Ok(child) => {
+                tracing::info!(pid = child.id(), "New server is ready. Shutting down");
+                self.inner.handed_over.store(true, Ordering::SeqCst);
+                shutdown.trigger();
+                true
+            }
+            Err(e) => {
+                tracing::error!("Restart failed, the server keeps running: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Tells the previous server that this one is ready to accept connections.
+    /// Returns `false` (and does nothing) if this process was not started by a restart.
+    pub(crate) fn notify_ready(&self) -> io::Result<bool> {
+        let Some(pipe) = self.inner.ready_pipe.lock().unwrap().take() else {
+            return Ok(false);
+        };
+        let value: u8 = 1;
+        if unsafe { libc::write(pipe.as_raw_fd(), &value as *const u8 as *const c_void, 1) } != 1 {
+            Err(io::Error::last_os_error())?;
+        }
+
+        Ok(true)
+    }
+
+    /// Consumes the pending restart requests.
+    fn reset(&self) {
+        let mut value: u64 = 0;
+        unsafe { libc::read(self.as_raw_fd(), &mut value as *mut u64 as *mut c_void, 8) };
+    }
+}
+
+impl AsRawFd for Restart {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.eventfd.as_raw_fd()
+    }
+}
+
+/// Returns the listener handed over by the previous server, if this process was started by a restart.
+/// The listener owns the inherited descriptor, so this is meant to be called once, e.g. in `main`.
+pub fn inherited_listener() -> io::Result<Option<Listener>> {
+    let Some(fd) = fd_var(LISTEN_FD_VAR)? else {
+        return Ok(None);
+    };
+    // SAFETY: The previous server passed this descriptor for the new one to own.
+    let listener: Listener = Listener::from_fd(unsafe { OwnedFd::from_raw_fd(fd) })?;
+    set_cloexec(fd)?;
+    tracing::info!(
+        fd,
+        addr = %listener.listen_addr()?,
+        "Using the listener inherited from the previous server"
+    );
+
+    Ok(Some(listener))
+}
+
+/// A new server that has been started by a restart but has not reported yet whether it is ready.
+pub(crate) struct Successor {
+    child: Child,
+    ready_rx: OwnedFd,
+    /// The new server is killed if it is not ready by then
+    pub(crate) deadline: Instant,
+}
+
+impl Successor {
+    /// Reads the report of the new server once its pipe is readable, or gives up on it if
+    /// `ready` is `false`. The new server is killed unless it is ready.
+    fn finish(mut self, ready: bool) -> io::Result<Child> {
+        let result: io::Result<()> = match ready {
+            true => read_ready(&self.ready_rx),
+            false => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The new server did not become ready in time",
+            )),
+        };
+
+        match result {
+            Ok(()) => Ok(self.child),
+            Err(e) => {
+                let _ = self.child.kill();
+                // The child may have already been reaped by a server that waits for any child
+                let _ = self.child.wait();
+                Err(e)
+            }
+        }
+    }
+}
+
+impl AsRawFd for Successor {
+    fn as_raw_fd(&self) -> RawFd {
+        self.ready_rx.as_raw_fd()
+    }
+}
+
+/// Executes the server binary again with the same arguments, passing it `listener`.
+/// The new server has until `timeout` to report that it is ready.
+fn spawn_successor(listener: &Listener, timeout: Duration) -> io::Result<Successor> {
+    let mut args = env::args_os();
+    let program: OsString = args
+        .next()
+        .ok_or_else(|| io::Error::other("Unknown program name"))?;
+
+    let (ready_rx, ready_tx): (OwnedFd, OwnedFd) = pipe()?;
+    let listener_fd: RawFd = listener.as_raw_fd();
+    let ready_fd: RawFd = ready_tx.as_raw_fd();
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(LISTEN_FD_VAR, listener_fd.to_string())
+        .env(READY_FD_VAR, ready_fd.to_string())
+        // The systemd watchdog follows the main pid, which the new server takes over once ready
+        .env_remove("WATCHDOG_PID");
+    // SAFETY: Only async-signal-safe calls are made between `fork` and `exec`.
+    // Descriptors are inherited only in the child, so the flags stay untouched in this process.
+    unsafe {
+        command.pre_exec(move || {
+            clear_cloexec(listener_fd)?;
+            clear_cloexec(ready_fd)
+        })
+    };
+    let child: Child = command.spawn()?;
+    // Only the new server may hold the write end, so that its exit is seen as end of file
+    drop(ready_tx);
+
+    Ok(Successor {
+        child,
+        ready_rx,
+        deadline: Instant::now() + timeout,
+    })
+}
+
+fn read_ready(ready_rx: &OwnedFd) -> io::Result<()> {
+    let mut value: u8 = 0;
+    match unsafe { libc::read(ready_rx.as_raw_fd(), &mut value as *mut u8 as *mut c_void, 1) } {
+        1 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        _ => Err(io::Error::other("The new server exited before becoming ready")),
+    }
+}
+
+/// Reads a descriptor number from the environment. A new server is always passed its own,
+/// so the variables are left in place.
+fn fd_var(name: &str) -> io::Result<Option<RawFd>> {
+    let Some(value) = env::var_os(name) else {
+        return Ok(None);
+    };
+
+    value
+        .to_str()
+        .and_then(|value| value.parse::<RawFd>().ok())
+        .map(Some)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid {}", name)))
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds: [c_int; 2] = [-1; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), O_CLOEXEC) } != 0 {
+        Err(io::Error::last_os_error())?;
+    }
+    // SAFETY: `pipe2` returned two new file descriptors that nothing else owns.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    match unsafe { libc::fcntl(fd, F_SETFD, libc::FD_CLOEXEC) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn clear_cloexec(fd: RawFd) -> io::Result<()> {
+    match unsafe { libc::fcntl(fd, F_SETFD, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,127 @@
+use std::{io, ptr, slice};
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use libc::{c_void, MAP_ANONYMOUS, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+
+/// State of a [`Slot`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum SlotState {
+    /// No child is assigned to the slot
+    Empty = 0,
+    /// The child is waiting for a connection
+    Idle = 1,
+    /// The child is handling a connection
+    Busy = 2,
+    /// The child has been told to exit (or decided to) once its current connection is done
+    Retiring = 3,
+}
+
+impl SlotState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Idle,
+            2 => Self::Busy,
+            3 => Self::Retiring,
+            _ => Self::Empty,
+        }
+    }
+}
+
+/// Scoreboard entry of a single prefork child
+#[repr(C)]
+pub(crate) struct Slot {
+    state: AtomicU8,
+    requests: AtomicU64,
+}
+
+impl Slot {
+    pub(crate) fn state(&self) -> SlotState {
+        SlotState::from_u8(self.state.load(Ordering::Acquire))
+    }
+
+    /// Assigns the slot to a new child, which starts as idle.
+    pub(crate) fn assign(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.state.store(SlotState::Idle as u8, Ordering::Release);
+    }
+
+    pub(crate) fn release(&self) {
+        self.state.store(SlotState::Empty as u8, Ordering::Release);
+    }
+
+    /// Atomically changes the state from `from` to `to`. Returns `false` if the state was not `from`.
+    pub(crate) fn transition(&self, from: SlotState, to: SlotState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub(crate) fn retire(&self) {
+        self.state.store(SlotState::Retiring as u8, Ordering::Release);
+    }
+
+    /// Counts a handled connection and returns the total so far.
+    pub(crate) fn count_request(&self) -> u64 {
+        self.requests.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+/// Table of [`Slot`]s in anonymous shared memory, through which prefork children report
+/// whether they are idle or busy. The mapping is inherited across `fork`, so the parent
+/// sees the updates the children make.
+pub(crate) struct Scoreboard {
+    slots: *mut Slot,
+    len: usize,
+}
+
+impl Scoreboard {
+    pub(crate) fn new(len: usize) -> io::Result<Self> {
+        let size: usize = len.max(1) * std::mem::size_of::<Slot>();
+
+        match unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        } {
+            MAP_FAILED => Err(io::Error::last_os_error()),
+            // Anonymous mappings are zero-filled, which is a valid `Slot` in the `Empty` state
+            addr => Ok(Self {
+                slots: addr as *mut Slot,
+                len,
+            }),
+        }
+    }
+
+    pub(crate) fn slots(&self) -> &[Slot] {
+        // SAFETY: The mapping holds `len` initialized slots and lives as long as `self`.
+        unsafe { slice::from_raw_parts(self.slots, self.len) }
+    }
+
+    pub(crate) fn slot(&self, index: usize) -> &Slot {
+        &self.slots()[index]
+    }
+
+    /// Returns the index of a slot that is not assigned to any child.
+    pub(crate) fn free_slot(&self) -> Option<usize> {
+        self.slots()
+            .iter()
+            .position(|slot| slot.state() == SlotState::Empty)
+    }
+
+    /// Returns the number of slots in the given state.
+    pub(crate) fn count(&self, state: SlotState) -> usize {
+        self.slots().iter().filter(|slot| slot.state() == state).count()
+    }
+}
+
+impl Drop for Scoreboard {
+    fn drop(&mut self) {
+        let size: usize = self.len.max(1) * std::mem::size_of::<Slot>();
+        unsafe { libc::munmap(self.slots as *mut c_void, size) };
+    }
+}
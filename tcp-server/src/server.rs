@@ -1,12 +1,31 @@
 #![allow(unused_imports)]
 use std::{io, process};
+use std::sync::Arc;
+use std::str::FromStr;
 use std::net::SocketAddr;
+use std::time::Duration;
 use std::path::{Path, PathBuf};
-use clap::{Parser, ValueHint};
+use clap::{CommandFactory, Parser, ValueHint};
+use clap::error::ErrorKind;
 use tracing_subscriber::EnvFilter;
 
 use tcp_server::core::*;
-use tcp_server::service::{Service, DelayedEchoService, FileTransferService};
+use tcp_server::shutdown::Shutdown;
+use tcp_server::restart::{self, Restart, DEFAULT_READY_TIMEOUT};
+use tcp_server::systemd::{self, Notifier};
+use tcp_server::accept::AcceptStrategy;
+#[cfg(feature = "prefork")]
+use tcp_server::accept::ReusePortAddr;
+use tcp_server::listener::{Listener, ToListener, UnixAddr};
+use tcp_server::affinity::{CpuAffinity, PinCpus};
+use tcp_server::limit::{ConnectionLimiter, LimitStats, Limits, RateLimit, Cidr};
+use tcp_server::service::{
+    Service, NonBlockingService, DelayedEchoService, FileTransferService, ConnectionTimeouts,
+};
+#[cfg(feature = "tokio")]
+use tcp_server::service::AsyncService;
+#[cfg(all(feature = "tls", not(feature = "epoll")))]
+use tcp_server::tls::{self, ServerTlsBuilder};
 
 const PROTOCOL_VERSION: u32 = 1;
 const CHUNK_SIZE: usize = 1024;
@@ -14,51 +33,369 @@ const CHUNK_SIZE: usize = 1024;
 #[derive(Parser, Debug)]
 #[command(version = "1.0", about = "TCP server")]
 struct Args {
-    /// Socket address to bind to
+    /// Socket address to bind to, unless systemd passes a socket (`LISTEN_FDS`)
     #[arg(short = 'a', long = "socket_addr", default_value = "127.0.0.1:7878")]
     socket_addr: SocketAddr,
 
+    /// Unix socket to listen on instead, as a path or `@NAME` for the abstract namespace
+    #[arg(short = 'u', long = "unix", value_hint = ValueHint::FilePath, conflicts_with = "socket_addr")]
+    unix: Option<UnixAddr>,
+
     /// Base directory for file storage
     #[arg(short = 'd', long = "dir", value_hint = ValueHint::DirPath, default_value = "data")]
     base_dir: PathBuf,
 
+    /// Seconds given to in-flight connections to finish after SIGINT or SIGTERM
+    #[arg(short = 't', long = "shutdown-timeout", default_value = "30")]
+    shutdown_timeout: u64,
+
+    /// Seconds a client has to send its request after connecting (0 for no limit)
+    #[arg(long = "handshake-timeout", default_value = "10")]
+    handshake_timeout: u64,
+
+    /// Seconds the server waits for each message from a client (0 for no limit)
+    #[arg(long = "idle-timeout", default_value = "60")]
+    idle_timeout: u64,
+
+    /// Seconds a connection may last from accept to close (unlimited by default)
+    #[arg(long = "transfer-timeout")]
+    transfer_timeout: Option<u64>,
+
+    /// Maximum number of connections handled at the same time (unlimited by default)
+    #[arg(long = "max-connections")]
+    max_connections: Option<usize>,
+
+    /// Maximum number of connections handled at the same time per client IP
+    #[arg(long = "max-connections-per-ip")]
+    max_connections_per_ip: Option<usize>,
+
+    /// New connections per second allowed per client IP
+    #[arg(long = "rate-limit")]
+    rate_limit: Option<f64>,
+
+    /// New connections a client IP may open in a burst before --rate-limit applies
+    #[arg(long = "rate-burst", default_value = "10", requires = "rate_limit")]
+    rate_burst: u32,
+
+    /// Only admit clients from this network, e.g. `10.0.0.0/8` (can be repeated)
+    #[arg(long = "allow")]
+    allow: Vec<Cidr>,
+
+    /// Turn away clients from this network, even if allowed (can be repeated)
+    #[arg(long = "deny")]
+    deny: Vec<Cidr>,
+
+    /// PEM file with the certificate chain to serve TLS with. The epoll server has no TLS support
+    #[cfg(all(feature = "tls", not(feature = "epoll")))]
+    #[arg(long = "tls-cert", value_hint = ValueHint::FilePath, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key of --tls-cert
+    #[cfg(all(feature = "tls", not(feature = "epoll")))]
+    #[arg(long = "tls-key", value_hint = ValueHint::FilePath, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Certificate for clients asking for a server name via SNI, as `NAME:CERT:KEY` (can be repeated)
+    #[cfg(all(feature = "tls", not(feature = "epoll")))]
+    #[arg(long = "tls-sni")]
+    tls_sni: Vec<SniCertificate>,
+
+    /// PEM file with the CA certificates that client certificates must be issued by (mutual TLS)
+    #[cfg(all(feature = "tls", not(feature = "epoll")))]
+    #[arg(long = "tls-client-ca", value_hint = ValueHint::FilePath)]
+    tls_client_ca: Option<PathBuf>,
+
     /// Number of worker threads for the thread pool server
     #[cfg(feature = "threadpool")]
     #[arg(short = 'w', long = "workers", default_value = "4")]
     workers: usize,
 
+    /// Maximum number of worker threads; the pool grows up to it while connections queue up
+    #[cfg(feature = "threadpool")]
+    #[arg(long = "max-workers")]
+    max_workers: Option<usize>,
+
+    /// Seconds a worker above --workers may stay idle before it retires
+    #[cfg(feature = "threadpool")]
+    #[arg(long = "keep-alive", default_value = "60", requires = "max_workers")]
+    keep_alive: u64,
+
+    /// Maximum number of connections waiting for a worker (unbounded by default)
+    #[cfg(feature = "threadpool")]
+    #[arg(short = 'q', long = "queue-capacity")]
+    queue_capacity: Option<usize>,
+
+    /// What to do with new connections while the queue is full
+    #[cfg(feature = "threadpool")]
+    #[arg(long = "on-full", value_enum, default_value_t = OverloadPolicy::Block)]
+    overload_policy: OverloadPolicy,
+
     /// Maximum number of child processes for the fork-per-connection server
     #[cfg(feature = "fork_per_connection")]
     #[arg(short = 'm', long = "max-processes", default_value = "4")]
     max_processes: usize,
 
+    /// What to do with new connections while the maximum number of child processes is running
+    #[cfg(feature = "fork_per_connection")]
+    #[arg(long = "on-limit", value_enum, default_value_t = LimitPolicy::Queue)]
+    limit_policy: LimitPolicy,
+
     /// Number of preforked child processes for the prefork server
     #[cfg(feature = "prefork")]
     #[arg(short = 'p', long = "processes", default_value = "4")]
     processes: usize,
+
+    /// Minimum number of idle children; enables adaptive sizing of the prefork pool
+    #[cfg(feature = "prefork")]
+    #[arg(long = "min-spare", requires_all = ["max_spare", "max_children"])]
+    min_spare: Option<usize>,
+
+    /// Maximum number of idle children for the adaptive prefork pool
+    #[cfg(feature = "prefork")]
+    #[arg(long = "max-spare", requires_all = ["min_spare", "max_children"])]
+    max_spare: Option<usize>,
+
+    /// Maximum number of children for the adaptive prefork pool
+    #[cfg(feature = "prefork")]
+    #[arg(
+        long = "max-children",
+        requires_all = ["min_spare", "max_spare"],
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_children: Option<usize>,
+
+    /// Number of connections after which a prefork child is replaced (unlimited by default)
+    #[cfg(feature = "prefork")]
+    #[arg(long = "max-requests", value_parser = clap::value_parser!(u64).range(1..))]
+    max_requests: Option<u64>,
+
+    /// How prefork children take turns accepting connections
+    #[cfg(feature = "prefork")]
+    #[arg(long = "accept", value_enum, default_value_t = AcceptStrategy::Shared)]
+    accept_strategy: AcceptStrategy,
+
+    /// Pin thread pool workers or prefork children to CPUs:
+    /// `round-robin`, `numa` (node by node) or a CPU list such as `0,2,4-7`
+    #[cfg(any(feature = "threadpool", feature = "prefork"))]
+    #[arg(long = "pin-cpus")]
+    pin_cpus: Option<PinCpus>,
+
+    /// Number of reactor threads for the epoll server
+    #[cfg(feature = "epoll")]
+    #[arg(short = 'r', long = "reactors", default_value = "1")]
+    reactors: usize,
 }
 
-fn run_server(args: &Args, service: impl Service) -> io::Result<()> {
-    #[cfg(not(any(feature = "threadpool", feature = "fork_per_connection", feature = "prefork")))]
+/// A certificate served to clients asking for `name` via SNI.
+#[cfg(all(feature = "tls", not(feature = "epoll")))]
+#[derive(Debug, Clone)]
+struct SniCertificate {
+    name: String,
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+#[cfg(all(feature = "tls", not(feature = "epoll")))]
+impl FromStr for SniCertificate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.splitn(3, ':').collect::<Vec<&str>>().as_slice() {
+            [name, cert, key] if !name.is_empty() => Ok(Self {
+                name: name.to_string(),
+                cert: cert.into(),
+                key: key.into(),
+            }),
+            _ => Err(format!("expected `NAME:CERT:KEY`, got {:?}", s)),
+        }
+    }
+}
+
+/// Service traits required by the server models that can be enabled at compile time.
+#[cfg(not(feature = "tokio"))]
+trait ServerService: Service + EventDriven {}
+#[cfg(not(feature = "tokio"))]
+impl<S: Service + EventDriven> ServerService for S {}
+
+#[cfg(feature = "tokio")]
+trait ServerService: Service + EventDriven + AsyncService {}
+#[cfg(feature = "tokio")]
+impl<S: Service + EventDriven + AsyncService> ServerService for S {}
+
+/// Only the epoll server needs a [`NonBlockingService`], which TLS services are not.
+#[cfg(feature = "epoll")]
+trait EventDriven: NonBlockingService {}
+#[cfg(feature = "epoll")]
+impl<S: NonBlockingService> EventDriven for S {}
+
+#[cfg(not(feature = "epoll"))]
+trait EventDriven {}
+#[cfg(not(feature = "epoll"))]
+impl<S> EventDriven for S {}
+
+/// Returns the listener handed over by the server being restarted or passed by systemd socket
+/// activation, if there is one; otherwise binds to the address from the command line.
+fn listener(args: &Args) -> io::Result<Listener> {
+    if let Some(listener) = restart::inherited_listener()? {
+        return Ok(listener);
+    }
+    if let Some(listener) = systemd::activated_listener()? {
+        return Ok(listener);
+    }
+
+    match &args.unix {
+        Some(unix) => unix.clone().bind(),
+        // `SO_REUSEPORT` has to be set before the listener is bound
+        #[cfg(feature = "prefork")]
+        None if args.accept_strategy == AcceptStrategy::ReusePort => ReusePortAddr(args.socket_addr).bind(),
+        None => args.socket_addr.bind(),
+    }
+}
+
+fn run_server(
+    args: &Args,
+    listener: Listener,
+    service: impl ServerService,
+    limiter: &Arc<ConnectionLimiter>,
+) -> io::Result<()> {
+    let shutdown = Shutdown::new(Duration::from_secs(args.shutdown_timeout))?;
+    shutdown.listen_for_signals()?;
+    // SIGHUP or SIGUSR2 restarts the server from its binary, e.g. after an upgrade
+    let restart = Restart::from_env(DEFAULT_READY_TIMEOUT)?;
+    restart.listen_for_signals()?;
+    // Set when running under systemd
+    let notifier: Option<Notifier> = Notifier::from_env()?;
+
+    #[cfg(not(any(
+        feature = "threadpool",
+        feature = "fork_per_connection",
+        feature = "prefork",
+        feature = "epoll",
+        feature = "tokio"
+    )))]
     {
-        let server = IterativeTcpServer::new(args.socket_addr, service)?;
+        let mut server = IterativeTcpServer::new(listener, service)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter));
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
         server.serve()
     }
     #[cfg(feature = "threadpool")]
     {
-        let server = ThreadPoolTcpServer::new(args.socket_addr, service, args.workers)?;
+        let mut server = ThreadPoolTcpServer::new(listener, service, args.workers)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter));
+        if let Some(capacity) = args.queue_capacity {
+            server = server.with_queue_capacity(capacity, args.overload_policy);
+        }
+        if let Some(max_workers) = args.max_workers {
+            server = server.with_max_workers(max_workers, Duration::from_secs(args.keep_alive));
+        }
+        if let Some(pin) = &args.pin_cpus {
+            server = server.with_cpu_affinity(CpuAffinity::new(pin)?);
+        }
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
         server.serve()
     }
     #[cfg(feature = "fork_per_connection")]
     {
-        let server = ForkPerConnectionTcpServer::new(args.socket_addr, service, args.max_processes)?;
+        let mut server = ForkPerConnectionTcpServer::new(listener, service, args.max_processes)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter))
+            .with_limit_policy(args.limit_policy);
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
         server.serve()
     }
     #[cfg(feature = "prefork")]
     {
-        let server = PreforkTcpServer::new(args.socket_addr, service, args.processes)?;
+        let mut server = PreforkTcpServer::new(listener, service, args.processes)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter))
+            .with_accept_strategy(args.accept_strategy)?;
+        if let (Some(min_spare), Some(max_spare), Some(max_children)) =
+            (args.min_spare, args.max_spare, args.max_children)
+        {
+            server = server.with_spare_children(min_spare, max_spare, max_children);
+        }
+        if let Some(max_requests) = args.max_requests {
+            server = server.with_max_requests_per_child(max_requests);
+        }
+        if let Some(pin) = &args.pin_cpus {
+            server = server.with_cpu_affinity(CpuAffinity::new(pin)?);
+        }
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
+        server.serve()
+    }
+    #[cfg(feature = "epoll")]
+    {
+        let mut server = EpollTcpServer::new(listener, service, args.reactors)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter));
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
         server.serve()
     }
+    #[cfg(feature = "tokio")]
+    {
+        let mut server = AsyncTcpServer::new(listener, service)?
+            .with_shutdown(shutdown)
+            .with_restart(restart)
+            .with_limiter(Arc::clone(limiter));
+        if let Some(notifier) = notifier {
+            server = server.with_notifier(notifier);
+        }
+        tokio::runtime::Runtime::new()?.block_on(server.serve())
+    }
+}
+
+/// Loads the TLS configuration from the command line, if any TLS certificate is given.
+#[cfg(all(feature = "tls", not(feature = "epoll")))]
+fn tls_config(args: &Args) -> io::Result<Option<Arc<rustls::ServerConfig>>> {
+    if args.tls_cert.is_none() && args.tls_sni.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = ServerTlsBuilder::new();
+    if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+        builder = builder.certificate(tls::load_certificates(cert)?, tls::load_private_key(key)?);
+    }
+    for sni in &args.tls_sni {
+        builder = builder.sni_certificate(
+            &sni.name,
+            tls::load_certificates(&sni.cert)?,
+            tls::load_private_key(&sni.key)?,
+        );
+    }
+    if let Some(client_ca) = &args.tls_client_ca {
+        builder = builder.client_auth(tls::load_certificates(client_ca)?);
+    }
+
+    builder.build().map(Some)
+}
+
+/// Exits with a usage error for arguments that are valid on their own but not together.
+#[cfg(any(feature = "threadpool", feature = "prefork"))]
+fn conflicting_args(message: &str) -> ! {
+    Args::command().error(ErrorKind::ArgumentConflict, message).exit()
+}
+
+/// Converts a timeout in seconds from the command line, where 0 means no limit.
+fn timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
 }
 
 fn main() -> io::Result<()> {
@@ -75,8 +412,53 @@ fn main() -> io::Result<()> {
 
     let args = Args::parse();
     tracing::debug!(?args, "Parsed arguments");
+    #[cfg(feature = "threadpool")]
+    if args.max_workers.is_some_and(|max_workers| max_workers < args.workers) {
+        conflicting_args("--max-workers must not be less than --workers");
+    }
+    #[cfg(feature = "prefork")]
+    if let (Some(min_spare), Some(max_spare), Some(max_children)) =
+        (args.min_spare, args.max_spare, args.max_children)
+    {
+        if min_spare > max_spare {
+            conflicting_args("--min-spare must not be greater than --max-spare");
+        }
+        if max_children < args.processes {
+            conflicting_args("--max-children must not be less than --processes");
+        }
+    }
+
+    let timeouts = ConnectionTimeouts {
+        handshake: timeout(args.handshake_timeout),
+        read_idle: timeout(args.idle_timeout),
+        total: args.transfer_timeout.and_then(timeout),
+    };
+    let ft_service =
+        FileTransferService::new(&args.base_dir, PROTOCOL_VERSION, CHUNK_SIZE).with_timeouts(timeouts);
 
-    let ft_service = FileTransferService::new(&args.base_dir, PROTOCOL_VERSION, CHUNK_SIZE);
+    let listener: Listener = listener(&args)?;
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        max_connections: args.max_connections,
+        max_connections_per_ip: args.max_connections_per_ip,
+        rate: args.rate_limit.map(|per_second| RateLimit {
+            per_second,
+            burst: args.rate_burst,
+        }),
+        allow: args.allow.clone(),
+        deny: args.deny.clone(),
+    });
+    #[cfg(all(feature = "tls", not(feature = "epoll")))]
+    let result: io::Result<()> = match tls_config(&args)? {
+        Some(config) => run_server(&args, listener, ft_service.with_tls(config), &limiter),
+        None => run_server(&args, listener, ft_service, &limiter),
+    };
+    #[cfg(not(all(feature = "tls", not(feature = "epoll"))))]
+    let result: io::Result<()> = run_server(&args, listener, ft_service, &limiter);
+
+    let stats: LimitStats = limiter.stats();
+    if stats.admitted + stats.rejected() > 0 {
+        tracing::info!(?stats, "Connection limit statistics");
+    }
 
-    run_server(&args, ft_service)
+    result
 }
@@ -1,62 +1,450 @@
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::path::{Path, PathBuf};
 use std::net::{TcpStream, Shutdown, ToSocketAddrs};
 use std::fs::{self, File, Metadata};
 use std::io::{self, Read, BufRead, Write, BufReader, BufWriter};
-use tracing::instrument;
+use tracing::{instrument, Span};
 use prost::Message;
 
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use tracing::Instrument as _;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, AsyncReadExt as _, AsyncWriteExt as _, AsyncBufReadExt as _};
+
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
 use crate::proto::prelude::*;
+use crate::stream::{Stream, PeerAddr};
+use crate::listener::{SocketStream, UnixAddr};
+#[cfg(feature = "tokio")]
+use crate::stream::AsyncStream;
+
+/// Longest message the non-blocking file transfer service buffers. The query and the ack
+/// a client sends are far shorter.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Chunks the non-blocking file transfer service reads from the file per call to `resume`.
+/// File reads block the reactor, so a transfer yields to the other connections in between.
+const CHUNKS_PER_RESUME: usize = 16;
 
+/// Handles connections over any [`Stream`]: the servers pass TCP or Unix sockets,
+/// tests can pass in-memory streams (see [`crate::stream::duplex`]).
 pub trait Service: Send + Sync + 'static {
-    fn handle_connection(&self, stream: TcpStream) -> io::Result<()>;
+    fn handle_connection(&self, stream: impl Stream) -> io::Result<()>;
+
+    /// Turns away a connection the server has no capacity for. Runs on the accepting thread,
+    /// so it must not wait for the client. Closes the connection by default.
+    fn reject_connection(&self, stream: impl Stream) -> io::Result<()> {
+        stream.shutdown(Shutdown::Both)
+    }
+}
+
+/// What a non-blocking connection is waiting for before it can make progress.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+    /// The connection is finished and can be closed.
+    Close,
+}
+
+/// A non-blocking counterpart of [`Service`] used by event-driven servers.
+///
+/// Each connection is driven as a resumable state machine: the server calls
+/// [`NonBlockingService::resume`] right after the connection is accepted and then
+/// every time the socket becomes ready for the returned [`Interest`]. The stream is
+/// in non-blocking mode, so implementations must treat `WouldBlock` as "not ready yet"
+/// and return the interest they are waiting for instead of blocking.
+pub trait NonBlockingService: Send + Sync + 'static {
+    /// Per-connection state kept by the server between wakeups.
+    type State: Send;
+
+    fn init(&self, stream: &impl Stream) -> Self::State;
+
+    fn resume(&self, state: &mut Self::State, stream: &mut impl Stream) -> io::Result<Interest>;
+
+    /// Returns when the connection times out, if it does. Once the deadline passes, the server
+    /// resumes the connection so that the service can fail it, and closes it if it stays open.
+    fn deadline(&self, _state: &Self::State) -> Option<Instant> {
+        None
+    }
+}
+
+/// An async counterpart of [`Service`] used by servers running on the tokio runtime.
+#[cfg(feature = "tokio")]
+pub trait AsyncService: Send + Sync + 'static {
+    fn handle_connection(&self, stream: impl AsyncStream) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Wraps a service in another one that adds behaviour around every connection,
+/// such as tracing or logging errors.
+pub trait Layer<S> {
+    type Service;
+
+    fn layer(&self, inner: S) -> Self::Service;
+}
+
+/// A layer that returns the service unchanged.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Identity;
+
+impl<S> Layer<S> for Identity {
+    type Service = S;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        inner
+    }
+}
+
+/// Two layers applied one after the other: `inner` wraps the service, `outer` wraps the result.
+#[derive(Debug, Clone)]
+pub struct Stack<Inner, Outer> {
+    inner: Inner,
+    outer: Outer,
+}
+
+impl<S, Inner: Layer<S>, Outer: Layer<Inner::Service>> Layer<S> for Stack<Inner, Outer> {
+    type Service = Outer::Service;
+
+    fn layer(&self, service: S) -> Self::Service {
+        self.outer.layer(self.inner.layer(service))
+    }
+}
+
+/// Stacks layers around a service.
+///
+/// Layers are listed from the outside in: the first one added sees every connection first
+/// and its result last. The servers wrap every service in a [`TraceLayer`] and a
+/// [`LogErrorsLayer`] themselves, so those are only needed for services used on their own.
+///
+/// ```no_run
+/// # use std::io;
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # use tcp_server::core::ThreadPoolTcpServer;
+/// # use tcp_server::service::{FileTransferService, Layer, Service, ServiceBuilder};
+/// # use tcp_server::stream::Stream;
+/// /// Counts the connections handled by the inner service
+/// struct CountLayer;
+///
+/// struct Count<S> {
+///     inner: S,
+///     connections: AtomicUsize,
+/// }
+///
+/// impl<S> Layer<S> for CountLayer {
+///     type Service = Count<S>;
+///
+///     fn layer(&self, inner: S) -> Self::Service {
+///         Count { inner, connections: AtomicUsize::new(0) }
+///     }
+/// }
+///
+/// impl<S: Service> Service for Count<S> {
+///     fn handle_connection(&self, stream: impl Stream) -> io::Result<()> {
+///         self.connections.fetch_add(1, Ordering::Relaxed);
+///         self.inner.handle_connection(stream)
+///     }
+/// }
+///
+/// let service = ServiceBuilder::new()
+///     .layer(CountLayer)
+///     .service(FileTransferService::new("data", 1, 1024));
+/// // Traced and with its errors logged by the server
+/// let server = ThreadPoolTcpServer::new("127.0.0.1:8080", service, 4)?;
+/// # Ok::<(), io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServiceBuilder<L> {
+    layer: L,
+}
+
+impl ServiceBuilder<Identity> {
+    pub fn new() -> Self {
+        Self { layer: Identity }
+    }
+}
+
+impl Default for ServiceBuilder<Identity> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> ServiceBuilder<L> {
+    /// Adds a layer inside the ones added so far.
+    pub fn layer<T>(self, layer: T) -> ServiceBuilder<Stack<T, L>> {
+        ServiceBuilder {
+            layer: Stack {
+                inner: layer,
+                outer: self.layer,
+            },
+        }
+    }
+
+    /// Wraps the service in all layers.
+    pub fn service<S>(&self, service: S) -> L::Service
+    where
+        L: Layer<S>,
+    {
+        self.layer.layer(service)
+    }
+}
+
+/// A layer that runs every connection in a `connection` span with the peer address
+/// and logs when the connection ends.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace { inner }
+    }
+}
+
+/// Service produced by [`TraceLayer`]
+#[derive(Debug)]
+pub struct Trace<S> {
+    inner: S,
+}
+
+fn connection_span(peer: Option<PeerAddr>) -> Span {
+    tracing::info_span!("connection", peer = peer.as_ref().map(tracing::field::display))
+}
+
+impl<S: Service> Service for Trace<S> {
+    fn handle_connection(&self, stream: impl Stream) -> io::Result<()> {
+        let _span = connection_span(stream.peer_addr().ok()).entered();
+        let start: Instant = Instant::now();
+
+        let result: io::Result<()> = self.inner.handle_connection(stream);
+        tracing::debug!(elapsed = ?start.elapsed(), "Connection closed");
+
+        result
+    }
+
+    fn reject_connection(&self, stream: impl Stream) -> io::Result<()> {
+        connection_span(stream.peer_addr().ok()).in_scope(|| self.inner.reject_connection(stream))
+    }
+}
+
+/// Per-connection state of a [`Trace`] service
+#[derive(Debug)]
+pub struct TraceState<T> {
+    span: Span,
+    start: Instant,
+    inner: T,
+}
+
+impl<S: NonBlockingService> NonBlockingService for Trace<S> {
+    type State = TraceState<S::State>;
+
+    fn init(&self, stream: &impl Stream) -> Self::State {
+        let span: Span = connection_span(stream.peer_addr().ok());
+
+        TraceState {
+            inner: span.in_scope(|| self.inner.init(stream)),
+            span,
+            start: Instant::now(),
+        }
+    }
+
+    fn resume(&self, state: &mut Self::State, stream: &mut impl Stream) -> io::Result<Interest> {
+        let _span = state.span.enter();
+
+        let result: io::Result<Interest> = self.inner.resume(&mut state.inner, stream);
+        if !matches!(result, Ok(Interest::Read | Interest::Write)) {
+            tracing::debug!(elapsed = ?state.start.elapsed(), "Connection closed");
+        }
+
+        result
+    }
+
+    fn deadline(&self, state: &Self::State) -> Option<Instant> {
+        self.inner.deadline(&state.inner)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncService> AsyncService for Trace<S> {
+    async fn handle_connection(&self, stream: impl AsyncStream) -> io::Result<()> {
+        let span: Span = connection_span(stream.peer_addr().ok());
+
+        async {
+            let start: Instant = Instant::now();
+
+            let result: io::Result<()> = self.inner.handle_connection(stream).await;
+            tracing::debug!(elapsed = ?start.elapsed(), "Connection closed");
+
+            result
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+/// A layer that logs the errors of the service instead of returning them.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LogErrorsLayer;
+
+impl<S> Layer<S> for LogErrorsLayer {
+    type Service = LogErrors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LogErrors { inner }
+    }
+}
+
+/// Service produced by [`LogErrorsLayer`]
+#[derive(Debug)]
+pub struct LogErrors<S> {
+    inner: S,
+}
+
+impl<S: Service> Service for LogErrors<S> {
+    fn handle_connection(&self, stream: impl Stream) -> io::Result<()> {
+        if let Err(e) = self.inner.handle_connection(stream) {
+            tracing::error!("Service error: failed to handle connection: {}", e);
+        }
+        Ok(())
+    }
+
+    fn reject_connection(&self, stream: impl Stream) -> io::Result<()> {
+        if let Err(e) = self.inner.reject_connection(stream) {
+            tracing::error!("Service error: failed to reject connection: {}", e);
+        }
+        Ok(())
+    }
+}
+
+impl<S: NonBlockingService> NonBlockingService for LogErrors<S> {
+    type State = S::State;
+
+    fn init(&self, stream: &impl Stream) -> Self::State {
+        self.inner.init(stream)
+    }
+
+    /// Closes the connection on errors.
+    fn resume(&self, state: &mut Self::State, stream: &mut impl Stream) -> io::Result<Interest> {
+        self.inner.resume(state, stream).or_else(|e| {
+            tracing::error!("Service error: failed to handle connection: {}", e);
+            Ok(Interest::Close)
+        })
+    }
+
+    fn deadline(&self, state: &Self::State) -> Option<Instant> {
+        self.inner.deadline(state)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncService> AsyncService for LogErrors<S> {
+    async fn handle_connection(&self, stream: impl AsyncStream) -> io::Result<()> {
+        if let Err(e) = self.inner.handle_connection(stream).await {
+            tracing::error!("Service error: failed to handle connection: {}", e);
+        }
+        Ok(())
+    }
+}
+
+/// Limits on how long a connection may take. `None` means no limit.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    /// Time from accepting the connection until the client's request has been received
+    pub handshake: Option<Duration>,
+    /// Time the server waits for each message from the client. Unlike a plain socket timeout,
+    /// it is not reset by every byte, so a client cannot hold the connection by trickling data.
+    pub read_idle: Option<Duration>,
+    /// Time from accepting the connection until it is closed
+    pub total: Option<Duration>,
 }
 
 /// A simple echo service that delays the echo response for a specified duration.
 /// This service is primarily used for testing purposes.
 pub struct DelayedEchoService {
     delay: Duration,
+    timeouts: ConnectionTimeouts,
 }
 
 impl DelayedEchoService {
     pub fn new(delay: u64) -> Self {
         Self {
             delay: Duration::from_secs(delay),
+            timeouts: ConnectionTimeouts::default(),
         }
     }
 
-    #[instrument(name = "echo_service", skip_all, fields(peer = ?stream.peer_addr().ok()))]
-    pub fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
-        let buf_reader: BufReader<&mut TcpStream> = BufReader::new(&mut stream);
+    /// Limits how long a connection may take. The handshake is the whole request.
+    pub fn with_timeouts(mut self, timeouts: ConnectionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    #[instrument(name = "echo_service", skip_all)]
+    pub fn handle_connection(&self, mut stream: impl Stream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.timeouts);
+        let buf_reader = BufReader::new(TimedStream::new(&mut stream, deadlines.read()));
         let data: Vec<String> = buf_reader
             .lines()
-            .map(|line| line.unwrap())
-            .take_while(|line| !line.is_empty())
-            .collect();
+            .take_while(|line| !matches!(line, Ok(line) if line.is_empty()))
+            .collect::<io::Result<_>>()?;
 
         tracing::info!("Received data: {:#?}", data);
         thread::sleep(self.delay);
 
         let data: String = data.join("\n") + "\n";
-        stream.write_all(data.as_bytes())?;
+        TimedStream::new(&mut stream, deadlines.write()).write_all(data.as_bytes())?;
 
         stream.shutdown(Shutdown::Both)
     }
 }
 
 impl Service for DelayedEchoService {
-    fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
+    fn handle_connection(&self, stream: impl Stream) -> io::Result<()> {
         self.handle_connection(stream)
     }
 }
 
+#[cfg(feature = "tokio")]
+impl AsyncService for DelayedEchoService {
+    #[instrument(name = "echo_service", skip_all)]
+    async fn handle_connection(&self, mut stream: impl AsyncStream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.timeouts);
+        let data: Vec<String> = with_deadline(deadlines.read(), async {
+            let mut lines = tokio::io::BufReader::new(&mut stream).lines();
+            let mut data: Vec<String> = Vec::new();
+            while let Some(line) = lines.next_line().await? {
+                if line.is_empty() {
+                    break;
+                }
+                data.push(line);
+            }
+            Ok(data)
+        })
+        .await?;
+
+        tracing::info!("Received data: {:#?}", data);
+        tokio::time::sleep(self.delay).await;
+
+        let data: String = data.join("\n") + "\n";
+        with_deadline(deadlines.write(), stream.write_all(data.as_bytes())).await?;
+
+        stream.shutdown().await
+    }
+}
+
 /// Service that implements a simple file transfer protocol.
 #[derive(Debug)]
 pub struct FileTransferService {
     base_dir: PathBuf,
     protocol_version: u32,
     chunk_size: usize,
+    timeouts: ConnectionTimeouts,
 }
 
 impl FileTransferService {
@@ -69,49 +457,92 @@ impl FileTransferService {
             base_dir: base_dir.into(),
             protocol_version,
             chunk_size,
+            timeouts: ConnectionTimeouts::default(),
+        }
+    }
+
+    /// Limits how long a connection may take. The handshake ends once the `FileQuery`
+    /// has been received.
+    pub fn with_timeouts(mut self, timeouts: ConnectionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Serves connections over TLS with the given configuration (see [`crate::tls`]).
+    /// The TLS handshake counts towards the handshake timeout.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(self, config: Arc<rustls::ServerConfig>) -> TlsFileTransferService {
+        TlsFileTransferService {
+            inner: self,
+            config,
         }
     }
 
     /// Handles a single file transfer connection.
     /// The connection is expected to follow the file transfer protocol.
-    #[instrument(name = "file_transfer_service", skip_all, fields(peer = ?stream.peer_addr().ok()))]
-    pub fn handle_connection(&self, stream: &mut TcpStream) -> io::Result<()> {
+    #[instrument(name = "file_transfer_service", skip_all)]
+    pub fn handle_connection(&self, stream: &mut impl Stream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.timeouts);
+        let mut stream = TimedStream::new(stream, None);
+
+        self.transfer(&mut stream, deadlines)
+    }
+
+    /// Runs the file transfer protocol over an established connection.
+    fn transfer(&self, stream: &mut impl Transport, mut deadlines: Deadlines) -> io::Result<()> {
         // 1. Read FileQuery message
-        let query: FileQuery = self.read_file_query(stream)?;
+        let query: FileQuery = self.read_file_query(stream, deadlines.read())?;
         tracing::debug!(file_query = ?query, "Received FileQuery");
-        self.verify_protocol_version(stream, &query)?;
+        deadlines.finish_handshake();
+        self.verify_protocol_version(stream, &query, deadlines.write())?;
 
         // 2. Write FileResponse message
         // TODO: !Possible directory traversal here!
         let file_path: PathBuf = self.base_dir.join(&query.filename);
-        self.write_file_response(stream, &file_path)?;
+        self.write_file_response(stream, &file_path, deadlines.write())?;
 
         // 3. Read TransferAck message
-        let ack: TransferAck = self.read_transfer_ack(stream)?;
+        let ack: TransferAck = self.read_transfer_ack(stream, deadlines.read())?;
         tracing::debug!(ack_status = ?AckStatus::try_from(ack.status).unwrap(), "Received ClientAck");
 
         // 4. Write FileChunk messages if the client accepted the file
         if ack.status == AckStatus::Accepted as i32 {
-            self.write_file_chunks(stream, &file_path)?;
+            self.write_file_chunks(stream, &file_path, deadlines.write())?;
             tracing::debug!("File transfer complete");
         }
         tracing::debug!("Shutting down connection");
 
-        self.shutdown(stream)
+        stream.shutdown()
+    }
+
+    /// Tells the client that the server is busy without waiting for its query.
+    #[instrument(name = "file_transfer_service", skip_all)]
+    pub fn reject_connection(&self, stream: &mut impl Stream) -> io::Result<()> {
+        // Discard the query if it has already arrived: closing a socket with unread data
+        // resets the connection, and the client could lose the response
+        stream.set_nonblocking(true)?;
+        let mut buf: [u8; 1024] = [0; 1024];
+        while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+        stream.set_nonblocking(false)?;
+
+        write_message(stream, &error_response(Kind::ServerBusy, "Server is busy"))?;
+        stream.shutdown(Shutdown::Write)
     }
 
     /// Reads a `FileQuery` message from the stream.
     ///
     /// The message is expected to be length-delimited (with a 4-byte big-endian length prefix).
-    /// If decoding fails, the connection is shut down and an error is returned.
-    fn read_file_query(&self, stream: &mut TcpStream) -> io::Result<FileQuery> {
+    /// If decoding fails or the deadline passes, the connection is shut down and an error is returned.
+    fn read_file_query(
+        &self,
+        stream: &mut impl Transport,
+        deadline: Option<Deadline>,
+    ) -> io::Result<FileQuery> {
+        stream.set_deadline(deadline);
         read_message::<FileQuery>(stream).or_else(|e| {
-            self.shutdown(stream)?;
+            stream.shutdown()?;
 
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to read FileQuery: {}", e),
-            ))
+            Err(read_error("FileQuery", e))
         })
     }
 
@@ -119,13 +550,14 @@ impl FileTransferService {
     ///
     /// If the versions do not match, an error message is sent (with `UNSUPPORTED_VERSION`)
     /// and the connection is closed. An error is returned in this case.
-    fn verify_protocol_version(&self, stream: &mut TcpStream, query: &FileQuery) -> io::Result<()> {
-        if query.version != self.protocol_version {
-            let message: String = format!(
-                "Protocol version mismatch: server={:?}, client={:?}",
-                self.protocol_version, query.version,
-            );
-            self.write_error_and_shutdown(stream, Kind::UnsupportedVersion, &message)?;
+    fn verify_protocol_version(
+        &self,
+        stream: &mut impl Transport,
+        query: &FileQuery,
+        deadline: Option<Deadline>,
+    ) -> io::Result<()> {
+        if let Some(message) = self.version_mismatch(query) {
+            self.write_error_and_shutdown(stream, Kind::UnsupportedVersion, &message, deadline)?;
 
             Err(io::Error::new(io::ErrorKind::Unsupported, message))?
         }
@@ -139,7 +571,27 @@ impl FileTransferService {
     /// Otherwise, the status will be `NOT_FOUND`. If the file does not exist, the connection is
     /// shut down and an error is returned. The response is sent as a length-delimited
     /// message (with a 4-byte big-endian length prefix).
-    fn write_file_response(&self, stream: &mut TcpStream, file_path: &Path) -> io::Result<()> {
+    fn write_file_response(
+        &self,
+        stream: &mut impl Transport,
+        file_path: &Path,
+        deadline: Option<Deadline>,
+    ) -> io::Result<()> {
+        let response: FileResponse = self.file_response(file_path);
+        stream.set_deadline(deadline);
+        write_message(stream, &response)?;
+
+        if !is_file_found(&response) {
+            stream.shutdown()?;
+
+            Err(file_not_found(file_path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds a `FileResponse` message with the metadata of the requested file.
+    fn file_response(&self, file_path: &Path) -> FileResponse {
         let metadata: Option<Metadata> = fs::metadata(file_path).ok();
         let file_metadata = FileMetadata {
             status: match metadata {
@@ -148,35 +600,36 @@ impl FileTransferService {
             },
             file_size: metadata.as_ref().map_or(0, |m| m.len()),
         };
-        let response = FileResponse {
-            response: Some(Response::Metadata(file_metadata)),
-        };
-        write_message(stream, &response)?;
-
-        if metadata.is_none() {
-            self.shutdown(stream)?;
 
-            Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("File not found: {:?}", file_path),
-            ))?;
+        FileResponse {
+            response: Some(Response::Metadata(file_metadata)),
         }
+    }
 
-        Ok(())
+    /// Returns an error message if the protocol version in the `FileQuery` does not match.
+    fn version_mismatch(&self, query: &FileQuery) -> Option<String> {
+        (query.version != self.protocol_version).then(|| {
+            format!(
+                "Protocol version mismatch: server={:?}, client={:?}",
+                self.protocol_version, query.version,
+            )
+        })
     }
 
     /// Reads a `TransferAck` message from the stream.
     ///
     /// The message is expected to be length-delimited (with a 4-byte big-endian length prefix).
-    /// If decoding fails, the connection is shut down and an error is returned.
-    fn read_transfer_ack(&self, stream: &mut TcpStream) -> io::Result<TransferAck> {
+    /// If decoding fails or the deadline passes, the connection is shut down and an error is returned.
+    fn read_transfer_ack(
+        &self,
+        stream: &mut impl Transport,
+        deadline: Option<Deadline>,
+    ) -> io::Result<TransferAck> {
+        stream.set_deadline(deadline);
         read_message::<TransferAck>(stream).or_else(|e| {
-            self.shutdown(stream)?;
+            stream.shutdown()?;
 
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to read TransferAck: {}", e),
-            ))
+            Err(read_error("TransferAck", e))
         })
     }
 
@@ -185,8 +638,14 @@ impl FileTransferService {
     /// The file is opened for reading and is split into chunks of size `self.chunk_size`.
     /// Each chunk is wrapped in a `FileChunk` message and sent using a length-delimited format.
     /// Once all chunks have been sent, the writer is flushed.
-    fn write_file_chunks(&self, stream: &mut TcpStream, file_path: &Path) -> io::Result<()> {
-        let mut writer: BufWriter<&mut TcpStream> = BufWriter::new(stream);
+    fn write_file_chunks(
+        &self,
+        stream: &mut impl Transport,
+        file_path: &Path,
+        deadline: Option<Deadline>,
+    ) -> io::Result<()> {
+        stream.set_deadline(deadline);
+        let mut writer = BufWriter::new(stream);
         let mut file: BufReader<File> = BufReader::new(File::open(file_path)?);
 
         let mut index: u32 = 0;
@@ -209,34 +668,338 @@ impl FileTransferService {
     }
 
     /// Sends an error message to the client and then shuts down the connection.
-    fn write_error_and_shutdown(&self, stream: &mut TcpStream, kind: Kind, message: &str) -> io::Result<()> {
-        let error_info = ErrorDetails {
-            kind: kind as i32,
-            message: message.to_string(),
-        };
-        let response = FileResponse {
-            response: Some(Response::Error(error_info)),
-        };
-        write_message(stream, &response)?;
+    fn write_error_and_shutdown(
+        &self,
+        stream: &mut impl Transport,
+        kind: Kind,
+        message: &str,
+        deadline: Option<Deadline>,
+    ) -> io::Result<()> {
+        stream.set_deadline(deadline);
+        write_message(stream, &error_response(kind, message))?;
 
-        self.shutdown(stream)
+        stream.shutdown()
     }
 
     /// Shuts down the connection.
-    fn shutdown(&self, stream: &mut TcpStream) -> io::Result<()> {
+    fn shutdown(&self, stream: &impl Stream) -> io::Result<()> {
         stream.shutdown(Shutdown::Both)
     }
 }
 
 impl Service for FileTransferService {
-    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+    fn handle_connection(&self, mut stream: impl Stream) -> io::Result<()> {
         self.handle_connection(&mut stream)
     }
+
+    fn reject_connection(&self, mut stream: impl Stream) -> io::Result<()> {
+        self.reject_connection(&mut stream)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncService for FileTransferService {
+    #[instrument(name = "file_transfer_service", skip_all)]
+    async fn handle_connection(&self, stream: impl AsyncStream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.timeouts);
+
+        self.transfer_async(stream, deadlines).await
+    }
+}
+
+/// A [`FileTransferService`] that serves its connections over TLS, made with
+/// [`FileTransferService::with_tls`]. It is not a [`NonBlockingService`], so the event-driven
+/// servers do not accept it.
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+pub struct TlsFileTransferService {
+    inner: FileTransferService,
+    config: Arc<rustls::ServerConfig>,
+}
+
+/// Rejected connections are just closed: a plain text response means nothing to a TLS client.
+#[cfg(feature = "tls")]
+impl Service for TlsFileTransferService {
+    #[instrument(name = "file_transfer_service", skip_all)]
+    fn handle_connection(&self, mut stream: impl Stream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.inner.timeouts);
+        let connection = rustls::ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        let stream = TimedStream::new(&mut stream, None);
+
+        self.inner
+            .transfer(&mut rustls::StreamOwned::new(connection, stream), deadlines)
+    }
+}
+
+#[cfg(all(feature = "tls", feature = "tokio"))]
+impl AsyncService for TlsFileTransferService {
+    #[instrument(name = "file_transfer_service", skip_all)]
+    async fn handle_connection(&self, stream: impl AsyncStream) -> io::Result<()> {
+        let deadlines: Deadlines = Deadlines::start(&self.inner.timeouts);
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::clone(&self.config));
+        let stream = with_deadline(deadlines.read(), acceptor.accept(stream)).await?;
+
+        self.inner.transfer_async(stream, deadlines).await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl FileTransferService {
+    /// Runs the same protocol steps as [`FileTransferService::handle_connection`] on the tokio runtime,
+    /// so that both produce byte-identical transfers.
+    async fn transfer_async(
+        &self,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin + Send,
+        mut deadlines: Deadlines,
+    ) -> io::Result<()> {
+        // 1. Read FileQuery message
+        let query: FileQuery = match with_deadline(deadlines.read(), read_message_async(&mut stream)).await {
+            Ok(query) => query,
+            Err(e) => {
+                stream.shutdown().await?;
+                Err(read_error("FileQuery", e))?
+            }
+        };
+        tracing::debug!(file_query = ?query, "Received FileQuery");
+        deadlines.finish_handshake();
+
+        if let Some(message) = self.version_mismatch(&query) {
+            let response: FileResponse = error_response(Kind::UnsupportedVersion, &message);
+            with_deadline(deadlines.write(), write_message_async(&mut stream, &response)).await?;
+            stream.shutdown().await?;
+
+            Err(io::Error::new(io::ErrorKind::Unsupported, message))?
+        }
+
+        // 2. Write FileResponse message
+        // TODO: !Possible directory traversal here!
+        let file_path: PathBuf = self.base_dir.join(&query.filename);
+        let response: FileResponse = self.file_response(&file_path);
+        with_deadline(deadlines.write(), write_message_async(&mut stream, &response)).await?;
+
+        if !is_file_found(&response) {
+            stream.shutdown().await?;
+
+            Err(file_not_found(&file_path))?;
+        }
+
+        // 3. Read TransferAck message
+        let ack: TransferAck = match with_deadline(deadlines.read(), read_message_async(&mut stream)).await {
+            Ok(ack) => ack,
+            Err(e) => {
+                stream.shutdown().await?;
+                Err(read_error("TransferAck", e))?
+            }
+        };
+        tracing::debug!(ack_status = ?AckStatus::try_from(ack.status), "Received ClientAck");
+
+        // 4. Write FileChunk messages if the client accepted the file
+        if ack.status == AckStatus::Accepted as i32 {
+            let mut writer = tokio::io::BufWriter::new(&mut stream);
+            let mut file = tokio::io::BufReader::new(tokio::fs::File::open(&file_path).await?);
+
+            with_deadline(deadlines.write(), async {
+                let mut index: u32 = 0;
+                let mut buf: Vec<u8> = vec![0; self.chunk_size];
+
+                loop {
+                    let bytes_read: usize = file.read(&mut buf).await?;
+                    if bytes_read == 0 {
+                        break; // EOF
+                    }
+                    let file_chunk = FileChunk {
+                        index,
+                        data: buf[..bytes_read].to_vec(),
+                    };
+                    write_message_async(&mut writer, &file_chunk).await?;
+                    index += 1;
+                }
+                writer.flush().await
+            })
+            .await?;
+            tracing::debug!("File transfer complete");
+        }
+        tracing::debug!("Shutting down connection");
+
+        stream.shutdown().await
+    }
+}
+
+/// Step of the file transfer protocol a non-blocking connection is at.
+#[derive(Debug)]
+enum Phase {
+    ReadQuery,
+    WriteResponse {
+        file_path: PathBuf,
+        /// Whether the response tells the client that the file was found
+        found: bool,
+    },
+    ReadAck {
+        file_path: PathBuf,
+    },
+    WriteChunks {
+        file: BufReader<File>,
+        index: u32,
+    },
+    /// Flush the pending output, shut down the connection and report the result.
+    Close {
+        result: io::Result<()>,
+    },
+}
+
+/// Per-connection state of the non-blocking file transfer protocol.
+#[derive(Debug)]
+pub struct FileTransferState {
+    phase: Phase,
+    read_buf: Vec<u8>,
+    write_buf: Vec<u8>,
+    written: usize,
+    deadlines: Deadlines,
+    /// Deadline for the message being read, set when the connection starts waiting for it
+    read_deadline: Option<Deadline>,
+}
+
+impl FileTransferState {
+    fn deadline(&self) -> Option<Deadline> {
+        match self.phase {
+            Phase::ReadQuery | Phase::ReadAck { .. } => self.read_deadline,
+            _ => self.deadlines.write(),
+        }
+    }
+}
+
+impl NonBlockingService for FileTransferService {
+    type State = FileTransferState;
+
+    fn init(&self, _stream: &impl Stream) -> Self::State {
+        let deadlines: Deadlines = Deadlines::start(&self.timeouts);
+
+        FileTransferState {
+            phase: Phase::ReadQuery,
+            read_buf: Vec::new(),
+            write_buf: Vec::new(),
+            written: 0,
+            read_deadline: deadlines.read(),
+            deadlines,
+        }
+    }
+
+    /// Runs the same protocol steps as [`FileTransferService::handle_connection`],
+    /// returning to the caller whenever the socket is not ready.
+    #[instrument(name = "file_transfer_service", skip_all)]
+    fn resume(&self, state: &mut Self::State, stream: &mut impl Stream) -> io::Result<Interest> {
+        if let Some(deadline) = state.deadline().filter(Deadline::has_passed) {
+            let _ = self.shutdown(stream);
+            return Err(deadline.timed_out());
+        }
+
+        let mut chunks_read: usize = 0;
+        loop {
+            // Pending output must be flushed before moving on to the next step
+            if !try_flush(stream, &state.write_buf, &mut state.written)? {
+                return Ok(Interest::Write);
+            }
+            state.write_buf.clear();
+            state.written = 0;
+
+            let phase: Phase = match &mut state.phase {
+                Phase::ReadQuery => match try_read_message::<FileQuery>(stream, &mut state.read_buf) {
+                    Ok(None) => return Ok(Interest::Read),
+                    Ok(Some(query)) => {
+                        tracing::debug!(file_query = ?query, "Received FileQuery");
+                        state.deadlines.finish_handshake();
+
+                        match self.version_mismatch(&query) {
+                            Some(message) => {
+                                state.write_buf =
+                                    encode_message(&error_response(Kind::UnsupportedVersion, &message));
+                                let e = io::Error::new(io::ErrorKind::Unsupported, message);
+                                Phase::Close { result: Err(e) }
+                            }
+                            None => {
+                                // TODO: !Possible directory traversal here!
+                                let file_path: PathBuf = self.base_dir.join(&query.filename);
+                                let response: FileResponse = self.file_response(&file_path);
+                                state.write_buf = encode_message(&response);
+                                Phase::WriteResponse {
+                                    file_path,
+                                    found: is_file_found(&response),
+                                }
+                            }
+                        }
+                    }
+                    Err(e) => Phase::Close {
+                        result: Err(read_error("FileQuery", e)),
+                    },
+                },
+                Phase::WriteResponse { file_path, found: true } => {
+                    state.read_deadline = state.deadlines.read();
+                    Phase::ReadAck {
+                        file_path: std::mem::take(file_path),
+                    }
+                }
+                Phase::WriteResponse { file_path, found: false } => Phase::Close {
+                    result: Err(file_not_found(file_path)),
+                },
+                Phase::ReadAck { file_path } => {
+                    match try_read_message::<TransferAck>(stream, &mut state.read_buf) {
+                        Ok(None) => return Ok(Interest::Read),
+                        Ok(Some(ack)) if ack.status == AckStatus::Accepted as i32 => {
+                            tracing::debug!(ack_status = ?AckStatus::Accepted, "Received ClientAck");
+                            Phase::WriteChunks {
+                                file: BufReader::new(File::open(&file_path)?),
+                                index: 0,
+                            }
+                        }
+                        Ok(Some(ack)) => {
+                            tracing::debug!(ack_status = ?AckStatus::try_from(ack.status), "Received ClientAck");
+                            Phase::Close { result: Ok(()) }
+                        }
+                        Err(e) => Phase::Close {
+                            result: Err(read_error("TransferAck", e)),
+                        },
+                    }
+                }
+                // The socket is still writable, so the transfer is resumed on the next turn of the reactor
+                Phase::WriteChunks { .. } if chunks_read == CHUNKS_PER_RESUME => return Ok(Interest::Write),
+                Phase::WriteChunks { file, index } => {
+                    chunks_read += 1;
+                    let mut buf: Vec<u8> = vec![0; self.chunk_size];
+                    let bytes_read: usize = file.read(&mut buf)?;
+                    if bytes_read == 0 {
+                        tracing::debug!("File transfer complete");
+                        Phase::Close { result: Ok(()) }
+                    } else {
+                        buf.truncate(bytes_read);
+                        state.write_buf = encode_message(&FileChunk {
+                            index: *index,
+                            data: buf,
+                        });
+                        *index += 1;
+                        continue;
+                    }
+                }
+                Phase::Close { result } => {
+                    tracing::debug!("Shutting down connection");
+                    // Shutting down a socket the peer has already closed is not an error here
+                    let _ = self.shutdown(stream);
+                    let result: io::Result<()> = std::mem::replace(result, Ok(()));
+
+                    return result.map(|_| Interest::Close);
+                }
+            };
+            state.phase = phase;
+        }
+    }
+
+    fn deadline(&self, state: &Self::State) -> Option<Instant> {
+        state.deadline().map(|deadline| deadline.at)
+    }
 }
 
 /// A client part of the file transfer protocol.
 pub struct FileTransferClient {
-    stream: TcpStream,
+    stream: ClientStream,
     protocol_version: u32,
 }
 
@@ -244,7 +1007,39 @@ impl FileTransferClient {
     /// Connects to the server at the specified address and returns a new `FileTransferClient`.
     pub fn connect(addr: impl ToSocketAddrs, protocol_version: u32) -> io::Result<Self> {
         Ok(Self {
-            stream: TcpStream::connect(addr)?,
+            stream: ClientStream::Plain(SocketStream::Tcp(TcpStream::connect(addr)?)),
+            protocol_version,
+        })
+    }
+
+    /// Connects to the server listening on a Unix socket.
+    pub fn connect_unix(addr: &UnixAddr, protocol_version: u32) -> io::Result<Self> {
+        Ok(Self {
+            stream: ClientStream::Plain(SocketStream::connect_unix(addr)?),
+            protocol_version,
+        })
+    }
+
+    /// Connects to the server at the specified address over TLS (see [`crate::tls`]).
+    ///
+    /// `server_name` is sent to the server via SNI and must match its certificate.
+    /// The TLS handshake completes with the first request.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        addr: impl ToSocketAddrs,
+        protocol_version: u32,
+        config: Arc<rustls::ClientConfig>,
+        server_name: &str,
+    ) -> io::Result<Self> {
+        let server_name = rustls::pki_types::ServerName::try_from(server_name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let connection = rustls::ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+
+        Ok(Self {
+            stream: ClientStream::Tls(Box::new(rustls::StreamOwned::new(
+                connection,
+                TcpStream::connect(addr)?,
+            ))),
             protocol_version,
         })
     }
@@ -305,6 +1100,317 @@ impl FileTransferClient {
     }
 }
 
+/// The connection of a [`FileTransferClient`].
+enum ClientStream {
+    Plain(SocketStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            ClientStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Builds a `FileResponse` message with error details.
+fn error_response(kind: Kind, message: &str) -> FileResponse {
+    let error_info = ErrorDetails {
+        kind: kind as i32,
+        message: message.to_string(),
+    };
+
+    FileResponse {
+        response: Some(Response::Error(error_info)),
+    }
+}
+
+/// Returns `true` if the response says that the requested file exists.
+fn is_file_found(response: &FileResponse) -> bool {
+    matches!(response.response, Some(Response::Metadata(metadata)) if metadata.status == Status::Found as i32)
+}
+
+fn file_not_found(file_path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("File not found: {:?}", file_path),
+    )
+}
+
+/// Wraps an error that occurred while reading the named message. Timeouts keep their kind,
+/// everything else is reported as invalid data.
+fn read_error(message_name: &str, e: io::Error) -> io::Error {
+    let kind: io::ErrorKind = match e.kind() {
+        io::ErrorKind::TimedOut => io::ErrorKind::TimedOut,
+        _ => io::ErrorKind::InvalidData,
+    };
+
+    io::Error::new(kind, format!("Failed to read {}: {}", message_name, e))
+}
+
+/// The deadlines of a single connection, derived from [`ConnectionTimeouts`]
+/// when the service starts handling it.
+#[derive(Debug, Clone)]
+struct Deadlines {
+    handshake: Option<Instant>,
+    read_idle: Option<Duration>,
+    total: Option<Instant>,
+}
+
+impl Deadlines {
+    fn start(timeouts: &ConnectionTimeouts) -> Self {
+        let now: Instant = Instant::now();
+
+        Self {
+            // A timeout too large to represent is no limit at all
+            handshake: timeouts.handshake.and_then(|t| now.checked_add(t)),
+            read_idle: timeouts.read_idle,
+            total: timeouts.total.and_then(|t| now.checked_add(t)),
+        }
+    }
+
+    fn finish_handshake(&mut self) {
+        self.handshake = None;
+    }
+
+    /// Returns the deadline for a message the server starts waiting for now.
+    fn read(&self) -> Option<Deadline> {
+        let idle: Option<Instant> = self.read_idle.and_then(|t| Instant::now().checked_add(t));
+
+        [
+            self.handshake.map(|at| Deadline {
+                at,
+                what: "Handshake",
+            }),
+            idle.map(|at| Deadline { at, what: "Read" }),
+            self.write(),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|deadline| deadline.at)
+    }
+
+    /// Returns the deadline for sending data to the client.
+    fn write(&self) -> Option<Deadline> {
+        self.total.map(|at| Deadline {
+            at,
+            what: "Connection",
+        })
+    }
+}
+
+/// A point in time by which an operation must complete, with what it limits for error messages
+#[derive(Debug, Copy, Clone)]
+struct Deadline {
+    at: Instant,
+    what: &'static str,
+}
+
+impl Deadline {
+    fn has_passed(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    /// Returns the time left, or a `TimedOut` error if there is none.
+    fn remaining(&self) -> io::Result<Duration> {
+        match self.at.saturating_duration_since(Instant::now()) {
+            Duration::ZERO => Err(self.timed_out()),
+            remaining => Ok(remaining),
+        }
+    }
+
+    fn timed_out(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, format!("{} timed out", self.what))
+    }
+}
+
+/// A blocking stream whose reads and writes fail with `TimedOut` once the deadline passes.
+/// The socket timeouts are set to the remaining time before every call, so that the deadline
+/// holds for the whole message rather than for each call.
+struct TimedStream<'a, T: Stream> {
+    stream: &'a mut T,
+    deadline: Option<Deadline>,
+}
+
+impl<'a, T: Stream> TimedStream<'a, T> {
+    fn new(stream: &'a mut T, deadline: Option<Deadline>) -> Self {
+        Self { stream, deadline }
+    }
+
+    /// Maps the error a socket timeout produces to the deadline's own error.
+    fn check(&self, result: io::Result<usize>) -> io::Result<usize> {
+        match (result, self.deadline) {
+            (Err(e), Some(deadline)) if e.kind() == io::ErrorKind::WouldBlock => Err(deadline.timed_out()),
+            (result, _) => result,
+        }
+    }
+}
+
+impl<T: Stream> Read for TimedStream<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout: Option<Duration> = self.deadline.map(|d| d.remaining()).transpose()?;
+        self.stream.set_read_timeout(timeout)?;
+
+        let result: io::Result<usize> = self.stream.read(buf);
+        self.check(result)
+    }
+}
+
+impl<T: Stream> Write for TimedStream<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let timeout: Option<Duration> = self.deadline.map(|d| d.remaining()).transpose()?;
+        self.stream.set_write_timeout(timeout)?;
+
+        let result: io::Result<usize> = self.stream.write(buf);
+        self.check(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A blocking connection the file transfer protocol runs over: a plain socket or TLS on top of it.
+trait Transport: Read + Write {
+    /// Sets the deadline for the following reads and writes.
+    fn set_deadline(&mut self, deadline: Option<Deadline>);
+
+    /// Closes the connection in both directions.
+    fn shutdown(&mut self) -> io::Result<()>;
+}
+
+impl<T: Stream> Transport for TimedStream<'_, T> {
+    fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.deadline = deadline;
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl<T: Stream> Transport for rustls::StreamOwned<rustls::ServerConnection, TimedStream<'_, T>> {
+    fn set_deadline(&mut self, deadline: Option<Deadline>) {
+        self.sock.set_deadline(deadline);
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        // The close_notify alert is best effort: the peer may be gone or the deadline may have passed.
+        // Pending records are written directly, as flushing would wait for an unfinished handshake
+        self.conn.send_close_notify();
+        while self.conn.wants_write() {
+            match self.conn.write_tls(&mut self.sock) {
+                Ok(n) if n > 0 => {}
+                _ => break,
+            }
+        }
+
+        self.sock.shutdown()
+    }
+}
+
+/// Runs `future` and fails with `TimedOut` if the deadline passes first.
+#[cfg(feature = "tokio")]
+async fn with_deadline<T>(
+    deadline: Option<Deadline>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(tokio::time::Instant::from_std(deadline.at), future)
+            .await
+            .unwrap_or_else(|_| Err(deadline.timed_out())),
+        None => future.await,
+    }
+}
+
+/// Reads whatever is available from a non-blocking reader into `buf` and decodes
+/// a length-delimited message once it has been received completely.
+///
+/// Returns `Ok(None)` if the reader would block before the message is complete.
+/// Bytes following the message are left in `buf` for the next call. Fails for messages
+/// longer than [`MAX_MESSAGE_LEN`], before buffering them.
+fn try_read_message<M: Message + Default>(
+    reader: &mut impl Read,
+    buf: &mut Vec<u8>,
+) -> io::Result<Option<M>> {
+    let mut read_buf: [u8; 4096] = [0; 4096];
+
+    loop {
+        if let Some(len_buf) = buf.first_chunk::<4>() {
+            let message_len: usize = u32::from_be_bytes(*len_buf) as usize;
+            if message_len > MAX_MESSAGE_LEN {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Message of {} bytes exceeds the limit of {} bytes",
+                        message_len, MAX_MESSAGE_LEN
+                    ),
+                ))?
+            }
+            let frame_len: usize = 4 + message_len;
+            if buf.len() >= frame_len {
+                let message_buf: Vec<u8> = buf.drain(..frame_len).skip(4).collect();
+                return decode_message(&message_buf).map(Some);
+            }
+        }
+
+        match reader.read(&mut read_buf) {
+            Ok(0) => Err(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+            Ok(n) => buf.extend_from_slice(&read_buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+}
+
+/// Writes `buf[*written..]` to a non-blocking writer.
+/// Returns `Ok(false)` if the writer would block before everything has been written.
+fn try_flush(writer: &mut impl Write, buf: &[u8], written: &mut usize) -> io::Result<bool> {
+    while *written < buf.len() {
+        match writer.write(&buf[*written..]) {
+            Ok(0) => Err(io::Error::from(io::ErrorKind::WriteZero))?,
+            Ok(n) => *written += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => Err(e)?,
+        }
+    }
+
+    Ok(true)
+}
+
+/// Encodes a message in the length-delimited format used by [`write_message`].
+fn encode_message(message: &impl Message) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    write_message(&mut buf, message).expect("Writing to a Vec cannot fail");
+
+    buf
+}
+
 /// Reads a length-delimited message from the reader.
 /// The message must be prefixed with a 4-byte length (big-endian):
 ///
@@ -317,7 +1423,12 @@ fn read_message<M: Message + Default>(reader: &mut impl Read) -> io::Result<M> {
     let mut message_buf: Vec<u8> = vec![0; message_len as usize];
     reader.read_exact(&mut message_buf)?;
 
-    M::decode(&*message_buf).map_err(|e| {
+    decode_message(&message_buf)
+}
+
+/// Decodes a Protobuf message (without the length prefix).
+fn decode_message<M: Message + Default>(message_buf: &[u8]) -> io::Result<M> {
+    M::decode(message_buf).map_err(|e| {
         io::Error::new(
             io::ErrorKind::InvalidData,
             format!("Failed to decode Protobuf message: {}", e),
@@ -325,6 +1436,28 @@ fn read_message<M: Message + Default>(reader: &mut impl Read) -> io::Result<M> {
     })
 }
 
+/// Async version of [`read_message`].
+#[cfg(feature = "tokio")]
+async fn read_message_async<M: Message + Default>(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<M> {
+    let mut len_buf: [u8; 4] = [0; 4];
+    reader.read_exact(&mut len_buf).await?;
+    let message_len = u32::from_be_bytes(len_buf);
+
+    let mut message_buf: Vec<u8> = vec![0; message_len as usize];
+    reader.read_exact(&mut message_buf).await?;
+
+    decode_message(&message_buf)
+}
+
+/// Async version of [`write_message`].
+#[cfg(feature = "tokio")]
+async fn write_message_async(
+    writer: &mut (impl AsyncWrite + Unpin),
+    message: &impl Message,
+) -> io::Result<()> {
+    writer.write_all(&encode_message(message)).await
+}
+
 /// Writes a length-delimited message to the writer.
 /// The message is encoded to bytes and prefixed with a 4-byte length (big-endian):
 ///
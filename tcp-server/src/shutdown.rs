@@ -0,0 +1,224 @@
+use std::io;
+use std::sync::{Arc, Mutex, Condvar};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::os::fd::{RawFd, AsFd, AsRawFd, FromRawFd as _, OwnedFd};
+use libc::{c_int, c_void, pollfd, POLLIN, EFD_CLOEXEC, SIGINT, SIGTERM, SHUT_RDWR};
+
+/// Default time given to in-flight connections to finish after shutdown has been requested.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// Time given to connection handlers to return after their sockets have been shut down.
+const ABORT_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Descriptor of the eventfd written by the signal handler (-1 if no handler is installed)
+static SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_shutdown_signal(_: c_int) {
+    let fd: RawFd = SIGNAL_FD.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let value: u64 = 1;
+        // SAFETY: `write` is async-signal-safe and `value` outlives the call.
+        unsafe { libc::write(fd, &value as *const u64 as *const c_void, 8) };
+    }
+}
+
+/// Shutdown mechanism shared by all server models.
+///
+/// Shutdown is requested by writing to an eventfd, either from the `SIGINT`/`SIGTERM` handler
+/// or by calling [`Shutdown::trigger`]. The eventfd is never read, so it stays readable and
+/// every thread that polls it wakes up. Forked children inherit the same eventfd, so a single
+/// request reaches the whole process tree.
+///
+/// The handle also tracks in-flight connections, so that servers can wait for them to finish
+/// within the grace period and shut down the sockets of the ones that did not.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    eventfd: OwnedFd,
+    grace_period: Duration,
+    next_id: AtomicU64,
+    /// Duplicated descriptors of the in-flight connection sockets
+    connections: Mutex<HashMap<u64, OwnedFd>>,
+    drained: Condvar,
+}
+
+impl Shutdown {
+    pub fn new(grace_period: Duration) -> io::Result<Self> {
+        let fd: RawFd = match unsafe { libc::eventfd(0, EFD_CLOEXEC) } {
+            -1 => Err(io::Error::last_os_error())?,
+            fd => fd,
+        };
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                // SAFETY: `eventfd` returned a new file descriptor that nothing else owns.
+                eventfd: unsafe { OwnedFd::from_raw_fd(fd) },
+                grace_period,
+                next_id: AtomicU64::new(0),
+                connections: Mutex::new(HashMap::new()),
+                drained: Condvar::new(),
+            }),
+        })
+    }
+
+    /// Installs `SIGINT` and `SIGTERM` handlers that trigger this shutdown.
+    /// Only one `Shutdown` can be connected to the signals at a time.
+    pub fn listen_for_signals(&self) -> io::Result<()> {
+        SIGNAL_FD.store(self.as_raw_fd(), Ordering::Relaxed);
+
+        for signal in [SIGINT, SIGTERM] {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = handle_shutdown_signal as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+
+            if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } != 0 {
+                Err(io::Error::last_os_error())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Requests shutdown.
+    pub fn trigger(&self) {
+        let value: u64 = 1;
+        unsafe { libc::write(self.as_raw_fd(), &value as *const u64 as *const c_void, 8) };
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.wait(Some(Duration::ZERO))
+    }
+
+    /// Waits until shutdown is requested or the timeout expires.
+    /// Returns `true` if shutdown has been requested.
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let deadline: Option<Instant> = timeout.map(|t| Instant::now() + t);
+
+        loop {
+            let timeout: Option<Duration> = deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            match poll_readable(&[self.as_raw_fd()], timeout) {
+                Ok(ready) => return ready[0],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    tracing::error!("Failed to poll the shutdown eventfd: {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.inner.grace_period
+    }
+
+    /// Returns the point in time by which in-flight connections must finish,
+    /// counting the grace period from now.
+    pub fn deadline(&self) -> Instant {
+        Instant::now() + self.inner.grace_period
+    }
+
+    /// Registers an in-flight connection. It stays registered until the guard is dropped.
+    pub fn track(&self, socket: &impl AsFd) -> ConnectionGuard {
+        let id: u64 = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        match socket.as_fd().try_clone_to_owned() {
+            Ok(socket) => {
+                self.inner.connections.lock().unwrap().insert(id, socket);
+            }
+            Err(e) => tracing::warn!("Failed to track a connection: {}", e),
+        }
+
+        ConnectionGuard {
+            shutdown: self.clone(),
+            id,
+        }
+    }
+
+    /// Returns the number of in-flight connections.
+    pub fn active_connections(&self) -> usize {
+        self.inner.connections.lock().unwrap().len()
+    }
+
+    /// Waits for in-flight connections to finish until the deadline. Connections that are still
+    /// open afterwards are shut down, which makes their pending reads and writes fail.
+    pub fn drain_connections(&self, deadline: Instant) {
+        if !self.wait_for_connections(deadline) {
+            let connections = self.inner.connections.lock().unwrap();
+            tracing::warn!(
+                remaining = connections.len(),
+                "Grace period expired. Aborting connections"
+            );
+
+            connections.values().for_each(|socket| {
+                unsafe { libc::shutdown(socket.as_raw_fd(), SHUT_RDWR) };
+            });
+            drop(connections);
+
+            self.wait_for_connections(Instant::now() + ABORT_GRACE_PERIOD);
+        }
+    }
+
+    /// Waits until there are no in-flight connections. Returns `false` if the deadline passed first.
+    fn wait_for_connections(&self, deadline: Instant) -> bool {
+        let mut connections = self.inner.connections.lock().unwrap();
+
+        while !connections.is_empty() {
+            let timeout: Duration = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return false;
+            }
+            connections = self.inner.drained.wait_timeout(connections, timeout).unwrap().0;
+        }
+
+        true
+    }
+}
+
+impl AsRawFd for Shutdown {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.eventfd.as_raw_fd()
+    }
+}
+
+/// Keeps a connection registered in [`Shutdown`] while it is being handled.
+pub struct ConnectionGuard {
+    shutdown: Shutdown,
+    id: u64,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.shutdown.inner.connections.lock().unwrap();
+        connections.remove(&self.id);
+
+        if connections.is_empty() {
+            self.shutdown.inner.drained.notify_all();
+        }
+    }
+}
+
+/// Waits until any of the descriptors becomes readable or the timeout expires (`None` waits forever).
+/// Returns the readiness of each descriptor.
+pub(crate) fn poll_readable(fds: &[RawFd], timeout: Option<Duration>) -> io::Result<Vec<bool>> {
+    let mut pollfds: Vec<pollfd> = fds
+        .iter()
+        .map(|&fd| pollfd {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // Round up, so that a sub-millisecond timeout does not turn into a busy loop
+    let timeout_ms: c_int = timeout.map_or(-1, |t| {
+        t.as_micros().div_ceil(1000).min(c_int::MAX as u128) as c_int
+    });
+
+    match unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout_ms) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(pollfds.iter().map(|p| p.revents != 0).collect()),
+    }
+}
@@ -0,0 +1,338 @@
+use std::{fmt, io, mem};
+use std::cell::Cell;
+use std::sync::{Arc, Condvar, Mutex};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use std::os::unix::net::UnixStream;
+use std::os::fd::{AsFd, AsRawFd as _, BorrowedFd};
+use libc::{c_void, gid_t, pid_t, socklen_t, uid_t, ucred, SOL_SOCKET, SO_PEERCRED};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::listener::UnixAddr;
+
+/// A connection that services handle: a TCP socket or any other blocking byte stream
+/// that can be shut down and knows its peer.
+pub trait Stream: Read + Write {
+    fn peer_addr(&self) -> io::Result<PeerAddr>;
+
+    /// Returns the credentials of the peer process, if the stream is a Unix socket.
+    fn peer_cred(&self) -> io::Result<Option<PeerCred>> {
+        Ok(None)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+
+    /// Makes reads fail with `WouldBlock` or `TimedOut` once `timeout` passes. `None` waits forever.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Makes writes fail with `WouldBlock` or `TimedOut` once `timeout` passes. `None` waits forever.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Makes reads and writes fail with `WouldBlock` instead of waiting.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+/// An async counterpart of [`Stream`] for services running on the tokio runtime.
+/// The connection is shut down with [`tokio::io::AsyncWriteExt::shutdown`].
+#[cfg(feature = "tokio")]
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    fn peer_addr(&self) -> io::Result<PeerAddr>;
+
+    /// Returns the credentials of the peer process, if the stream is a Unix socket.
+    fn peer_cred(&self) -> io::Result<Option<PeerCred>> {
+        Ok(None)
+    }
+}
+
+/// The address of the other end of a [`Stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerAddr {
+    Inet(SocketAddr),
+    /// A Unix socket bound to an address. Clients are usually unnamed.
+    Unix(UnixAddr),
+    /// A peer without an address, such as the other end of an in-memory stream
+    Unnamed,
+}
+
+impl PeerAddr {
+    /// Returns the IP address of the peer, if it has one.
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            PeerAddr::Inet(addr) => Some(addr.ip()),
+            PeerAddr::Unix(_) | PeerAddr::Unnamed => None,
+        }
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Inet(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix(addr) => write!(f, "{}", addr),
+            PeerAddr::Unnamed => write!(f, "(unnamed)"),
+        }
+    }
+}
+
+/// Credentials of the process at the other end of a Unix socket, as they were when it
+/// connected (`SO_PEERCRED`).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PeerCred {
+    pub pid: pid_t,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+pub(crate) fn peer_cred(socket: BorrowedFd<'_>) -> io::Result<PeerCred> {
+    let mut cred: ucred = unsafe { mem::zeroed() };
+    let mut len: socklen_t = mem::size_of::<ucred>() as socklen_t;
+    if unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            SOL_SOCKET,
+            SO_PEERCRED,
+            &mut cred as *mut ucred as *mut c_void,
+            &mut len,
+        )
+    } != 0
+    {
+        Err(io::Error::last_os_error())?;
+    }
+
+    Ok(PeerCred {
+        pid: cred.pid,
+        uid: cred.uid,
+        gid: cred.gid,
+    })
+}
+
+impl Stream for TcpStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        TcpStream::peer_addr(self).map(PeerAddr::Inet)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+impl Stream for UnixStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        let addr = UnixStream::peer_addr(self)?;
+        Ok(UnixAddr::from_std(&addr).map_or(PeerAddr::Unnamed, PeerAddr::Unix))
+    }
+
+    fn peer_cred(&self) -> io::Result<Option<PeerCred>> {
+        peer_cred(self.as_fd()).map(Some)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncStream for tokio::net::TcpStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        tokio::net::TcpStream::peer_addr(self).map(PeerAddr::Inet)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncStream for tokio::net::UnixStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        let addr: std::os::unix::net::SocketAddr = tokio::net::UnixStream::peer_addr(self)?.into();
+        Ok(UnixAddr::from_std(&addr).map_or(PeerAddr::Unnamed, PeerAddr::Unix))
+    }
+
+    fn peer_cred(&self) -> io::Result<Option<PeerCred>> {
+        peer_cred(self.as_fd()).map(Some)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncStream for tokio::io::DuplexStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        Ok(PeerAddr::Unnamed)
+    }
+}
+
+/// Creates a pair of connected in-memory streams: what is written to one is read from the other.
+///
+/// Writes never block, so write timeouts have no effect. Reads block until data arrives, the
+/// other end shuts down its write side or is dropped, or the read timeout passes.
+///
+/// ```
+/// # use std::io::{Read, Write};
+/// # use std::net::Shutdown;
+/// # use tcp_server::stream::{duplex, Stream};
+/// let (mut client, mut server) = duplex();
+/// client.write_all(b"ping").unwrap();
+/// client.shutdown(Shutdown::Write).unwrap();
+///
+/// let mut received: String = String::new();
+/// server.read_to_string(&mut received).unwrap();
+/// assert_eq!(received, "ping");
+/// ```
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let (a, b): (Arc<Pipe>, Arc<Pipe>) = Default::default();
+
+    (
+        DuplexStream::new(Arc::clone(&a), Arc::clone(&b)),
+        DuplexStream::new(b, a),
+    )
+}
+
+/// One end of an in-memory connection created by [`duplex`].
+#[derive(Debug)]
+pub struct DuplexStream {
+    incoming: Arc<Pipe>,
+    outgoing: Arc<Pipe>,
+    read_timeout: Cell<Option<Duration>>,
+    nonblocking: Cell<bool>,
+}
+
+/// Bytes on their way from one end of a duplex stream to the other
+#[derive(Debug, Default)]
+struct Pipe {
+    state: Mutex<PipeState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    /// Set once either end stops using the pipe: no more bytes will be written to it
+    closed: bool,
+}
+
+impl Pipe {
+    fn close(&self, discard: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        if discard {
+            state.buf.clear();
+        }
+        self.changed.notify_all();
+    }
+}
+
+impl DuplexStream {
+    fn new(incoming: Arc<Pipe>, outgoing: Arc<Pipe>) -> Self {
+        Self {
+            incoming,
+            outgoing,
+            read_timeout: Cell::new(None),
+            nonblocking: Cell::new(false),
+        }
+    }
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline: Option<Instant> = self.read_timeout.get().map(|timeout| Instant::now() + timeout);
+        let mut state = self.incoming.state.lock().unwrap();
+
+        while state.buf.is_empty() && !state.closed && !buf.is_empty() {
+            let timeout: Option<Duration> = match self.nonblocking.get() {
+                true => Some(Duration::ZERO),
+                false => deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            };
+            state = match timeout {
+                Some(timeout) if timeout.is_zero() => Err(io::Error::from(io::ErrorKind::WouldBlock))?,
+                Some(timeout) => self.incoming.changed.wait_timeout(state, timeout).unwrap().0,
+                None => self.incoming.changed.wait(state).unwrap(),
+            };
+        }
+
+        state.buf.read(buf)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.outgoing.state.lock().unwrap();
+        if state.closed {
+            Err(io::Error::from(io::ErrorKind::BrokenPipe))?
+        }
+        state.buf.extend(buf);
+        self.outgoing.changed.notify_all();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for DuplexStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        Ok(PeerAddr::Unnamed)
+    }
+
+    /// Shutting down the read side discards unread data and fails further writes of the other end.
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.incoming.close(true);
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.outgoing.close(false);
+        }
+        Ok(())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if timeout == Some(Duration::ZERO) {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot set a 0 duration timeout",
+            ))?
+        }
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+}
+
+impl Drop for DuplexStream {
+    fn drop(&mut self) {
+        let _ = Stream::shutdown(self, Shutdown::Both);
+    }
+}
@@ -0,0 +1,238 @@
+use std::{env, io, thread};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use std::os::unix::net::UnixDatagram;
+use std::os::fd::{RawFd, FromRawFd as _, OwnedFd};
+use libc::{F_SETFD, FD_CLOEXEC};
+
+use crate::listener::{Listener, UnixAddr};
+use crate::shutdown::Shutdown;
+use crate::restart::Restart;
+
+/// First descriptor passed by socket activation (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Returns the listening socket passed by systemd socket activation, if this process was
+/// started for one (`LISTEN_FDS` and `LISTEN_PID`). The listener owns the descriptor, so this
+/// is meant to be called once, e.g. in `main`.
+pub fn activated_listener() -> io::Result<Option<Listener>> {
+    let pid: Option<String> = env::var("LISTEN_PID").ok();
+    let count: Option<String> = env::var("LISTEN_FDS").ok();
+
+    // The sockets were meant for another process, e.g. the one that executed this one
+    let (Some(pid), Some(count)) = (pid, count) else {
+        return Ok(None);
+    };
+    if pid.parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(None);
+    }
+
+    match count.parse::<RawFd>() {
+        Ok(0) => Ok(None),
+        Ok(1) => {
+            if unsafe { libc::fcntl(LISTEN_FDS_START, F_SETFD, FD_CLOEXEC) } == -1 {
+                Err(io::Error::last_os_error())?;
+            }
+            // SAFETY: systemd passed this descriptor for the process to own.
+            let listener: Listener = Listener::from_fd(unsafe { OwnedFd::from_raw_fd(LISTEN_FDS_START) })?;
+            tracing::info!(addr = %listener.listen_addr()?, "Using the socket passed by systemd");
+
+            Ok(Some(listener))
+        }
+        Ok(count) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Expected one socket from systemd, got {}", count),
+        )),
+        Err(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "Invalid LISTEN_FDS")),
+    }
+}
+
+/// Sends service state notifications to systemd (`sd_notify`).
+///
+/// A server with a notifier reports `READY=1` with its address as `STATUS` once it accepts
+/// connections and `STOPPING=1` once shutdown is requested. If a watchdog interval is set,
+/// it sends `WATCHDOG=1` at half that interval until shutdown, as long as its serving loop
+/// makes progress: the forking servers send it from that loop, the others from a thread that
+/// checks the [`Heartbeat`] of the loop. A server that handles connections on its accepting
+/// thread (the iterative server, or a thread pool server that runs them on the caller) misses
+/// pings while it does, so its watchdog interval should exceed the longest connection. A server
+/// started by a hot restart also reports its pid as `MAINPID`, which requires `NotifyAccess=all`;
+/// the server it replaces does not report `STOPPING=1`.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    addr: UnixAddr,
+    watchdog: Option<Duration>,
+    beats: Arc<AtomicU64>,
+}
+
+impl Notifier {
+    /// Creates a notifier that sends to the given socket, e.g. a stand-in for systemd in tests.
+    pub fn new(addr: UnixAddr) -> Self {
+        Self {
+            addr,
+            watchdog: None,
+            beats: Arc::default(),
+        }
+    }
+
+    /// Creates a notifier for the socket systemd passes in `NOTIFY_SOCKET`, with the watchdog
+    /// interval from `WATCHDOG_USEC` (if `WATCHDOG_PID` is set, only for that process).
+    /// Returns `None` if the server does not run under systemd.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let Some(addr) = env::var("NOTIFY_SOCKET").ok() else {
+            return Ok(None);
+        };
+        let addr: UnixAddr = addr.parse().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid NOTIFY_SOCKET: {}", e),
+            )
+        })?;
+
+        let for_this_process: bool =
+            env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse::<u32>().ok() == Some(std::process::id()));
+        let watchdog: Option<Duration> = match env::var("WATCHDOG_USEC") {
+            Ok(usec) if for_this_process => match usec.parse::<u64>() {
+                Ok(usec) if usec > 0 => Some(Duration::from_micros(usec)),
+                _ => Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Invalid WATCHDOG_USEC",
+                ))?,
+            },
+            _ => None,
+        };
+
+        Ok(Some(Self {
+            addr,
+            watchdog,
+            beats: Arc::default(),
+        }))
+    }
+
+    /// Sends `WATCHDOG=1` at half of `interval`; systemd restarts the service if a ping is late.
+    pub fn with_watchdog(mut self, interval: Duration) -> Self {
+        self.watchdog = Some(interval);
+        self
+    }
+
+    pub fn watchdog(&self) -> Option<Duration> {
+        self.watchdog
+    }
+
+    /// Returns the heartbeat the serving loop must beat for the watchdog to be pinged,
+    /// or `None` without a watchdog.
+    pub(crate) fn heartbeat(&self) -> Option<Heartbeat> {
+        self.watchdog.map(|interval| Heartbeat {
+            beats: Arc::clone(&self.beats),
+            interval: interval / 4,
+        })
+    }
+
+    /// Sends newline-separated `KEY=VALUE` assignments, e.g. `READY=1\nSTATUS=Serving`.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &self.addr.to_std()?)?;
+        Ok(())
+    }
+
+    /// Reports that the server is ready, along with its status and, if it was `restarted`, its pid.
+    pub(crate) fn ready(&self, status: &str, restarted: bool) -> io::Result<()> {
+        let mut ready: String = format!("READY=1\nSTATUS={}", status);
+        if restarted {
+            ready.push_str(&format!("\nMAINPID={}", std::process::id()));
+        }
+        self.notify(&ready)
+    }
+
+    pub(crate) fn ping_watchdog(&self) {
+        if let Err(e) = self.notify("WATCHDOG=1") {
+            tracing::warn!("Failed to ping the systemd watchdog: {}", e);
+        }
+    }
+
+    /// Reports `STOPPING=1`, unless a new server started by `restart` has taken over.
+    pub(crate) fn stopping(&self, restart: Option<&Restart>) {
+        if !restart.is_some_and(Restart::is_handed_over) {
+            if let Err(e) = self.notify("STOPPING=1\nSTATUS=Shutting down") {
+                tracing::warn!("Failed to notify systemd: {}", e);
+            }
+        }
+    }
+
+    /// Reports that the server is ready. Spawns a thread that pings the watchdog and reports
+    /// `STOPPING=1` once shutdown is requested.
+    pub(crate) fn start(
+        &self,
+        status: &str,
+        restarted: bool,
+        shutdown: Shutdown,
+        restart: Option<Restart>,
+    ) -> io::Result<NotifierThread> {
+        self.ready(status, restarted)?;
+
+        let notifier: Notifier = self.clone();
+        let waiting: Shutdown = shutdown.clone();
+        let handle: thread::JoinHandle<()> = thread::spawn(move || {
+            match notifier.watchdog {
+                Some(interval) => {
+                    let mut last_beats: u64 = notifier.beats.load(Ordering::Relaxed);
+                    while !waiting.wait(Some(interval / 2)) {
+                        let beats: u64 = notifier.beats.load(Ordering::Relaxed);
+                        // A stuck serving loop must not be kept alive by this thread
+                        if beats == last_beats {
+                            tracing::warn!("The server stopped making progress. Skipping the watchdog ping");
+                            continue;
+                        }
+                        last_beats = beats;
+                        notifier.ping_watchdog();
+                    }
+                }
+                None => {
+                    waiting.wait(None);
+                }
+            }
+
+            notifier.stopping(restart.as_ref());
+        });
+
+        Ok(NotifierThread {
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+}
+
+/// Sign of life of a serving loop. The watchdog is only pinged if it has been beaten since
+/// the previous ping.
+#[derive(Debug, Clone)]
+pub(crate) struct Heartbeat {
+    beats: Arc<AtomicU64>,
+    interval: Duration,
+}
+
+impl Heartbeat {
+    pub(crate) fn beat(&self) {
+        self.beats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Longest time the serving loop may wait between beats, a quarter of the watchdog interval
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// The thread started by [`Notifier::start`]. Once shutdown has been requested, dropping it
+/// waits for `STOPPING=1` to be sent, so that the notification is not lost when the process exits.
+pub(crate) struct NotifierThread {
+    shutdown: Shutdown,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for NotifierThread {
+    fn drop(&mut self) {
+        // Without a shutdown request the thread would never finish
+        if let Some(handle) = self.handle.take().filter(|_| self.shutdown.is_triggered()) {
+            let _ = handle.join();
+        }
+    }
+}
@@ -1,39 +1,314 @@
-use std::{thread, mem, cmp};
-use std::sync::{mpsc, Arc, Mutex};
+use std::{fmt, thread, mem, cmp};
+use std::any::Any;
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::panic::{self, AssertUnwindSafe};
-use libc::{cpu_set_t, CPU_SETSIZE, CPU_ISSET, _SC_NPROCESSORS_ONLN};
+use libc::_SC_NPROCESSORS_ONLN;
+
+use crate::affinity::{self, CpuAffinity};
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// Maximum number of jobs a worker moves from the injector to its own deque at once
+const INJECTOR_BATCH: usize = 32;
+
+/// Default time after which an idle worker above the core count retires.
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// Pool and index of the worker running on this thread (null if it is not a worker thread)
+    static CURRENT_WORKER: Cell<(*const Shared, usize)> = const { Cell::new((std::ptr::null(), 0)) };
+}
+
+/// Work-stealing thread pool.
+///
+/// Every worker has a deque of its own. Jobs submitted from outside the pool go to a shared
+/// injector queue, from which workers take them in batches; jobs submitted by a job that runs
+/// in the pool go to the deque of its worker. A worker takes jobs from the back of its own deque
+/// first, then from the injector, and finally steals from the front of the other workers' deques,
+/// so workers rarely contend for the same lock.
+///
+/// A pool built with [`Builder::max_workers`] is elastic: it keeps the core workers for its whole
+/// life, spawns more workers up to the maximum while every worker is busy and jobs are queued,
+/// and retires the extra workers once they have been idle for the keep-alive time.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+}
+
+/// Configures a [`ThreadPool`] before its workers are started.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    core_workers: usize,
+    max_workers: usize,
+    keep_alive: Duration,
+    capacity: Option<usize>,
+    cpu_affinity: Option<CpuAffinity>,
+}
+
+impl Builder {
+    /// Starts configuring a pool with `size` workers and an unbounded job queue.
+    pub fn new(size: usize) -> Self {
+        Self {
+            core_workers: size,
+            max_workers: size,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            capacity: None,
+            cpu_affinity: None,
+        }
+    }
+
+    /// Lets the pool grow up to `max` workers while jobs queue up. The workers above the
+    /// initial size retire after being idle for the keep-alive time.
+    pub fn max_workers(mut self, max: usize) -> Self {
+        self.max_workers = max;
+        self
+    }
+
+    /// Sets how long a worker above the core count may stay idle before it retires.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Bounds the queue to at most `capacity` jobs that no worker has picked up yet.
+    /// When the queue is full, [`ThreadPool::execute`] blocks and [`ThreadPool::try_execute`] fails.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Pins each worker to a CPU, chosen by the worker's id.
+    pub fn pin_cpus(mut self, affinity: CpuAffinity) -> Self {
+        self.cpu_affinity = Some(affinity);
+        self
+    }
+
+    pub fn build(self) -> ThreadPool {
+        ThreadPool::with_config(self)
+    }
+}
+
+/// State shared between the pool and its workers
+struct Shared {
+    injector: Mutex<VecDeque<Job>>,
+    /// One deque per worker slot, up to the maximum number of workers
+    deques: Vec<Mutex<VecDeque<Job>>>,
+    capacity: Option<usize>,
+    /// Indexed by worker id; the threads of retired workers are joined when their slot is reused
+    workers: Mutex<Vec<Worker>>,
+    core_workers: usize,
+    max_workers: usize,
+    keep_alive: Duration,
+    cpu_affinity: Option<CpuAffinity>,
+    /// Number of workers that have been started and have not retired
+    alive: AtomicUsize,
+    /// Slots of retired workers and of workers that have not been started yet
+    free_slots: Mutex<Vec<usize>>,
+    /// Number of jobs waiting in the queues, including the ones being submitted
+    queue_depth: AtomicUsize,
+    /// Number of workers looking for jobs in the queues
+    searching: AtomicUsize,
+    /// Number of workers waiting for jobs
+    sleeping: AtomicUsize,
+    /// Number of callers waiting for room in a bounded queue
+    blocked: AtomicUsize,
+    /// Number of jobs that have been queued and have not finished
+    unfinished: AtomicUsize,
+    shutdown: AtomicBool,
+    lock: Mutex<()>,
+    /// Signalled when jobs are queued or the pool shuts down
+    job_available: Condvar,
+    /// Signalled when a worker takes a job out of a bounded queue
+    space_available: Condvar,
+    /// Signalled when the last unfinished job finishes
+    idle: Condvar,
 }
 
 impl ThreadPool {
+    /// Creates a pool with an unbounded job queue.
     pub fn new(size: usize) -> Self {
-        assert!(size > 0, "ThreadPool size must be greater than 0");
+        Builder::new(size).build()
+    }
+
+    /// Creates a pool whose queue holds at most `capacity` jobs that no worker has picked up yet.
+    /// When the queue is full, [`ThreadPool::execute`] blocks and [`ThreadPool::try_execute`] fails.
+    pub fn bounded(size: usize, capacity: usize) -> Self {
+        Builder::new(size).queue_capacity(capacity).build()
+    }
+
+    fn with_config(config: Builder) -> Self {
+        let Builder {
+            core_workers,
+            max_workers,
+            keep_alive,
+            capacity,
+            cpu_affinity,
+        } = config;
+        assert!(core_workers > 0, "ThreadPool size must be greater than 0");
+        assert!(
+            max_workers >= core_workers,
+            "ThreadPool max workers must not be less than its size"
+        );
+        assert!(
+            capacity != Some(0),
+            "ThreadPool queue capacity must be greater than 0"
+        );
 
-        let (tx, rx) = mpsc::channel();
-        let rx: Arc<Mutex<mpsc::Receiver<Job>>> = Arc::new(Mutex::new(rx));
-        let workers: Vec<Worker> = (0..size)
-            .map(|id| Worker::new(id, Arc::clone(&rx)))
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            deques: (0..max_workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            capacity,
+            workers: Mutex::new(Vec::new()),
+            core_workers,
+            max_workers,
+            keep_alive,
+            cpu_affinity,
+            alive: AtomicUsize::new(core_workers),
+            free_slots: Mutex::new((core_workers..max_workers).rev().collect()),
+            queue_depth: AtomicUsize::new(0),
+            searching: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            unfinished: AtomicUsize::new(0),
+            shutdown: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            job_available: Condvar::new(),
+            space_available: Condvar::new(),
+            idle: Condvar::new(),
+        });
+        *shared.workers.lock().unwrap() = (0..max_workers)
+            .map(|id| match id < core_workers {
+                true => Worker::new(id, Arc::clone(&shared)),
+                false => Worker { id, thread: None },
+            })
             .collect();
 
-        tracing::info!(worker_count = size, "ThreadPool created");
+        tracing::info!(
+            worker_count = core_workers,
+            max_workers,
+            queue_capacity = ?capacity,
+            "ThreadPool created"
+        );
 
-        Self {
-            workers,
-            sender: Some(tx),
-        }
+        Self { shared }
     }
 
+    /// Queues a job, waiting for room in the queue if it is bounded and full.
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job: Job = Box::new(f);
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        if !self.shared.try_reserve() {
+            let mut guard = self.shared.lock.lock().unwrap();
+            self.shared.blocked.fetch_add(1, Ordering::SeqCst);
+
+            while !self.shared.try_reserve() {
+                guard = self.shared.space_available.wait(guard).unwrap();
+            }
+            self.shared.blocked.fetch_sub(1, Ordering::SeqCst);
+        }
+
+        self.shared.push(Box::new(f));
+        self.shared.grow_if_backed_up();
+    }
+
+    /// Queues a job and returns a handle to wait for its result. The job can be cancelled
+    /// through the handle as long as no worker has started it.
+    pub fn spawn<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, state) = job_with_state(f, None);
+        self.execute(job);
+
+        JobHandle { state }
+    }
+
+    /// Runs `f` with a [`Scope`] in which jobs may borrow data from the caller's stack.
+    /// Returns once every job spawned in the scope has finished. If one of them panicked and its
+    /// handle was not joined, the panic is propagated when the scope ends.
+    ///
+    /// Like [`ThreadPool::join`], this must not be called from a job of the same pool unless
+    /// other workers are free to run the scoped jobs.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            data: Arc::new(ScopeData::default()),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        // The scoped jobs borrow from the caller, so they must finish even if `f` panicked
+        scope.data.wait();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if scope.data.panicked.load(Ordering::SeqCst) => panic!("A scoped job panicked"),
+            Ok(value) => value,
+        }
+    }
+
+    /// Waits until every queued and running job has finished. The pool stays usable afterwards.
+    pub fn join(&self) {
+        self.shared.wait_idle(None);
+    }
+
+    /// Shuts the pool down, giving the queued and running jobs up to `timeout` to finish.
+    /// Returns `false` if they did not; the workers still running them are then detached
+    /// instead of joined.
+    pub fn shutdown_timeout(self, timeout: Duration) -> bool {
+        self.shared.begin_shutdown();
+        let finished: bool = self.shared.wait_idle(Some(timeout));
+
+        if !finished {
+            let workers: Vec<Worker> = mem::take(&mut *self.shared.workers.lock().unwrap());
+            tracing::warn!(
+                unfinished = self.shared.unfinished.load(Ordering::SeqCst),
+                "Jobs did not finish within {:?}. Detaching {} workers",
+                timeout,
+                workers.iter().filter(|worker| worker.thread.is_some()).count()
+            );
+        }
+        // The remaining workers are joined on drop
+        finished
+    }
+
+    /// Queues a job if there is room in the queue, otherwise gives it back.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.shared.try_reserve() {
+            true => {
+                self.shared.push(Box::new(f));
+                self.shared.grow_if_backed_up();
+                Ok(())
+            }
+            false => Err(f),
+        }
+    }
+
+    /// Returns the number of jobs that are queued and not yet picked up by a worker.
+    pub fn queue_depth(&self) -> usize {
+        self.shared.queue_depth.load(Ordering::SeqCst)
+    }
+
+    /// Returns the capacity of the queue, or `None` if it is unbounded.
+    pub fn queue_capacity(&self) -> Option<usize> {
+        self.shared.capacity
+    }
+
+    /// Returns the number of running workers.
+    pub fn worker_count(&self) -> usize {
+        self.shared.alive.load(Ordering::SeqCst)
     }
 }
 
@@ -46,31 +321,244 @@ impl Default for ThreadPool {
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
-
-        self.workers.iter_mut().for_each(|worker| {
-            tracing::info!(worker_id = worker.id, "Shutting down worker");
+        // The workers finish the queued jobs before they exit
+        self.shared.begin_shutdown();
 
+        // Workers may spawn other workers until they see the shutdown, so the threads
+        // are joined without holding the lock
+        let workers: Vec<Worker> = mem::take(&mut *self.shared.workers.lock().unwrap());
+        workers.into_iter().for_each(|mut worker| {
             if let Some(thread) = worker.thread.take() {
+                tracing::info!(worker_id = worker.id, "Shutting down worker");
                 thread.join().unwrap();
             }
         });
     }
 }
 
+impl Shared {
+    /// Takes a place in the queue. Fails if the queue is bounded and full.
+    fn try_reserve(&self) -> bool {
+        let capacity: usize = self.capacity.unwrap_or(usize::MAX);
+
+        self.queue_depth
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |depth| {
+                (depth < capacity).then_some(depth + 1)
+            })
+            .is_ok()
+    }
+
+    /// Queues a job for which a place has been reserved. A sleeping worker is woken up
+    /// only if no worker is already looking for jobs, as that one will find this job too.
+    fn push(&self, job: Job) {
+        self.unfinished.fetch_add(1, Ordering::SeqCst);
+        match self.current_worker() {
+            Some(id) => self.deques[id].lock().unwrap().push_back(job),
+            None => self.injector.lock().unwrap().push_back(job),
+        }
+
+        if self.searching.load(Ordering::SeqCst) == 0 {
+            self.wake_worker();
+        }
+    }
+
+    /// Tells the workers to exit once the queues are empty.
+    fn begin_shutdown(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        drop(self.lock.lock().unwrap());
+        self.job_available.notify_all();
+    }
+
+    fn job_finished(&self) {
+        if self.unfinished.fetch_sub(1, Ordering::SeqCst) == 1 {
+            drop(self.lock.lock().unwrap());
+            self.idle.notify_all();
+        }
+    }
+
+    /// Waits until no jobs are unfinished. Returns `false` if `timeout` elapsed first.
+    fn wait_idle(&self, timeout: Option<Duration>) -> bool {
+        let deadline: Option<Instant> = timeout.map(|timeout| Instant::now() + timeout);
+        let mut guard = self.lock.lock().unwrap();
+
+        while self.unfinished.load(Ordering::SeqCst) > 0 {
+            guard = match deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())) {
+                None => self.idle.wait(guard).unwrap(),
+                Some(Duration::ZERO) => return false,
+                Some(remaining) => self.idle.wait_timeout(guard, remaining).unwrap().0,
+            };
+        }
+
+        true
+    }
+
+    fn wake_worker(&self) {
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            drop(self.lock.lock().unwrap());
+            self.job_available.notify_one();
+        }
+    }
+
+    /// Spawns another worker if every worker is busy while jobs are waiting
+    /// and the pool has not reached its maximum size.
+    fn grow_if_backed_up(self: &Arc<Self>) {
+        let backed_up: bool = self.alive.load(Ordering::SeqCst) < self.max_workers
+            && self.searching.load(Ordering::SeqCst) == 0
+            && self.sleeping.load(Ordering::SeqCst) == 0
+            && self.queue_depth.load(Ordering::SeqCst) > 0;
+        if !backed_up {
+            return;
+        }
+
+        let mut workers = self.workers.lock().unwrap();
+        // The pool has been dropped and the workers are finishing the queued jobs
+        if workers.is_empty() {
+            return;
+        }
+        // A retired worker gives its slot back before it exits, so joining it does not block for long
+        let Some(id) = self.free_slots.lock().unwrap().pop() else {
+            return;
+        };
+        if let Some(thread) = workers[id].thread.take() {
+            thread.join().unwrap();
+        }
+        self.alive.fetch_add(1, Ordering::SeqCst);
+        workers[id] = Worker::new(id, Arc::clone(self));
+
+        tracing::info!(
+            worker_id = id,
+            worker_count = self.alive.load(Ordering::SeqCst),
+            queue_depth = self.queue_depth.load(Ordering::SeqCst),
+            "Queue is backing up. Spawned worker"
+        );
+    }
+
+    /// Returns the index of the worker of this pool that runs on the current thread.
+    fn current_worker(&self) -> Option<usize> {
+        let (pool, id) = CURRENT_WORKER.with(Cell::get);
+        std::ptr::eq(pool, self).then_some(id)
+    }
+
+    /// Finds a job for the given worker: from its own deque, then from the injector,
+    /// then from the other workers. The worker counts as searching until it finds one
+    /// or goes to sleep.
+    fn find_job(&self, id: usize) -> Option<Job> {
+        self.searching.fetch_add(1, Ordering::SeqCst);
+        // The guard of the own deque must be dropped before the injector moves jobs to it
+        let local: Option<Job> = self.deques[id].lock().unwrap().pop_back();
+        let job: Job = local
+            .or_else(|| self.take_from_injector(id))
+            .or_else(|| self.steal(id))?;
+
+        self.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        // The last searching worker may have taken a different job than the one whose
+        // submitter relied on it, so it passes the search on
+        if self.searching.fetch_sub(1, Ordering::SeqCst) == 1 && self.queue_depth.load(Ordering::SeqCst) > 0 {
+            self.wake_worker();
+        }
+        if self.blocked.load(Ordering::SeqCst) > 0 {
+            drop(self.lock.lock().unwrap());
+            self.space_available.notify_one();
+        }
+        Some(job)
+    }
+
+    /// Takes a job from the injector, moving a batch of the jobs behind it to the worker's deque.
+    fn take_from_injector(&self, id: usize) -> Option<Job> {
+        let mut injector = self.injector.lock().unwrap();
+        let job: Job = injector.pop_front()?;
+
+        // Leave a fair share for the other workers
+        let batch: usize = (injector.len() / self.deques.len()).min(INJECTOR_BATCH);
+        if batch > 0 {
+            self.deques[id].lock().unwrap().extend(injector.drain(..batch));
+        }
+        Some(job)
+    }
+
+    /// Steals the oldest job of another worker, starting with the next one.
+    fn steal(&self, id: usize) -> Option<Job> {
+        let n: usize = self.deques.len();
+
+        (1..n).find_map(|offset| self.deques[(id + offset) % n].lock().unwrap().pop_front())
+    }
+
+    /// Waits until jobs may be available, the pool shuts down, or the worker has been idle
+    /// for the keep-alive time while the pool has more than its core workers.
+    fn wait_for_job(&self, id: usize) -> Wakeup {
+        let mut guard = self.lock.lock().unwrap();
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        self.searching.fetch_sub(1, Ordering::SeqCst);
+
+        // Jobs are counted before they are queued, so a job that is counted but not yet
+        // visible makes the worker look again instead of missing the wakeup
+        let wakeup: Wakeup = loop {
+            if self.queue_depth.load(Ordering::SeqCst) > 0 {
+                break Wakeup::Job;
+            }
+            if self.shutdown.load(Ordering::SeqCst) {
+                break Wakeup::Shutdown;
+            }
+            if self.alive.load(Ordering::SeqCst) <= self.core_workers {
+                guard = self.job_available.wait(guard).unwrap();
+                continue;
+            }
+
+            let (next_guard, result) = self.job_available.wait_timeout(guard, self.keep_alive).unwrap();
+            guard = next_guard;
+            if result.timed_out() && self.queue_depth.load(Ordering::SeqCst) == 0 && self.try_retire(id) {
+                break Wakeup::Retire;
+            }
+        };
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+
+        wakeup
+    }
+
+    /// Gives up the worker's slot unless that would leave fewer than the core workers.
+    fn try_retire(&self, id: usize) -> bool {
+        let core_workers: usize = self.core_workers;
+        let retired: bool = self
+            .alive
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |alive| {
+                (alive > core_workers).then_some(alive - 1)
+            })
+            .is_ok();
+        if retired {
+            self.free_slots.lock().unwrap().push(id);
+        }
+
+        retired
+    }
+}
+
+/// Why a worker waiting for jobs woke up
+enum Wakeup {
+    Job,
+    Retire,
+    Shutdown,
+}
+
 struct Worker {
     id: usize,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: usize, rx: Arc<Mutex<mpsc::Receiver<Job>>>) -> Self {
+    fn new(id: usize, shared: Arc<Shared>) -> Self {
         let thread: thread::JoinHandle<()> = thread::spawn(move || {
             let span = tracing::info_span!("Worker", worker_id = id);
             let _guard = span.enter();
 
             tracing::info!("Worker started");
-            Self::worker_loop(rx);
+            if let Some(affinity) = &shared.cpu_affinity {
+                match affinity.pin_current_thread(id) {
+                    Ok(cpu) => tracing::debug!(cpu, "Pinned worker"),
+                    Err(e) => tracing::warn!("Failed to pin worker to CPU {}: {}", affinity.cpu(id), e),
+                }
+            }
+            CURRENT_WORKER.with(|current| current.set((Arc::as_ptr(&shared), id)));
+            Self::worker_loop(id, &shared);
         });
 
         Self {
@@ -79,40 +567,292 @@ impl Worker {
         }
     }
 
-    fn worker_loop(rx: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    fn worker_loop(id: usize, shared: &Arc<Shared>) {
         loop {
-            let received_job: Result<Job, _> = rx.lock().unwrap().recv();
-
-            match received_job {
-                Ok(job) => {
+            match shared.find_job(id) {
+                Some(job) => {
                     tracing::debug!("Received a job. Executing.");
+                    // Jobs queued while every worker was busy have not made the pool grow yet
+                    shared.grow_if_backed_up();
 
                     if let Err(err) = panic::catch_unwind(AssertUnwindSafe(job)) {
-                        let panic_msg: &str = err
-                            .downcast_ref()
-                            .copied()
-                            .or_else(|| err.downcast_ref::<String>().map(|s| &**s))
-                            .unwrap_or("Any { .. }");
-
-                        tracing::error!("Job panicked: {:?}", panic_msg);
+                        tracing::error!("Job panicked: {:?}", panic_message(&*err));
                     }
+                    shared.job_finished();
                 }
-                Err(_) => {
-                    tracing::debug!("Channel closed. Shutting down worker.");
-                    break;
+                None => match shared.wait_for_job(id) {
+                    Wakeup::Job => {}
+                    Wakeup::Retire => {
+                        tracing::info!("Idle for {:?}. Retiring worker.", shared.keep_alive);
+                        break;
+                    }
+                    Wakeup::Shutdown => {
+                        tracing::debug!("Pool shut down. Shutting down worker.");
+                        break;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Handle to a job queued with [`ThreadPool::spawn`].
+///
+/// Dropping the handle detaches the job. If a detached job panics, the panic is logged.
+pub struct JobHandle<T> {
+    state: Arc<JobState<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to finish and returns its result, or why there is none.
+    pub fn join(self) -> Result<T, JobError> {
+        self.state.join()
+    }
+
+    /// Cancels the job if no worker has started it yet, in which case it never runs.
+    /// Returns whether the job is cancelled; a job that has started is left to finish.
+    pub fn cancel(&self) -> bool {
+        self.state.cancel()
+    }
+
+    /// Returns `true` if the job has finished or has been cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+}
+
+/// Why a job did not produce a result
+pub enum JobError {
+    /// The job was cancelled before it started
+    Cancelled,
+    /// The job panicked with the given payload
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl fmt::Debug for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("Cancelled"),
+            Self::Panicked(payload) => f
+                .debug_tuple("Panicked")
+                .field(&panic_message(&**payload))
+                .finish(),
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => f.write_str("Job was cancelled"),
+            Self::Panicked(payload) => write!(f, "Job panicked: {}", panic_message(&**payload)),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// Lets jobs borrow data that outlives the scope. Created by [`ThreadPool::scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    data: Arc<ScopeData>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Queues a job that may borrow data from outside the scope and returns a handle to wait
+    /// for its result.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJobHandle<'scope, T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        self.data.unfinished.fetch_add(1, Ordering::SeqCst);
+        let (job, state) = job_with_state(f, Some(Arc::clone(&self.data)));
+        let data: Arc<ScopeData> = Arc::clone(&self.data);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            // Drops the job state before the scope can end, so that a panic nobody
+            // has joined is noticed
+            job();
+            data.job_finished();
+        });
+        // SAFETY: `ThreadPool::scope` does not return before every job spawned in the scope
+        // has finished, so the borrowed data outlives the job.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.execute(job);
+
+        ScopedJobHandle {
+            state,
+            scope: PhantomData,
+        }
+    }
+}
+
+/// Handle to a job queued with [`Scope::spawn`].
+pub struct ScopedJobHandle<'scope, T> {
+    state: Arc<JobState<T>>,
+    scope: PhantomData<&'scope ()>,
+}
+
+impl<T> ScopedJobHandle<'_, T> {
+    /// Waits for the job to finish and returns its result, or why there is none.
+    pub fn join(self) -> Result<T, JobError> {
+        self.state.join()
+    }
+
+    /// Cancels the job if no worker has started it yet, in which case it never runs.
+    /// Returns whether the job is cancelled; a job that has started is left to finish.
+    pub fn cancel(&self) -> bool {
+        self.state.cancel()
+    }
+
+    /// Returns `true` if the job has finished or has been cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.state.is_finished()
+    }
+}
+
+#[derive(Default)]
+struct ScopeData {
+    unfinished: AtomicUsize,
+    /// Set when a job panics and its payload is not taken by joining the handle
+    panicked: AtomicBool,
+    lock: Mutex<()>,
+    all_finished: Condvar,
+}
+
+impl ScopeData {
+    fn job_finished(&self) {
+        if self.unfinished.fetch_sub(1, Ordering::SeqCst) == 1 {
+            drop(self.lock.lock().unwrap());
+            self.all_finished.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut guard = self.lock.lock().unwrap();
+        while self.unfinished.load(Ordering::SeqCst) > 0 {
+            guard = self.all_finished.wait(guard).unwrap();
+        }
+    }
+}
+
+/// State shared between a job and its handle
+struct JobState<T> {
+    result: Mutex<JobResult<T>>,
+    finished: Condvar,
+    scope: Option<Arc<ScopeData>>,
+}
+
+enum JobResult<T> {
+    Queued,
+    Running,
+    Finished(thread::Result<T>),
+    Cancelled,
+    /// The result has been returned by `join`
+    Taken,
+}
+
+impl<T> JobState<T> {
+    /// Marks the job as running. Returns `false` if it has been cancelled.
+    fn start(&self) -> bool {
+        let mut result = self.result.lock().unwrap();
+        match *result {
+            JobResult::Queued => {
+                *result = JobResult::Running;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn finish(&self, outcome: thread::Result<T>) {
+        *self.result.lock().unwrap() = JobResult::Finished(outcome);
+        self.finished.notify_all();
+    }
+
+    fn cancel(&self) -> bool {
+        let mut result = self.result.lock().unwrap();
+        match *result {
+            JobResult::Queued => {
+                *result = JobResult::Cancelled;
+                self.finished.notify_all();
+                true
+            }
+            JobResult::Cancelled => true,
+            _ => false,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        !matches!(
+            *self.result.lock().unwrap(),
+            JobResult::Queued | JobResult::Running
+        )
+    }
+
+    fn join(&self) -> Result<T, JobError> {
+        let mut result = self.result.lock().unwrap();
+        loop {
+            match mem::replace(&mut *result, JobResult::Taken) {
+                JobResult::Finished(Ok(value)) => return Ok(value),
+                JobResult::Finished(Err(payload)) => return Err(JobError::Panicked(payload)),
+                JobResult::Cancelled => return Err(JobError::Cancelled),
+                pending => {
+                    *result = pending;
+                    result = self.finished.wait(result).unwrap();
                 }
             }
         }
     }
 }
 
-fn get_num_cpus() -> usize {
-    let mut set: cpu_set_t = unsafe { mem::zeroed() };
+impl<T> Drop for JobState<T> {
+    fn drop(&mut self) {
+        // Nobody has taken the panic payload
+        if let Ok(JobResult::Finished(Err(payload))) = self.result.get_mut() {
+            match &self.scope {
+                Some(scope) => scope.panicked.store(true, Ordering::SeqCst),
+                None => tracing::error!("Job panicked: {:?}", panic_message(&**payload)),
+            }
+        }
+    }
+}
 
-    if unsafe { libc::sched_getaffinity(0, mem::size_of::<cpu_set_t>(), &mut set) } == 0 {
-        return (0..CPU_SETSIZE as usize)
-            .filter(|&i| unsafe { CPU_ISSET(i, &set) })
-            .count();
+/// Wraps `f` in a job that reports its result, or its panic, to the returned state.
+/// A job cancelled before it starts returns without calling `f`.
+fn job_with_state<F, T>(f: F, scope: Option<Arc<ScopeData>>) -> (impl FnOnce() + Send, Arc<JobState<T>>)
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    let state = Arc::new(JobState {
+        result: Mutex::new(JobResult::Queued),
+        finished: Condvar::new(),
+        scope,
+    });
+    let job_state: Arc<JobState<T>> = Arc::clone(&state);
+    let job = move || {
+        if job_state.start() {
+            job_state.finish(panic::catch_unwind(AssertUnwindSafe(f)));
+        }
+    };
+
+    (job, state)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(|s| &**s))
+        .unwrap_or("Any { .. }")
+}
+
+fn get_num_cpus() -> usize {
+    if let Ok(cpus) = affinity::allowed_cpus() {
+        return cpus.len();
     }
     let count: i64 = unsafe { libc::sysconf(_SC_NPROCESSORS_ONLN) };
 
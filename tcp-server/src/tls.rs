@@ -0,0 +1,199 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::collections::HashMap;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::pki_types::pem::PemObject;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+
+/// Reads every certificate from a PEM file.
+pub fn load_certificates(path: impl AsRef<Path>) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certificates: Vec<CertificateDer<'static>> = CertificateDer::pem_file_iter(path.as_ref())
+        .and_then(|certificates| certificates.collect::<Result<_, _>>())
+        .map_err(|e| pem_error(path.as_ref(), e))?;
+
+    if certificates.is_empty() {
+        Err(pem_error(path.as_ref(), "no certificates found"))?
+    }
+
+    Ok(certificates)
+}
+
+/// Reads the first private key from a PEM file.
+pub fn load_private_key(path: impl AsRef<Path>) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path.as_ref()).map_err(|e| pem_error(path.as_ref(), e))
+}
+
+/// Builds the TLS configuration of a server.
+///
+/// The server presents the certificate registered for the name the client asks for via SNI,
+/// or the default certificate if there is none. Clients have to present a certificate
+/// only if [`ServerTlsBuilder::client_auth`] is set (mutual TLS).
+#[derive(Debug, Default)]
+pub struct ServerTlsBuilder {
+    default: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    by_name: Vec<(String, Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    client_roots: Vec<CertificateDer<'static>>,
+}
+
+impl ServerTlsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the certificate presented to clients that ask for no name or an unknown one.
+    pub fn certificate(mut self, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        self.default = Some((chain, key));
+        self
+    }
+
+    /// Adds a certificate presented to clients that ask for `server_name` via SNI.
+    pub fn sni_certificate(
+        mut self,
+        server_name: impl Into<String>,
+        chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Self {
+        self.by_name.push((server_name.into(), chain, key));
+        self
+    }
+
+    /// Requires clients to present a certificate issued by one of `roots`.
+    pub fn client_auth(mut self, roots: Vec<CertificateDer<'static>>) -> Self {
+        self.client_roots = roots;
+        self
+    }
+
+    pub fn build(self) -> io::Result<Arc<ServerConfig>> {
+        let provider: Arc<CryptoProvider> = Arc::new(crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?;
+
+        let builder = match self.client_roots.is_empty() {
+            true => builder.with_no_client_auth(),
+            false => {
+                let roots: Arc<RootCertStore> = Arc::new(root_store(self.client_roots)?);
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots, Arc::clone(&provider))
+                    .build()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let resolver = SniResolver {
+            default: self
+                .default
+                .map(|(chain, key)| certified_key(chain, key, &provider))
+                .transpose()?,
+            by_name: self
+                .by_name
+                .into_iter()
+                .map(|(name, chain, key)| {
+                    Ok((name.to_ascii_lowercase(), certified_key(chain, key, &provider)?))
+                })
+                .collect::<io::Result<_>>()?,
+        };
+        if resolver.default.is_none() && resolver.by_name.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No server certificate",
+            ))?
+        }
+
+        Ok(Arc::new(builder.with_cert_resolver(Arc::new(resolver))))
+    }
+}
+
+/// Builds the TLS configuration of a client.
+///
+/// The client trusts only the given root certificates and presents its own certificate
+/// if [`ClientTlsBuilder::certificate`] is set.
+#[derive(Debug, Default)]
+pub struct ClientTlsBuilder {
+    roots: Vec<CertificateDer<'static>>,
+    identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+impl ClientTlsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts servers with certificates issued by one of `roots`.
+    pub fn root_certificates(mut self, roots: Vec<CertificateDer<'static>>) -> Self {
+        self.roots.extend(roots);
+        self
+    }
+
+    /// Sets the certificate presented to servers that ask for one.
+    pub fn certificate(mut self, chain: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>) -> Self {
+        self.identity = Some((chain, key));
+        self
+    }
+
+    pub fn build(self) -> io::Result<Arc<ClientConfig>> {
+        let provider: Arc<CryptoProvider> = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(tls_error)?
+            .with_root_certificates(root_store(self.roots)?);
+
+        let config: ClientConfig = match self.identity {
+            Some((chain, key)) => builder.with_client_auth_cert(chain, key).map_err(tls_error)?,
+            None => builder.with_no_client_auth(),
+        };
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Picks the server certificate by the name the client sent via SNI.
+#[derive(Debug)]
+struct SniResolver {
+    default: Option<Arc<CertifiedKey>>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|name| self.by_name.get(&name.to_ascii_lowercase()))
+            .or(self.default.as_ref())
+            .cloned()
+    }
+}
+
+fn certified_key(
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    provider: &CryptoProvider,
+) -> io::Result<Arc<CertifiedKey>> {
+    Ok(Arc::new(
+        CertifiedKey::from_der(chain, key, provider).map_err(tls_error)?,
+    ))
+}
+
+fn root_store(roots: Vec<CertificateDer<'static>>) -> io::Result<RootCertStore> {
+    let mut store: RootCertStore = RootCertStore::empty();
+    for root in roots {
+        store.add(root).map_err(tls_error)?;
+    }
+
+    Ok(store)
+}
+
+fn tls_error(e: rustls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+fn pem_error(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Failed to read {:?}: {}", path, e),
+    )
+}
@@ -0,0 +1,65 @@
+//! Checks parsing of the CPU pinning options and that thread pool workers are pinned to their CPUs.
+use std::sync::mpsc;
+use std::time::Duration;
+
+use tcp_server::affinity::{self, CpuAffinity, PinCpus};
+use tcp_server::thread_pool;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn test_pin_cpus_parses_modes_and_cpu_lists() {
+    // GIVEN
+    let inputs: [&str; 6] = ["round-robin", "numa", "3", "0,2,4-6", "4-2", "one"];
+    // WHEN
+    let parsed: Vec<Result<PinCpus, String>> = inputs.iter().map(|input| input.parse()).collect();
+    // THEN
+    assert_eq!(parsed[0], Ok(PinCpus::RoundRobin));
+    assert_eq!(parsed[1], Ok(PinCpus::Numa));
+    assert_eq!(parsed[2], Ok(PinCpus::List(vec![3])));
+    assert_eq!(parsed[3], Ok(PinCpus::List(vec![0, 2, 4, 5, 6])));
+    assert!(parsed[4].is_err());
+    assert!(parsed[5].is_err());
+}
+
+#[test]
+fn test_numa_pinning_uses_allowed_cpus_only() {
+    // GIVEN
+    let mut allowed: Vec<usize> = affinity::allowed_cpus().unwrap();
+    // WHEN
+    let affinity: CpuAffinity = CpuAffinity::new(&PinCpus::Numa).unwrap();
+    // THEN
+    let mut cpus: Vec<usize> = (0..allowed.len()).map(|index| affinity.cpu(index)).collect();
+    cpus.sort_unstable();
+    allowed.sort_unstable();
+    assert_eq!(cpus, allowed);
+}
+
+#[test]
+fn test_cpus_outside_allowed_set_are_rejected() {
+    // GIVEN
+    let allowed: Vec<usize> = affinity::allowed_cpus().unwrap();
+    let outside: usize = allowed.iter().max().unwrap() + 1;
+    // WHEN
+    let result = CpuAffinity::new(&PinCpus::List(vec![outside]));
+    // THEN
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_workers_are_pinned_to_listed_cpus() {
+    // GIVEN
+    let cpu: usize = *affinity::allowed_cpus().unwrap().last().unwrap();
+    let affinity: CpuAffinity = CpuAffinity::new(&PinCpus::List(vec![cpu])).unwrap();
+    let pool = thread_pool::Builder::new(2).pin_cpus(affinity).build();
+    let (tx, rx) = mpsc::channel();
+    // WHEN
+    for _ in 0..4 {
+        let tx = tx.clone();
+        pool.execute(move || tx.send(affinity::allowed_cpus().unwrap()).unwrap());
+    }
+    // THEN
+    for _ in 0..4 {
+        assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), [cpu]);
+    }
+}
@@ -0,0 +1,238 @@
+//! Helpers shared by the integration tests: a directory with a test file, the file transfer
+//! service that serves it, the length-prefixed frames of the protocol and a runner for the
+//! `server` binary.
+#![allow(dead_code)]
+use std::{env, fs, io, thread};
+use std::sync::{mpsc, OnceLock};
+use std::path::{Path, PathBuf};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use prost::Message;
+
+use tcp_server::service::FileTransferService;
+use tcp_server::proto::prelude::*;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const CHUNK_SIZE: usize = 1024;
+pub const FILE_NAME: &str = "data.bin";
+/// Large enough for a transfer to still be in flight after a pause; the last chunk is not full
+pub const FILE_SIZE: usize = 64 * CHUNK_SIZE + 123;
+/// How long the binary tests wait for the server to log or answer
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Creates a directory with a test file (once per test binary) and returns its path
+pub fn get_base_dir() -> &'static Path {
+    static BASE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+    BASE_DIR.get_or_init(|| {
+        let base_dir: PathBuf = env::temp_dir().join(format!(
+            "tcp-server-{}-{}",
+            env!("CARGO_CRATE_NAME"),
+            std::process::id()
+        ));
+        fs::create_dir_all(&base_dir).unwrap();
+
+        let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+        fs::write(base_dir.join(FILE_NAME), data).unwrap();
+
+        base_dir
+    })
+}
+
+pub fn get_service() -> FileTransferService {
+    FileTransferService::new(get_base_dir(), PROTOCOL_VERSION, CHUNK_SIZE)
+}
+
+pub fn encode_frame(message: &impl Message) -> Vec<u8> {
+    let bytes: Vec<u8> = message.encode_to_vec();
+    [(bytes.len() as u32).to_be_bytes().as_slice(), &bytes].concat()
+}
+
+pub fn write_frame(stream: &mut impl Write, message: &impl Message) {
+    stream.write_all(&encode_frame(message)).unwrap();
+}
+
+pub fn read_frame<M: Message + Default>(stream: &mut impl Read) -> io::Result<M> {
+    let mut len_buf: [u8; 4] = [0; 4];
+    stream.read_exact(&mut len_buf)?;
+    let mut message_buf: Vec<u8> = vec![0; u32::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut message_buf)?;
+
+    Ok(M::decode(message_buf.as_slice())?)
+}
+
+pub fn query_file(stream: &mut TcpStream) -> io::Result<FileResponse> {
+    write_frame(
+        stream,
+        &FileQuery {
+            version: PROTOCOL_VERSION,
+            filename: FILE_NAME.to_string(),
+        },
+    );
+    read_frame::<FileResponse>(stream)
+}
+
+// Accepts the transfer and returns the number of bytes received
+pub fn receive_file(stream: &mut TcpStream) -> usize {
+    write_frame(
+        stream,
+        &TransferAck {
+            status: AckStatus::Accepted as i32,
+        },
+    );
+
+    let mut received: usize = 0;
+    while let Ok(chunk) = read_frame::<FileChunk>(stream) {
+        received += chunk.data.len();
+    }
+    received
+}
+
+// Runs a whole transfer and returns the number of bytes received
+pub fn transfer(socket_addr: SocketAddr) -> usize {
+    let mut stream: TcpStream = TcpStream::connect(socket_addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    query_file(&mut stream).unwrap();
+    receive_file(&mut stream)
+}
+
+// Returns `true` if the process exists and is not a zombie
+pub fn is_running(pid: i32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .map(|stat| !stat.contains(") Z "))
+        .unwrap_or(false)
+}
+
+/// Runs the `server` binary built with the features of the test, serving the test file, and
+/// stops it with SIGTERM when dropped.
+// Runs the `server` binary with arguments it must refuse and returns its usage error
+pub fn usage_error(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_server")).args(args).output().unwrap();
+    assert_eq!(output.status.code(), Some(2), "The server accepted {:?}", args);
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+pub struct ServerProcess {
+    pub process: Child,
+    pub socket_addr: SocketAddr,
+    logs: mpsc::Receiver<String>,
+}
+
+impl ServerProcess {
+    pub fn start(args: &[&str]) -> Self {
+        Self::start_with_env(args, &[])
+    }
+
+    pub fn start_with_env(args: &[&str], vars: &[(&str, &str)]) -> Self {
+        let socket_addr: SocketAddr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut process: Child = Command::new(env!("CARGO_BIN_EXE_server"))
+            .args(["--socket_addr", &socket_addr.to_string()])
+            .arg("--dir")
+            .arg(get_base_dir())
+            .args(args)
+            .env("RUST_LOG", "info")
+            .env("NO_COLOR", "1")
+            .envs(vars.iter().copied())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let (tx, logs) = mpsc::channel();
+        let stdout = BufReader::new(process.stdout.take().unwrap());
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                let _ = tx.send(line);
+            }
+        });
+
+        let server = Self {
+            process,
+            socket_addr,
+            logs,
+        };
+        server.wait_for_log("Listening on");
+        server
+    }
+
+    pub fn pid(&self) -> i32 {
+        self.process.id() as i32
+    }
+
+    // Waits for a log line containing `pattern` and returns it
+    pub fn wait_for_log(&self, pattern: &str) -> String {
+        let deadline: Instant = Instant::now() + TIMEOUT;
+        loop {
+            let timeout: Duration = deadline.saturating_duration_since(Instant::now());
+            match self.logs.recv_timeout(timeout) {
+                Ok(line) if line.contains(pattern) => return line,
+                Ok(_) => {}
+                Err(e) => panic!("No log line containing {:?}: {}", pattern, e),
+            }
+        }
+    }
+
+    // Returns the pids of the server's child processes, including zombies
+    pub fn children(&self) -> Vec<i32> {
+        let pid: i32 = self.pid();
+        fs::read_to_string(format!("/proc/{}/task/{}/children", pid, pid))
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|pid| pid.parse().unwrap())
+            .collect()
+    }
+
+    // Returns the number of threads of the server process
+    pub fn threads(&self) -> usize {
+        fs::read_to_string(format!("/proc/{}/status", self.pid()))
+            .unwrap()
+            .lines()
+            .find_map(|line| line.strip_prefix("Threads:"))
+            .map(|count| count.trim().parse().unwrap())
+            .unwrap()
+    }
+
+    // Waits until the server has exactly `count` children
+    pub fn wait_for_children(&self, count: usize) -> Vec<i32> {
+        let deadline: Instant = Instant::now() + TIMEOUT;
+        loop {
+            let children: Vec<i32> = self.children();
+            if children.len() == count {
+                return children;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "Expected {} children, got {:?}",
+                count,
+                children
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn signal(&self, signal: i32) {
+        unsafe { libc::kill(self.pid(), signal) };
+    }
+
+    // Waits for the server to exit and returns whether it exited successfully
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> bool {
+        let deadline: Instant = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.process.try_wait().unwrap() {
+                return status.success();
+            }
+            assert!(Instant::now() < deadline, "The server did not exit in time");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        if let Ok(None) = self.process.try_wait() {
+            self.signal(libc::SIGTERM);
+            let _ = self.process.wait();
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Checks that the blocking, event-driven and async servers speak exactly the same protocol:
+//! for the same requests, clients must receive byte-identical responses.
+#![cfg(feature = "tokio")]
+use std::{fs, thread};
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use prost::Message;
+
+use tcp_server::core::{IterativeTcpServer, EpollTcpServer, AsyncTcpServer};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, get_service, write_frame, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+
+fn start_sync_server() -> SocketAddr {
+    let server = IterativeTcpServer::new("127.0.0.1:0", get_service()).unwrap();
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    addr
+}
+
+fn start_epoll_server() -> SocketAddr {
+    let server = EpollTcpServer::new("127.0.0.1:0", get_service(), 1).unwrap();
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    addr
+}
+
+fn start_async_server() -> SocketAddr {
+    let server = AsyncTcpServer::new("127.0.0.1:0", get_service()).unwrap();
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(server.serve()));
+
+    addr
+}
+
+fn read_raw_frame(stream: &mut TcpStream, received: &mut Vec<u8>) {
+    let mut len_buf: [u8; 4] = [0; 4];
+    stream.read_exact(&mut len_buf).unwrap();
+    let mut message_buf: Vec<u8> = vec![0; u32::from_be_bytes(len_buf) as usize];
+    stream.read_exact(&mut message_buf).unwrap();
+
+    received.extend_from_slice(&len_buf);
+    received.extend_from_slice(&message_buf);
+}
+
+// Runs a single protocol exchange and returns every byte received from the server
+fn exchange(addr: SocketAddr, version: u32, filename: &str, ack: Option<AckStatus>) -> Vec<u8> {
+    let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+    let mut received: Vec<u8> = Vec::new();
+
+    write_frame(
+        &mut stream,
+        &FileQuery {
+            version,
+            filename: filename.to_string(),
+        },
+    );
+    if let Some(status) = ack {
+        read_raw_frame(&mut stream, &mut received);
+        write_frame(
+            &mut stream,
+            &TransferAck {
+                status: status as i32,
+            },
+        );
+    }
+    stream.read_to_end(&mut received).unwrap();
+
+    received
+}
+
+fn assert_conformance(version: u32, filename: &str, ack: Option<AckStatus>) -> Vec<u8> {
+    let sync_bytes: Vec<u8> = exchange(start_sync_server(), version, filename, ack);
+    let epoll_bytes: Vec<u8> = exchange(start_epoll_server(), version, filename, ack);
+    let async_bytes: Vec<u8> = exchange(start_async_server(), version, filename, ack);
+
+    assert!(!sync_bytes.is_empty());
+    assert!(
+        sync_bytes == epoll_bytes,
+        "Epoll server response differs from the sync server"
+    );
+    assert!(
+        sync_bytes == async_bytes,
+        "Async server response differs from the sync server"
+    );
+
+    sync_bytes
+}
+
+// Splits the received bytes into length-delimited messages
+fn split_frames(mut bytes: &[u8]) -> Vec<&[u8]> {
+    let mut frames: Vec<&[u8]> = Vec::new();
+    while let Some((len_buf, rest)) = bytes.split_first_chunk::<4>() {
+        let (frame, rest) = rest.split_at(u32::from_be_bytes(*len_buf) as usize);
+        frames.push(frame);
+        bytes = rest;
+    }
+
+    frames
+}
+
+#[test]
+fn test_accepted_transfer_is_byte_identical() {
+    // WHEN
+    let received: Vec<u8> = assert_conformance(PROTOCOL_VERSION, FILE_NAME, Some(AckStatus::Accepted));
+    // THEN
+    let frames: Vec<&[u8]> = split_frames(&received);
+    let response: FileResponse = FileResponse::decode(frames[0]).unwrap();
+    let chunks: Vec<FileChunk> = frames[1..]
+        .iter()
+        .map(|f| FileChunk::decode(*f).unwrap())
+        .collect();
+    let data: Vec<u8> = chunks.iter().flat_map(|c| c.data.clone()).collect();
+
+    assert!(matches!(response.response, Some(Response::Metadata(m)) if m.file_size == FILE_SIZE as u64));
+    assert!(chunks.iter().enumerate().all(|(i, c)| c.index == i as u32));
+    assert_eq!(data, fs::read(get_base_dir().join(FILE_NAME)).unwrap());
+}
+
+#[test]
+fn test_rejected_transfer_is_byte_identical() {
+    assert_conformance(PROTOCOL_VERSION, FILE_NAME, Some(AckStatus::Rejected));
+}
+
+#[test]
+fn test_file_not_found_is_byte_identical() {
+    assert_conformance(PROTOCOL_VERSION, "missing.bin", None);
+}
+
+#[test]
+fn test_version_mismatch_is_byte_identical() {
+    assert_conformance(PROTOCOL_VERSION + 1, FILE_NAME, None);
+}
@@ -0,0 +1,71 @@
+//! Checks that the epoll server completes transfers that take it several turns of the reactor,
+//! reports missing files and closes connections announcing oversized messages. Runs only with
+//! the `epoll` feature, which needs neither threads per connection nor tokio.
+#![cfg(feature = "epoll")]
+use std::thread;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+
+use tcp_server::core::EpollTcpServer;
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_service, read_frame, transfer, write_frame, FILE_SIZE, PROTOCOL_VERSION, TIMEOUT};
+
+// Starts a server with a single reactor, which all connections share
+fn start_server() -> SocketAddr {
+    let server = EpollTcpServer::new("127.0.0.1:0", get_service(), 1).unwrap();
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+    addr
+}
+
+#[test]
+fn test_concurrent_transfers_of_many_chunks_complete() {
+    // GIVEN
+    let addr: SocketAddr = start_server();
+    // WHEN
+    let handles: Vec<thread::JoinHandle<usize>> =
+        (0..4).map(|_| thread::spawn(move || transfer(addr))).collect();
+    // THEN
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), FILE_SIZE);
+    }
+}
+
+#[test]
+fn test_missing_file_is_reported_before_closing_the_connection() {
+    // GIVEN
+    let addr: SocketAddr = start_server();
+    let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // WHEN
+    let query = FileQuery {
+        version: PROTOCOL_VERSION,
+        filename: "missing.bin".to_string(),
+    };
+    write_frame(&mut stream, &query);
+    let response: FileResponse = read_frame(&mut stream).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Metadata(metadata)) => assert_eq!(metadata.status, Status::NotFound as i32),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    let mut received: Vec<u8> = Vec::new();
+    assert_eq!(stream.read_to_end(&mut received).unwrap(), 0);
+}
+
+#[test]
+fn test_connection_announcing_oversized_message_is_closed() {
+    // GIVEN
+    let addr: SocketAddr = start_server();
+    let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // WHEN
+    // The length prefix of a query of almost 4 GiB
+    stream.write_all(&u32::MAX.to_be_bytes()).unwrap();
+    let mut received: Vec<u8> = Vec::new();
+    let result = stream.read_to_end(&mut received);
+    // THEN
+    assert_eq!(result.unwrap(), 0);
+}
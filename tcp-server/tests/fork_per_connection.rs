@@ -0,0 +1,116 @@
+//! Checks that the fork-per-connection server reaps its children while idle, applies the
+//! limit policy, turns away connections over the connection limit before forking and only
+//! forks while it runs no other thread. Runs the `server` binary, so it needs the
+//! `fork_per_connection` feature.
+#![cfg(feature = "fork_per_connection")]
+use std::{fs, thread};
+use std::path::PathBuf;
+use std::net::TcpStream;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, query_file, read_frame, transfer, ServerProcess, FILE_SIZE, TIMEOUT};
+
+#[test]
+fn test_children_are_reaped_while_idle() {
+    // GIVEN
+    let server = ServerProcess::start(&["--max-processes", "2"]);
+    // WHEN
+    for _ in 0..3 {
+        assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+    }
+    // THEN
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    while !server.children().is_empty() {
+        assert!(
+            Instant::now() < deadline,
+            "Children were not reaped: {:?}",
+            server.children()
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_connections_are_rejected_at_limit() {
+    // GIVEN
+    let server = ServerProcess::start(&["--max-processes", "1", "--on-limit", "reject"]);
+    let busy: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    server.wait_for_log("Forked child");
+    // WHEN
+    let mut rejected: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    rejected.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let response: FileResponse = read_frame(&mut rejected).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Error(details)) => assert_eq!(details.kind, Kind::ServerBusy as i32),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+    server.wait_for_log("Rejected connection");
+    // The slot is free again once the busy child exits
+    drop(busy);
+    server.wait_for_log("Child exited");
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_connections_are_queued_at_limit() {
+    // GIVEN
+    let server = ServerProcess::start(&["--max-processes", "1", "--on-limit", "queue"]);
+    let busy: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    server.wait_for_log("Forked child");
+    // WHEN
+    let mut queued: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    queued.set_read_timeout(Some(Duration::from_millis(300))).unwrap();
+    // THEN
+    // Nobody handles the queued connection while the busy one is open
+    assert!(query_file(&mut queued).is_err());
+    drop(busy);
+
+    queued.set_read_timeout(Some(TIMEOUT)).unwrap();
+    assert!(read_frame::<FileResponse>(&mut queued).is_ok());
+}
+
+#[test]
+fn test_connections_over_the_connection_limit_get_busy_response() {
+    // GIVEN
+    let server = ServerProcess::start(&["--max-connections", "1"]);
+    let busy: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    server.wait_for_log("Forked child");
+    // WHEN
+    let mut rejected: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    rejected.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let response: FileResponse = read_frame(&mut rejected).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Error(details)) => assert_eq!(details.kind, Kind::ServerBusy as i32),
+        other => panic!("Expected a busy response, got {:?}", other),
+    }
+    server.wait_for_log("too many connections");
+    // The permit is released once the busy child is reaped
+    drop(busy);
+    server.wait_for_log("Child exited");
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_connections_are_forked_while_no_other_thread_runs() {
+    // GIVEN
+    let notify_path: PathBuf = get_base_dir().join("fork.notify");
+    let _ = fs::remove_file(&notify_path);
+    let _notify: UnixDatagram = UnixDatagram::bind(&notify_path).unwrap();
+    // The restart handover and a systemd watchdog are active, as they would be in production
+    let server = ServerProcess::start_with_env(
+        &["--max-processes", "2"],
+        &[("NOTIFY_SOCKET", notify_path.to_str().unwrap()), ("WATCHDOG_USEC", "200000")],
+    );
+    // WHEN
+    let received: Vec<usize> = (0..2).map(|_| transfer(server.socket_addr)).collect();
+    // THEN
+    // A child forked next to another thread could inherit a lock that thread holds
+    assert_eq!(server.threads(), 1);
+    assert_eq!(received, [FILE_SIZE, FILE_SIZE]);
+}
@@ -0,0 +1,90 @@
+//! Checks how the service builder stacks layers and the behaviour of the built-in layers.
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tcp_server::service::{Identity, Layer, LogErrorsLayer, Service, ServiceBuilder, TraceLayer};
+use tcp_server::stream::{duplex, Stream};
+
+type Calls = Arc<Mutex<Vec<&'static str>>>;
+
+/// Records its name before passing the connection on
+struct Record<S> {
+    name: &'static str,
+    calls: Calls,
+    inner: S,
+}
+
+impl<S: Service> Service for Record<S> {
+    fn handle_connection(&self, stream: impl Stream) -> io::Result<()> {
+        self.calls.lock().unwrap().push(self.name);
+        self.inner.handle_connection(stream)
+    }
+}
+
+struct RecordLayer {
+    name: &'static str,
+    calls: Calls,
+}
+
+impl<S> Layer<S> for RecordLayer {
+    type Service = Record<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Record {
+            name: self.name,
+            calls: Arc::clone(&self.calls),
+            inner,
+        }
+    }
+}
+
+/// Records the call and fails
+struct FailingService {
+    calls: Calls,
+}
+
+impl Service for FailingService {
+    fn handle_connection(&self, _stream: impl Stream) -> io::Result<()> {
+        self.calls.lock().unwrap().push("service");
+        Err(io::Error::other("failed"))
+    }
+}
+
+#[test]
+fn test_layers_added_first_are_outermost() {
+    // GIVEN
+    let calls: Calls = Calls::default();
+    let record = |name: &'static str| RecordLayer {
+        name,
+        calls: Arc::clone(&calls),
+    };
+    let service = ServiceBuilder::new()
+        .layer(record("outer"))
+        .layer(Identity)
+        .layer(record("inner"))
+        .service(FailingService {
+            calls: Arc::clone(&calls),
+        });
+    // WHEN
+    let result: io::Result<()> = service.handle_connection(duplex().1);
+    // THEN
+    assert!(result.is_err());
+    assert_eq!(*calls.lock().unwrap(), ["outer", "inner", "service"]);
+}
+
+#[test]
+fn test_log_errors_layer_reports_errors_instead_of_returning_them() {
+    // GIVEN
+    let calls: Calls = Calls::default();
+    let service = ServiceBuilder::new()
+        .layer(TraceLayer)
+        .layer(LogErrorsLayer)
+        .service(FailingService {
+            calls: Arc::clone(&calls),
+        });
+    // WHEN
+    let result: io::Result<()> = service.handle_connection(duplex().1);
+    // THEN
+    assert!(result.is_ok());
+    assert_eq!(*calls.lock().unwrap(), ["service"]);
+}
@@ -0,0 +1,177 @@
+//! Checks the connection limits, rate limits and network lists of the connection limiter,
+//! and that a limited server turns clients away as it accepts them.
+use std::{env, fs, thread};
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use tcp_server::core::{IterativeTcpServer, ThreadPoolTcpServer};
+use tcp_server::limit::{Cidr, ConnectionLimiter, ConnectionPermit, Limits, RateLimit, Rejection};
+use tcp_server::listener::UnixAddr;
+use tcp_server::service::{DelayedEchoService, FileTransferService};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, read_frame, CHUNK_SIZE, PROTOCOL_VERSION};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_cidr_parses_and_matches_networks() {
+    // GIVEN
+    let inputs: [&str; 6] = [
+        "10.0.0.0/8",
+        "192.168.1.7",
+        "fd00::/8",
+        "::/0",
+        "10.0.0.0/33",
+        "10.0.0/8",
+    ];
+    // WHEN
+    let parsed: Vec<Result<Cidr, String>> = inputs.iter().map(|input| input.parse()).collect();
+    // THEN
+    let private: Cidr = parsed[0].clone().unwrap();
+    assert!(private.contains(ip("10.1.2.3")));
+    assert!(private.contains(ip("::ffff:10.1.2.3")));
+    assert!(!private.contains(ip("11.0.0.1")));
+    assert!(!private.contains(ip("fd00::1")));
+
+    let host: Cidr = parsed[1].clone().unwrap();
+    assert!(host.contains(ip("192.168.1.7")));
+    assert!(!host.contains(ip("192.168.1.8")));
+
+    assert!(parsed[2].clone().unwrap().contains(ip("fd12::1")));
+    assert!(parsed[3].clone().unwrap().contains(ip("2001:db8::1")));
+    assert!(parsed[4].is_err());
+    assert!(parsed[5].is_err());
+}
+
+#[test]
+fn test_connection_limits_free_up_when_permits_are_dropped() {
+    // GIVEN
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        max_connections: Some(3),
+        max_connections_per_ip: Some(2),
+        ..Default::default()
+    });
+    let first: ConnectionPermit = limiter.admit(ip("10.0.0.1")).unwrap();
+    let _second: ConnectionPermit = limiter.admit(ip("10.0.0.1")).unwrap();
+    // WHEN
+    let from_same_ip = limiter.admit(ip("10.0.0.1"));
+    let _third: ConnectionPermit = limiter.admit(ip("10.0.0.2")).unwrap();
+    let over_global_limit = limiter.admit(ip("10.0.0.3"));
+    drop(first);
+    let after_drop = limiter.admit(ip("10.0.0.1"));
+    // THEN
+    assert_eq!(from_same_ip.unwrap_err(), Rejection::TooManyConnectionsFromIp);
+    assert_eq!(over_global_limit.unwrap_err(), Rejection::TooManyConnections);
+    assert!(after_drop.is_ok());
+    assert_eq!(limiter.connections(), 3);
+
+    let stats = limiter.stats();
+    assert_eq!(stats.admitted, 4);
+    assert_eq!(stats.too_many_connections_from_ip, 1);
+    assert_eq!(stats.too_many_connections, 1);
+    assert_eq!(stats.rejected(), 2);
+}
+
+#[test]
+fn test_rate_limit_allows_bursts_and_refills() {
+    // GIVEN
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        rate: Some(RateLimit {
+            per_second: 20.0,
+            burst: 3,
+        }),
+        ..Default::default()
+    });
+    // WHEN
+    let burst: Vec<bool> = (0..4).map(|_| limiter.admit(ip("10.0.0.1")).is_ok()).collect();
+    let other_ip = limiter.admit(ip("10.0.0.2"));
+    thread::sleep(Duration::from_millis(100));
+    let after_refill = limiter.admit(ip("10.0.0.1"));
+    // THEN
+    assert_eq!(burst, [true, true, true, false]);
+    assert!(other_ip.is_ok());
+    assert!(after_refill.is_ok());
+    assert_eq!(limiter.stats().rate_limited, 1);
+}
+
+#[test]
+fn test_deny_list_overrides_allow_list() {
+    // GIVEN
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        allow: vec!["10.0.0.0/8".parse().unwrap()],
+        deny: vec!["10.6.0.0/16".parse().unwrap()],
+        ..Default::default()
+    });
+    // WHEN
+    let allowed = limiter.admit(ip("10.5.0.1"));
+    let denied = limiter.admit(ip("10.6.0.1"));
+    let not_allowed = limiter.admit(ip("192.168.0.1"));
+    // THEN
+    assert!(allowed.is_ok());
+    assert_eq!(denied.unwrap_err(), Rejection::Denied);
+    assert_eq!(not_allowed.unwrap_err(), Rejection::Denied);
+    assert_eq!(limiter.stats().denied, 2);
+}
+
+#[test]
+fn test_limited_server_turns_away_clients_over_the_limit() {
+    // GIVEN
+    let base_dir: PathBuf = env::temp_dir().join(format!("tcp-server-limit-{}", std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        max_connections_per_ip: Some(1),
+        ..Default::default()
+    });
+    let service = FileTransferService::new(base_dir, PROTOCOL_VERSION, CHUNK_SIZE);
+    let server = ThreadPoolTcpServer::new("127.0.0.1:0", service, 2)
+        .unwrap()
+        .with_limiter(Arc::clone(&limiter));
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+    // Holds the only place for this address
+    let _holder: TcpStream = TcpStream::connect(addr).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    // WHEN
+    let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let response: FileResponse = read_frame(&mut stream).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Error(details)) => assert_eq!(details.kind, Kind::ServerBusy as i32),
+        other => panic!("Expected a busy response, got {:?}", other),
+    }
+    assert_eq!(limiter.stats().too_many_connections_from_ip, 1);
+    assert_eq!(limiter.connections(), 1);
+}
+
+#[test]
+fn test_peers_without_ip_address_are_not_limited() {
+    // GIVEN
+    let limiter: Arc<ConnectionLimiter> = ConnectionLimiter::new(Limits {
+        max_connections: Some(0),
+        ..Default::default()
+    });
+    let path: PathBuf = get_base_dir().join("limit.sock");
+    let server = IterativeTcpServer::new(UnixAddr::path(&path), DelayedEchoService::new(0))
+        .unwrap()
+        .with_limiter(Arc::clone(&limiter));
+    thread::spawn(move || server.serve());
+    // WHEN
+    let mut client: UnixStream = UnixStream::connect(&path).unwrap();
+    client.write_all(b"ping\n\n").unwrap();
+    // THEN
+    let mut echo: String = String::new();
+    client.read_to_string(&mut echo).unwrap();
+    assert_eq!(echo, "ping\n");
+    assert_eq!(limiter.stats().admitted, 0);
+}
@@ -0,0 +1,240 @@
+//! Checks that the prefork server serves transfers with every accept strategy, replaces
+//! children that exit, delays the respawn of children that keep crashing, resizes an adaptive
+//! pool with the load, replaces children after their maximum number of requests and enforces
+//! the connection limit across children, that children are only ever forked while the server
+//! runs no other thread, and that pool sizes it cannot keep are refused. Runs the `server`
+//! binary, so it needs the `prefork` feature.
+#![cfg(feature = "prefork")]
+use std::{fs, io, thread};
+use std::path::PathBuf;
+use std::net::TcpStream;
+use std::os::unix::net::UnixDatagram;
+use std::time::{Duration, Instant};
+
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, is_running, query_file, read_frame, receive_file, transfer, ServerProcess};
+use common::{usage_error, FILE_SIZE, TIMEOUT};
+
+// Kills the child and returns how long it took the server to fork its replacement
+fn kill_and_wait_for_respawn(server: &ServerProcess, pid: i32) -> (i32, Duration) {
+    let start: Instant = Instant::now();
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+
+    loop {
+        let children: Vec<i32> = server.children();
+        if let [new_pid] = children[..] {
+            if new_pid != pid {
+                return (new_pid, start.elapsed());
+            }
+        }
+        assert!(start.elapsed() < TIMEOUT, "Child {} was not replaced", pid);
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_transfers_are_served_with_every_accept_strategy() {
+    for strategy in ["shared", "mutex", "flock", "reuseport"] {
+        // GIVEN
+        let server = ServerProcess::start(&["--processes", "2", "--accept", strategy]);
+        server.wait_for_log("Accept strategy");
+        // WHEN
+        let received: Vec<usize> = (0..8).map(|_| transfer(server.socket_addr)).collect();
+        // THEN
+        assert!(
+            received.iter().all(|&len| len == FILE_SIZE),
+            "{} strategy cut transfers short: {:?}",
+            strategy,
+            received
+        );
+    }
+}
+
+#[test]
+fn test_reuseport_keeps_the_listener_of_the_server() {
+    // GIVEN
+    let server = ServerProcess::start(&["--processes", "2", "--accept", "reuseport"]);
+    server.wait_for_log("strategy=ReusePort");
+    let children: Vec<i32> = server.wait_for_children(2);
+    // WHEN
+    // Kill both children, so that only the listener of the server is left to queue connections
+    for pid in &children {
+        unsafe { libc::kill(*pid, libc::SIGKILL) };
+    }
+    while children.iter().any(|&pid| is_running(pid)) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let mut stream: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // THEN
+    // The connection waits in the queue of the listener until a respawned child accepts it
+    query_file(&mut stream).unwrap();
+    assert_eq!(receive_file(&mut stream), FILE_SIZE);
+}
+
+#[test]
+fn test_killed_child_is_replaced() {
+    // GIVEN
+    let server = ServerProcess::start(&["--processes", "2"]);
+    let children: Vec<i32> = server.wait_for_children(2);
+    // WHEN
+    unsafe { libc::kill(children[0], libc::SIGKILL) };
+    server.wait_for_log("Child exited");
+    // THEN
+    server.wait_for_log("Forked child process");
+    let replaced: Vec<i32> = server.wait_for_children(2);
+    assert!(!replaced.contains(&children[0]));
+    assert!(replaced.contains(&children[1]));
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_crashing_children_are_respawned_with_growing_delay() {
+    // GIVEN
+    let server = ServerProcess::start(&["--processes", "1"]);
+    let mut pid: i32 = server.wait_for_children(1)[0];
+    // WHEN
+    let mut delays: Vec<Duration> = Vec::new();
+    for _ in 0..4 {
+        let (new_pid, delay) = kill_and_wait_for_respawn(&server, pid);
+        server.wait_for_log("Child exited shortly after start. Delaying respawn");
+        pid = new_pid;
+        delays.push(delay);
+    }
+    // THEN
+    // The delay starts at 100ms and doubles with every early exit
+    assert!(delays[0] >= Duration::from_millis(100), "{:?}", delays);
+    assert!(delays[3] >= Duration::from_millis(800), "{:?}", delays);
+    assert!(delays[3] > delays[0] * 4, "{:?}", delays);
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_adaptive_pool_grows_under_load_and_shrinks_to_max_spare() {
+    // GIVEN
+    let server = ServerProcess::start(&[
+        "--processes", "1", "--min-spare", "1", "--max-spare", "2", "--max-children", "5",
+    ]);
+    server.wait_for_children(1);
+    // WHEN
+    // Each connection keeps a child busy until the transfer is acknowledged
+    let busy: Vec<TcpStream> = (0..3)
+        .map(|_| {
+            let mut stream: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+            stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+            query_file(&mut stream).unwrap();
+            stream
+        })
+        .collect();
+    // THEN
+    // Three busy children and the one idle child kept in reserve
+    server.wait_for_children(4);
+    drop(busy);
+    server.wait_for_log("Retiring a spare child");
+    server.wait_for_children(2);
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_child_is_replaced_after_max_requests() {
+    // GIVEN
+    let server = ServerProcess::start(&["--processes", "1", "--max-requests", "2"]);
+    let pid: i32 = server.wait_for_children(1)[0];
+    // WHEN
+    for _ in 0..2 {
+        assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+    }
+    // THEN
+    server.wait_for_log("Reached the maximum number of requests");
+    server.wait_for_log("Child retired");
+    let replaced: Vec<i32> = server.wait_for_children(1);
+    assert_ne!(replaced[0], pid);
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+}
+
+#[test]
+fn test_server_refuses_pool_sizes_it_cannot_keep() {
+    // GIVEN
+    let spare: &[&str] = &[
+        "--processes", "2", "--min-spare", "3", "--max-spare", "1", "--max-children", "4",
+    ];
+    let children: &[&str] = &[
+        "--processes", "4", "--min-spare", "1", "--max-spare", "2", "--max-children", "2",
+    ];
+    // WHEN
+    let spare_error: String = usage_error(spare);
+    let children_error: String = usage_error(children);
+    let requests_error: String = usage_error(&["--max-requests", "0"]);
+    // THEN
+    assert!(spare_error.contains("--min-spare must not be greater than --max-spare"), "{}", spare_error);
+    assert!(
+        children_error.contains("--max-children must not be less than --processes"),
+        "{}",
+        children_error
+    );
+    assert!(requests_error.contains("--max-requests"), "{}", requests_error);
+}
+
+#[test]
+fn test_connection_limit_holds_across_children() {
+    // GIVEN
+    let server = ServerProcess::start(&["--processes", "2", "--max-connections", "1"]);
+    server.wait_for_children(2);
+    // Keeps one child busy with an admitted connection
+    let mut busy: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    busy.set_read_timeout(Some(TIMEOUT)).unwrap();
+    query_file(&mut busy).unwrap();
+    // WHEN
+    // Only the other child is left to accept the connection
+    let mut rejected: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    rejected.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let response: FileResponse = read_frame(&mut rejected).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Error(details)) => assert_eq!(details.kind, Kind::ServerBusy as i32),
+        other => panic!("Expected a busy response, got {:?}", other),
+    }
+    // The child releases its permit after closing the connection, so the next one may still be rejected
+    drop(busy);
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    loop {
+        let mut stream: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let response: io::Result<FileResponse> = query_file(&mut stream);
+        if matches!(response, Ok(FileResponse { response: Some(Response::Metadata(_)) })) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "The permit of the closed connection was not released");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_children_are_respawned_while_no_other_thread_runs() {
+    // GIVEN
+    let notify_path: PathBuf = get_base_dir().join("respawn.notify");
+    let _ = fs::remove_file(&notify_path);
+    let notify: UnixDatagram = UnixDatagram::bind(&notify_path).unwrap();
+    notify.set_read_timeout(Some(TIMEOUT)).unwrap();
+    // The restart handover and a systemd watchdog are active, as they would be in production
+    let server = ServerProcess::start_with_env(
+        &["--processes", "1"],
+        &[("NOTIFY_SOCKET", notify_path.to_str().unwrap()), ("WATCHDOG_USEC", "200000")],
+    );
+    let pid: i32 = server.wait_for_children(1)[0];
+    // WHEN
+    let (new_pid, _) = kill_and_wait_for_respawn(&server, pid);
+    // THEN
+    // A child forked next to another thread could inherit a lock that thread holds
+    assert_eq!(server.threads(), 1);
+    assert_ne!(new_pid, pid);
+    assert_eq!(transfer(server.socket_addr), FILE_SIZE);
+    let mut buf: [u8; 64] = [0; 64];
+    let pinged: bool = (0..5).any(|_| {
+        let len: usize = notify.recv(&mut buf).unwrap();
+        &buf[..len] == b"WATCHDOG=1"
+    });
+    assert!(pinged, "The watchdog was not pinged");
+}
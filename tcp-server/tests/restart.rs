@@ -0,0 +1,145 @@
+//! Checks that a hot restart hands the listener over to a new server process while the old one
+//! finishes its in-flight transfers. Runs the `server` binary built with the enabled features.
+use std::{env, fs, thread};
+use std::sync::mpsc;
+use std::path::PathBuf;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{read_frame, write_frame, PROTOCOL_VERSION};
+
+const FILE_NAME: &str = "data.bin";
+const FILE_SIZE: usize = 256 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Kills the server processes when the test ends, even if it fails
+struct Servers(Vec<i32>);
+
+impl Drop for Servers {
+    fn drop(&mut self) {
+        self.0.iter().for_each(|&pid| unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        });
+    }
+}
+
+fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+// Starts the server and forwards its log lines (including those of the processes it starts)
+fn start_server(socket_addr: SocketAddr, base_dir: &PathBuf) -> (Child, mpsc::Receiver<String>) {
+    let mut server: Child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--socket_addr", &socket_addr.to_string()])
+        .args(["--shutdown-timeout", "5"])
+        .arg("--dir")
+        .arg(base_dir)
+        .env("RUST_LOG", "info")
+        .env("NO_COLOR", "1")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    let stdout = BufReader::new(server.stdout.take().unwrap());
+    // Keeps reading until every writer exits, so that the servers never block on a full pipe
+    thread::spawn(move || {
+        for line in stdout.lines().map_while(Result::ok) {
+            let _ = tx.send(line);
+        }
+    });
+
+    (server, rx)
+}
+
+// Waits for a log line containing `pattern`
+fn wait_for_log(logs: &mpsc::Receiver<String>, pattern: &str) -> String {
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    loop {
+        let timeout: Duration = deadline.saturating_duration_since(Instant::now());
+        match logs.recv_timeout(timeout) {
+            Ok(line) if line.contains(pattern) => return line,
+            Ok(_) => {}
+            Err(e) => panic!("No log line containing {:?}: {}", pattern, e),
+        }
+    }
+}
+
+fn wait_for_exit(server: &mut Child) -> ExitStatus {
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(status) = server.try_wait().unwrap() {
+            return status;
+        }
+        assert!(Instant::now() < deadline, "The old server did not exit in time");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Requests the file and reads the response, leaving the transfer waiting for the acknowledgement
+fn start_transfer(socket_addr: SocketAddr) -> TcpStream {
+    let mut stream: TcpStream = TcpStream::connect(socket_addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    write_frame(
+        &mut stream,
+        &FileQuery {
+            version: PROTOCOL_VERSION,
+            filename: FILE_NAME.to_string(),
+        },
+    );
+    read_frame::<FileResponse>(&mut stream).unwrap();
+
+    stream
+}
+
+// Acknowledges the transfer and returns the number of bytes received
+fn finish_transfer(mut stream: TcpStream) -> usize {
+    write_frame(
+        &mut stream,
+        &TransferAck {
+            status: AckStatus::Accepted as i32,
+        },
+    );
+    let mut received: usize = 0;
+    while let Ok(chunk) = read_frame::<FileChunk>(&mut stream) {
+        received += chunk.data.len();
+    }
+
+    received
+}
+
+#[test]
+fn test_restart_hands_over_listener_and_drains_old_server() {
+    // GIVEN
+    let base_dir: PathBuf = env::temp_dir().join(format!("tcp-server-restart-{}", std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    fs::write(base_dir.join(FILE_NAME), vec![7; FILE_SIZE]).unwrap();
+
+    let socket_addr: SocketAddr = free_addr();
+    let (mut old_server, logs) = start_server(socket_addr, &base_dir);
+    let mut servers = Servers(vec![old_server.id() as i32]);
+    wait_for_log(&logs, "Listening on");
+
+    let in_flight: TcpStream = start_transfer(socket_addr);
+    // WHEN
+    unsafe { libc::kill(old_server.id() as i32, libc::SIGHUP) };
+    let ready: String = wait_for_log(&logs, "New server is ready");
+    let new_pid: i32 = ready.rsplit("pid=").next().unwrap().trim().parse().unwrap();
+    servers.0.push(new_pid);
+    // THEN
+    // New connections are served by the new server while the old one is still draining
+    assert_eq!(finish_transfer(start_transfer(socket_addr)), FILE_SIZE);
+    assert_eq!(finish_transfer(in_flight), FILE_SIZE);
+
+    assert!(wait_for_exit(&mut old_server).success());
+    // The old process has been reaped, so its pid may be reused
+    servers.0.remove(0);
+    assert_eq!(finish_transfer(start_transfer(socket_addr)), FILE_SIZE);
+
+    fs::remove_dir_all(&base_dir).unwrap();
+}
@@ -0,0 +1,220 @@
+//! Checks that the servers stop accepting on shutdown, let in-flight transfers finish
+//! and abort the connections that outlive the grace period. The forking models are
+//! checked through the `server` binary built with their feature.
+use std::{io, thread};
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use tcp_server::core::{IterativeTcpServer, ThreadPoolTcpServer, EpollTcpServer};
+#[cfg(feature = "tokio")]
+use tcp_server::core::AsyncTcpServer;
+use tcp_server::shutdown::Shutdown;
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_service, read_frame, write_frame, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+#[cfg(any(feature = "fork_per_connection", feature = "prefork"))]
+use common::{is_running, query_file, receive_file, ServerProcess, TIMEOUT};
+
+type ServerHandle = (SocketAddr, Shutdown, thread::JoinHandle<io::Result<()>>);
+
+fn start_iterative_server(grace_period: Duration) -> ServerHandle {
+    let server = IterativeTcpServer::new("127.0.0.1:0", get_service())
+        .unwrap()
+        .with_shutdown(Shutdown::new(grace_period).unwrap());
+
+    (
+        server.local_addr().unwrap(),
+        server.shutdown_handle(),
+        thread::spawn(move || server.serve()),
+    )
+}
+
+fn start_thread_pool_server(grace_period: Duration) -> ServerHandle {
+    let server = ThreadPoolTcpServer::new("127.0.0.1:0", get_service(), 2)
+        .unwrap()
+        .with_shutdown(Shutdown::new(grace_period).unwrap());
+
+    (
+        server.local_addr().unwrap(),
+        server.shutdown_handle(),
+        thread::spawn(move || server.serve()),
+    )
+}
+
+fn start_epoll_server(grace_period: Duration) -> ServerHandle {
+    let server = EpollTcpServer::new("127.0.0.1:0", get_service(), 2)
+        .unwrap()
+        .with_shutdown(Shutdown::new(grace_period).unwrap());
+
+    (
+        server.local_addr().unwrap(),
+        server.shutdown_handle(),
+        thread::spawn(move || server.serve()),
+    )
+}
+
+#[cfg(feature = "tokio")]
+fn start_async_server(grace_period: Duration) -> ServerHandle {
+    let server = AsyncTcpServer::new("127.0.0.1:0", get_service())
+        .unwrap()
+        .with_shutdown(Shutdown::new(grace_period).unwrap());
+
+    (
+        server.local_addr().unwrap(),
+        server.shutdown_handle(),
+        thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(server.serve())),
+    )
+}
+
+fn start_servers(grace_period: Duration) -> Vec<(&'static str, ServerHandle)> {
+    #[allow(unused_mut)]
+    let mut servers = vec![
+        ("iterative", start_iterative_server(grace_period)),
+        ("thread pool", start_thread_pool_server(grace_period)),
+        ("epoll", start_epoll_server(grace_period)),
+    ];
+    #[cfg(feature = "tokio")]
+    servers.push(("async", start_async_server(grace_period)));
+
+    servers
+}
+
+// Waits for the server thread to finish, failing the test if it takes too long
+fn join_server(name: &str, handle: thread::JoinHandle<io::Result<()>>, timeout: Duration) {
+    let start: Instant = Instant::now();
+    while !handle.is_finished() {
+        assert!(
+            start.elapsed() < timeout,
+            "{} server did not shut down in time",
+            name
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    handle.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_idle_servers_stop_on_shutdown() {
+    // GIVEN
+    let servers = start_servers(Duration::from_secs(5));
+    // WHEN
+    servers
+        .iter()
+        .for_each(|(_, (_, shutdown, _))| shutdown.trigger());
+    // THEN
+    for (name, (addr, shutdown, handle)) in servers {
+        assert!(shutdown.is_triggered());
+        join_server(name, handle, Duration::from_secs(5));
+        // The listener is closed together with the server
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
+
+#[test]
+fn test_in_flight_transfer_finishes_after_shutdown() {
+    // GIVEN
+    let servers = start_servers(Duration::from_secs(5));
+    let mut streams: Vec<TcpStream> = servers
+        .iter()
+        .map(|(_, (addr, _, _))| {
+            let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+            write_frame(
+                &mut stream,
+                &FileQuery {
+                    version: PROTOCOL_VERSION,
+                    filename: FILE_NAME.to_string(),
+                },
+            );
+            read_frame::<FileResponse>(&mut stream).unwrap();
+            stream
+        })
+        .collect();
+    // WHEN
+    servers
+        .iter()
+        .for_each(|(_, (_, shutdown, _))| shutdown.trigger());
+    thread::sleep(Duration::from_millis(100));
+    // THEN
+    for ((name, (_, _, handle)), stream) in servers.into_iter().zip(&mut streams) {
+        write_frame(
+            stream,
+            &TransferAck {
+                status: AckStatus::Accepted as i32,
+            },
+        );
+        let mut received: usize = 0;
+        while let Ok(chunk) = read_frame::<FileChunk>(stream) {
+            received += chunk.data.len();
+        }
+
+        assert_eq!(received, FILE_SIZE, "{} server cut the transfer short", name);
+        join_server(name, handle, Duration::from_secs(5));
+    }
+}
+
+#[test]
+fn test_stalled_connection_is_aborted_after_grace_period() {
+    // GIVEN
+    let servers = start_servers(Duration::from_millis(200));
+    let mut streams: Vec<TcpStream> = servers
+        .iter()
+        .map(|(_, (addr, _, _))| TcpStream::connect(addr).unwrap())
+        .collect();
+    // Let the servers pick up the connections
+    thread::sleep(Duration::from_millis(100));
+    // WHEN
+    servers
+        .iter()
+        .for_each(|(_, (_, shutdown, _))| shutdown.trigger());
+    // THEN
+    for ((name, (_, _, handle)), stream) in servers.into_iter().zip(&mut streams) {
+        join_server(name, handle, Duration::from_secs(3));
+
+        let mut buf: Vec<u8> = Vec::new();
+        stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        assert!(matches!(stream.read_to_end(&mut buf), Ok(0) | Err(_)));
+    }
+}
+
+#[cfg(any(feature = "fork_per_connection", feature = "prefork"))]
+#[test]
+fn test_forking_server_finishes_in_flight_transfer_after_sigterm() {
+    // GIVEN
+    let mut server = ServerProcess::start(&["--shutdown-timeout", "5"]);
+    let mut stream: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    query_file(&mut stream).unwrap();
+    // WHEN
+    server.signal(libc::SIGTERM);
+    server.wait_for_log("Shutdown requested");
+    // THEN
+    assert_eq!(receive_file(&mut stream), FILE_SIZE);
+    assert!(server.wait_for_exit(Duration::from_secs(5)));
+    assert!(TcpStream::connect(server.socket_addr).is_err());
+}
+
+#[cfg(any(feature = "fork_per_connection", feature = "prefork"))]
+#[test]
+fn test_forking_server_kills_stalled_children_after_grace_period() {
+    // GIVEN
+    let mut server = ServerProcess::start(&["--shutdown-timeout", "1"]);
+    let mut stream: TcpStream = TcpStream::connect(server.socket_addr).unwrap();
+    // Let a child pick up the connection
+    thread::sleep(Duration::from_millis(200));
+    let children: Vec<i32> = server.children();
+    let start: Instant = Instant::now();
+    // WHEN
+    server.signal(libc::SIGTERM);
+    // THEN
+    assert!(server.wait_for_exit(Duration::from_secs(5)));
+    assert!(start.elapsed() >= Duration::from_secs(1));
+    let mut buf: Vec<u8> = Vec::new();
+    stream.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+    assert!(matches!(stream.read_to_end(&mut buf), Ok(0) | Err(_)));
+    for pid in children {
+        assert!(!is_running(pid), "Child {} outlived the server", pid);
+    }
+}
@@ -0,0 +1,149 @@
+//! Checks the in-memory duplex streams and that the services run over them
+//! exactly as over TCP sockets, including the limit on the messages clients send.
+use std::{fs, io, thread};
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::time::{Duration, Instant};
+
+use tcp_server::service::{NonBlockingService, Service};
+use tcp_server::stream::{duplex, DuplexStream, PeerAddr, Stream};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, get_service, encode_frame, read_frame, FILE_NAME, PROTOCOL_VERSION};
+
+// Runs the client side of a transfer and returns the file data received
+fn download(stream: &mut DuplexStream) -> Vec<u8> {
+    let query = FileQuery {
+        version: PROTOCOL_VERSION,
+        filename: FILE_NAME.to_string(),
+    };
+    stream.write_all(&encode_frame(&query)).unwrap();
+    read_frame::<FileResponse>(stream).unwrap();
+    let ack = TransferAck {
+        status: AckStatus::Accepted as i32,
+    };
+    stream.write_all(&encode_frame(&ack)).unwrap();
+
+    let mut data: Vec<u8> = Vec::new();
+    while let Ok(chunk) = read_frame::<FileChunk>(stream) {
+        data.extend_from_slice(&chunk.data);
+    }
+    data
+}
+
+#[test]
+fn test_duplex_stream_delivers_data_until_the_writer_shuts_down() {
+    // GIVEN
+    let (mut client, mut server) = duplex();
+    // WHEN
+    client.write_all(b"hello ").unwrap();
+    client.write_all(b"world").unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut received: String = String::new();
+    server.read_to_string(&mut received).unwrap();
+    server.write_all(b"bye").unwrap();
+    drop(server);
+    let mut reply: Vec<u8> = Vec::new();
+    client.read_to_end(&mut reply).unwrap();
+    // THEN
+    assert_eq!(received, "hello world");
+    assert_eq!(reply, b"bye");
+    assert_eq!(
+        client.write(b"late").unwrap_err().kind(),
+        io::ErrorKind::BrokenPipe
+    );
+    assert_eq!(client.peer_addr().unwrap(), PeerAddr::Unnamed);
+}
+
+#[test]
+fn test_duplex_stream_reads_time_out_or_do_not_block() {
+    // GIVEN
+    let (_client, mut server) = duplex();
+    let timeout: Duration = Duration::from_millis(50);
+    server.set_read_timeout(Some(timeout)).unwrap();
+    let start: Instant = Instant::now();
+    // WHEN
+    let timed_out = server.read(&mut [0; 1]);
+    let elapsed: Duration = start.elapsed();
+    server.set_nonblocking(true).unwrap();
+    let would_block = server.read(&mut [0; 1]);
+    // THEN
+    assert_eq!(timed_out.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    assert!(elapsed >= timeout);
+    assert_eq!(would_block.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+}
+
+#[test]
+fn test_file_transfer_service_runs_over_in_memory_stream() {
+    // GIVEN
+    let service = get_service();
+    let (mut client, server) = duplex();
+    let handle = thread::spawn(move || Service::handle_connection(&service, server));
+    // WHEN
+    let data: Vec<u8> = download(&mut client);
+    // THEN
+    assert!(handle.join().unwrap().is_ok());
+    assert!(data == fs::read(get_base_dir().join(FILE_NAME)).unwrap());
+}
+
+#[test]
+fn test_non_blocking_service_closes_connections_announcing_oversized_messages() {
+    // GIVEN
+    let service = get_service();
+    let (mut client, mut server) = duplex();
+    server.set_nonblocking(true).unwrap();
+    let mut state = NonBlockingService::init(&service, &server);
+    // WHEN
+    // The length prefix of a query of almost 4 GiB
+    client.write_all(&u32::MAX.to_be_bytes()).unwrap();
+    let result = service.resume(&mut state, &mut server);
+    // THEN
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    let mut received: Vec<u8> = Vec::new();
+    assert_eq!(client.read_to_end(&mut received).unwrap(), 0);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_async_file_transfer_service_runs_over_in_memory_stream() {
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+    use tcp_server::service::AsyncService;
+    use common::CHUNK_SIZE;
+
+    // GIVEN
+    let service = get_service();
+    let (mut client, server) = tokio::io::duplex(CHUNK_SIZE);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let handle = runtime.spawn(async move { AsyncService::handle_connection(&service, server).await });
+    // WHEN
+    let received: Vec<u8> = runtime.block_on(async {
+        let query = FileQuery {
+            version: PROTOCOL_VERSION,
+            filename: FILE_NAME.to_string(),
+        };
+        client.write_all(&encode_frame(&query)).await.unwrap();
+        let mut len_buf: [u8; 4] = [0; 4];
+        client.read_exact(&mut len_buf).await.unwrap();
+        client
+            .read_exact(&mut vec![0; u32::from_be_bytes(len_buf) as usize])
+            .await
+            .unwrap();
+        let ack = TransferAck {
+            status: AckStatus::Accepted as i32,
+        };
+        client.write_all(&encode_frame(&ack)).await.unwrap();
+
+        let mut received: Vec<u8> = Vec::new();
+        client.read_to_end(&mut received).await.unwrap();
+        received
+    });
+    // THEN
+    assert!(runtime.block_on(handle).unwrap().is_ok());
+    let mut frames: &[u8] = &received;
+    let mut data: Vec<u8> = Vec::new();
+    while let Ok(chunk) = read_frame::<FileChunk>(&mut frames) {
+        data.extend_from_slice(&chunk.data);
+    }
+    assert!(data == fs::read(get_base_dir().join(FILE_NAME)).unwrap());
+}
@@ -0,0 +1,299 @@
+//! Checks socket activation and the notifications sent to systemd, with a datagram socket
+//! standing in for `NOTIFY_SOCKET`. The tests of the `server` binary run the server model
+//! selected by the enabled features.
+use std::{env, fs, io, thread};
+use std::path::PathBuf;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd as _;
+use std::os::unix::net::UnixDatagram;
+use std::os::unix::process::CommandExt as _;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use tcp_server::core::{EpollTcpServer, IterativeTcpServer, ThreadPoolTcpServer};
+#[cfg(feature = "tokio")]
+use tcp_server::core::AsyncTcpServer;
+use tcp_server::listener::UnixAddr;
+use tcp_server::service::FileTransferClient;
+use tcp_server::shutdown::Shutdown;
+use tcp_server::systemd::Notifier;
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, get_service, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stands in for the socket on which systemd receives notifications
+struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+}
+
+impl NotifySocket {
+    fn new(name: &str) -> Self {
+        let path: PathBuf = get_base_dir().join(format!("{}.notify", name));
+        let _ = fs::remove_file(&path);
+
+        Self {
+            socket: UnixDatagram::bind(&path).unwrap(),
+            path,
+        }
+    }
+
+    fn notifier(&self) -> Notifier {
+        Notifier::new(UnixAddr::path(&self.path))
+    }
+
+    fn recv(&self, timeout: Duration) -> io::Result<String> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let mut buf: [u8; 1024] = [0; 1024];
+        let len: usize = self.socket.recv(&mut buf)?;
+
+        Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+    }
+
+    // Waits for a notification that contains `pattern`, skipping the others
+    fn wait_for(&self, pattern: &str) -> String {
+        let deadline: Instant = Instant::now() + TIMEOUT;
+        loop {
+            let timeout: Duration = deadline.saturating_duration_since(Instant::now());
+            match self.recv(timeout.max(Duration::from_millis(1))) {
+                Ok(message) if message.contains(pattern) => return message,
+                Ok(_) => {}
+                Err(e) => panic!("No notification containing {:?}: {}", pattern, e),
+            }
+        }
+    }
+}
+
+// Kills the server processes when the test ends, even if it fails
+struct Servers(Vec<i32>);
+
+impl Drop for Servers {
+    fn drop(&mut self) {
+        self.0.iter().for_each(|&pid| unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        });
+    }
+}
+
+// Starts the server binary the way systemd does for socket activation: `listener` is passed
+// as descriptor 3 and `LISTEN_PID` names the server process
+fn start_activated_server(listener: &TcpListener, notify: &NotifySocket) -> Child {
+    let fd: i32 = listener.as_raw_fd();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(r#"exec 3<&"$ACTIVATED_FD"; LISTEN_PID=$$ LISTEN_FDS=1 exec "$0" "$@""#)
+        .arg(env!("CARGO_BIN_EXE_server"))
+        .args(["--shutdown-timeout", "5"])
+        .arg("--dir")
+        .arg(get_base_dir())
+        .env("ACTIVATED_FD", fd.to_string())
+        .env("NOTIFY_SOCKET", &notify.path);
+    // SAFETY: `fcntl` is async-signal-safe.
+    unsafe {
+        command.pre_exec(move || match libc::fcntl(fd, libc::F_SETFD, 0) {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        })
+    };
+
+    command.spawn().unwrap()
+}
+
+// Downloads the test file and returns its size
+fn download(addr: SocketAddr) -> io::Result<usize> {
+    let mut client = FileTransferClient::connect(addr, PROTOCOL_VERSION)?;
+    client.request_file(FILE_NAME)?;
+    client.send_ack(AckStatus::Accepted)?;
+
+    let mut data: Vec<u8> = Vec::new();
+    client.receive_file(&mut data)?;
+
+    Ok(data.len())
+}
+
+fn wait_for_exit(server: &mut Child) -> bool {
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Some(status) = server.try_wait().unwrap() {
+            return status.success();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    false
+}
+
+#[test]
+fn test_every_server_model_reports_ready_and_stopping() {
+    // GIVEN
+    let notify: Vec<NotifySocket> = ["iterative", "thread-pool", "epoll", "async"]
+        .into_iter()
+        .map(NotifySocket::new)
+        .collect();
+    let iterative = IterativeTcpServer::new("127.0.0.1:0", get_service())
+        .unwrap()
+        .with_notifier(notify[0].notifier());
+    let thread_pool = ThreadPoolTcpServer::new("127.0.0.1:0", get_service(), 2)
+        .unwrap()
+        .with_notifier(notify[1].notifier());
+    let epoll = EpollTcpServer::new("127.0.0.1:0", get_service(), 1)
+        .unwrap()
+        .with_notifier(notify[2].notifier());
+    #[allow(unused_mut)]
+    let mut servers: Vec<(SocketAddr, Shutdown, thread::JoinHandle<io::Result<()>>)> = vec![
+        (
+            iterative.local_addr().unwrap(),
+            iterative.shutdown_handle(),
+            thread::spawn(move || iterative.serve()),
+        ),
+        (
+            thread_pool.local_addr().unwrap(),
+            thread_pool.shutdown_handle(),
+            thread::spawn(move || thread_pool.serve()),
+        ),
+        (
+            epoll.local_addr().unwrap(),
+            epoll.shutdown_handle(),
+            thread::spawn(move || epoll.serve()),
+        ),
+    ];
+
+    #[cfg(feature = "tokio")]
+    {
+        let async_ = AsyncTcpServer::new("127.0.0.1:0", get_service())
+            .unwrap()
+            .with_notifier(notify[3].notifier());
+        servers.push((
+            async_.local_addr().unwrap(),
+            async_.shutdown_handle(),
+            thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(async_.serve())),
+        ));
+    }
+    // WHEN
+    for ((addr, shutdown, handle), notify) in servers.into_iter().zip(&notify) {
+        let ready: String = notify.recv(TIMEOUT).unwrap();
+        shutdown.trigger();
+        let stopping: String = notify.recv(TIMEOUT).unwrap();
+        // THEN
+        assert_eq!(ready, format!("READY=1\nSTATUS=Listening on {}", addr));
+        assert_eq!(stopping, "STOPPING=1\nSTATUS=Shutting down");
+        assert!(handle.join().unwrap().is_ok());
+    }
+}
+
+#[test]
+fn test_watchdog_is_pinged_until_shutdown() {
+    // GIVEN
+    let notify = NotifySocket::new("watchdog");
+    let interval: Duration = Duration::from_millis(100);
+    let server = ThreadPoolTcpServer::new("127.0.0.1:0", get_service(), 1)
+        .unwrap()
+        .with_notifier(notify.notifier().with_watchdog(interval));
+    let shutdown: Shutdown = server.shutdown_handle();
+    let handle = thread::spawn(move || server.serve());
+    notify.wait_for("READY=1");
+    // WHEN
+    let start: Instant = Instant::now();
+    let pings: Vec<String> = (0..3).map(|_| notify.recv(TIMEOUT).unwrap()).collect();
+    let elapsed: Duration = start.elapsed();
+    shutdown.trigger();
+    // THEN
+    assert!(pings.iter().all(|ping| ping == "WATCHDOG=1"), "{:?}", pings);
+    assert!(elapsed >= interval, "Pinged too often: 3 pings in {:?}", elapsed);
+    assert!(
+        elapsed < 3 * interval,
+        "Pinged too rarely: 3 pings in {:?}",
+        elapsed
+    );
+    assert!(notify.wait_for("STOPPING=1").starts_with("STOPPING=1"));
+    assert!(handle.join().unwrap().is_ok());
+}
+
+#[test]
+fn test_watchdog_is_not_pinged_while_the_serving_loop_is_stuck() {
+    // GIVEN
+    let notify = NotifySocket::new("stuck");
+    let interval: Duration = Duration::from_millis(100);
+    let server = IterativeTcpServer::new("127.0.0.1:0", get_service())
+        .unwrap()
+        .with_notifier(notify.notifier().with_watchdog(interval));
+    let addr: SocketAddr = server.local_addr().unwrap();
+    let shutdown: Shutdown = server.shutdown_handle();
+    let handle = thread::spawn(move || server.serve());
+    notify.wait_for("WATCHDOG=1");
+    // WHEN
+    // The iterative server handles the connection on its accepting thread, where it waits for
+    // a request that never comes
+    let stream: TcpStream = TcpStream::connect(addr).unwrap();
+    thread::sleep(interval);
+    // Skip a ping that may have been sent before the server got stuck
+    while notify.recv(Duration::from_millis(1)).is_ok() {}
+    let stuck = notify.recv(3 * interval);
+    drop(stream);
+    // THEN
+    assert!(stuck.is_err(), "Pinged while stuck: {:?}", stuck);
+    // Pinging resumes once the loop is back
+    assert_eq!(notify.recv(TIMEOUT).unwrap(), "WATCHDOG=1");
+    shutdown.trigger();
+    assert!(notify.wait_for("STOPPING=1").starts_with("STOPPING=1"));
+    assert!(handle.join().unwrap().is_ok());
+}
+
+#[test]
+fn test_server_binary_serves_on_activated_socket() {
+    // GIVEN
+    let notify = NotifySocket::new("activated");
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let mut server: Child = start_activated_server(&listener, &notify);
+    let _servers = Servers(vec![server.id() as i32]);
+    // WHEN
+    let ready: String = notify.wait_for("READY=1");
+    let received = download(addr);
+    unsafe { libc::kill(server.id() as i32, libc::SIGTERM) };
+    let stopping: String = notify.wait_for("STOPPING=1");
+    // THEN
+    assert_eq!(ready, format!("READY=1\nSTATUS=Listening on {}", addr));
+    assert_eq!(received.unwrap(), FILE_SIZE);
+    assert_eq!(stopping, "STOPPING=1\nSTATUS=Shutting down");
+    assert!(wait_for_exit(&mut server));
+}
+
+#[test]
+fn test_restarted_server_reports_its_pid_instead_of_stopping() {
+    // GIVEN
+    let notify = NotifySocket::new("restart");
+    let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr: SocketAddr = listener.local_addr().unwrap();
+    let mut old_server: Child = start_activated_server(&listener, &notify);
+    let mut servers = Servers(vec![old_server.id() as i32]);
+    notify.wait_for("READY=1");
+    // WHEN
+    unsafe { libc::kill(old_server.id() as i32, libc::SIGHUP) };
+    let ready: String = notify.wait_for("READY=1");
+    let new_pid: i32 = ready
+        .lines()
+        .find_map(|line| line.strip_prefix("MAINPID="))
+        .expect("No MAINPID")
+        .parse()
+        .unwrap();
+    servers.0.push(new_pid);
+    let old_exited: bool = wait_for_exit(&mut old_server);
+    let after_handover = notify.recv(Duration::from_millis(200));
+    let received = download(addr);
+    unsafe { libc::kill(new_pid, libc::SIGTERM) };
+    // THEN
+    assert_ne!(new_pid, old_server.id() as i32);
+    assert!(old_exited);
+    assert!(
+        after_handover.is_err(),
+        "Unexpected notification: {:?}",
+        after_handover
+    );
+    assert_eq!(received.unwrap(), FILE_SIZE);
+    assert!(notify.wait_for("STOPPING=1").starts_with("STOPPING=1"));
+}
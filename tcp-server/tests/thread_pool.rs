@@ -0,0 +1,302 @@
+//! Checks work stealing, elastic sizing, job handles and the bounded job queue of the thread pool,
+//! and the overload policies of the thread pool server. With the `threadpool` feature, also checks
+//! that the `server` binary refuses a pool that could never grow to its size.
+use std::{env, fs, io, thread};
+use std::sync::{mpsc, Arc, Barrier};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::path::PathBuf;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use tcp_server::core::{ThreadPoolTcpServer, OverloadPolicy};
+use tcp_server::service::FileTransferService;
+use tcp_server::thread_pool::{self, JobError, ThreadPool};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{read_frame, write_frame, CHUNK_SIZE, FILE_NAME, PROTOCOL_VERSION};
+#[cfg(feature = "threadpool")]
+use common::usage_error;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn start_server(name: &str, policy: OverloadPolicy) -> SocketAddr {
+    let base_dir: PathBuf = env::temp_dir().join(format!("tcp-server-pool-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&base_dir).unwrap();
+    fs::write(base_dir.join(FILE_NAME), vec![7; CHUNK_SIZE]).unwrap();
+
+    let service = FileTransferService::new(base_dir, PROTOCOL_VERSION, CHUNK_SIZE);
+    let server = ThreadPoolTcpServer::new("127.0.0.1:0", service, 1)
+        .unwrap()
+        .with_queue_capacity(1, policy);
+    let addr: SocketAddr = server.local_addr().unwrap();
+    thread::spawn(move || server.serve());
+
+    addr
+}
+
+fn query_file(addr: SocketAddr) -> io::Result<FileResponse> {
+    let mut stream: TcpStream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    write_frame(
+        &mut stream,
+        &FileQuery {
+            version: PROTOCOL_VERSION,
+            filename: FILE_NAME.to_string(),
+        },
+    );
+
+    read_frame::<FileResponse>(&mut stream)
+}
+
+// Occupies the only worker and the only place in the queue with connections that send nothing
+fn fill_server(addr: SocketAddr) -> Vec<TcpStream> {
+    (0..2)
+        .map(|_| {
+            let stream: TcpStream = TcpStream::connect(addr).unwrap();
+            // Let the server accept the connection and a worker pick it up if it is free
+            thread::sleep(Duration::from_millis(100));
+            stream
+        })
+        .collect()
+}
+
+#[test]
+fn test_bounded_queue_rejects_jobs_when_full() {
+    // GIVEN
+    let pool = ThreadPool::bounded(1, 2);
+    let barrier = Arc::new(Barrier::new(2));
+    let (tx, rx) = mpsc::channel();
+    // Blocks the only worker until the barrier is reached
+    let worker_barrier: Arc<Barrier> = Arc::clone(&barrier);
+    pool.execute(move || {
+        worker_barrier.wait();
+    });
+    while pool.queue_depth() > 0 {
+        thread::yield_now();
+    }
+    // WHEN
+    let results: Vec<bool> = (0..3)
+        .map(|i| {
+            let tx = tx.clone();
+            pool.try_execute(move || tx.send(i).unwrap()).is_ok()
+        })
+        .collect();
+    // THEN
+    assert_eq!(results, [true, true, false]);
+    assert_eq!(pool.queue_depth(), 2);
+
+    barrier.wait();
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), 0);
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), 1);
+}
+
+#[test]
+fn test_jobs_queued_by_busy_worker_are_stolen() {
+    // GIVEN
+    let pool = Arc::new(ThreadPool::new(2));
+    let (tx, rx) = mpsc::channel();
+    // WHEN
+    let inner: Arc<ThreadPool> = Arc::clone(&pool);
+    pool.execute(move || {
+        let (stolen_tx, stolen_rx) = mpsc::channel();
+        // Goes to the deque of this worker, which stays busy until another worker runs the job
+        inner.execute(move || stolen_tx.send(()).unwrap());
+        // The pool must not be dropped by one of its own workers
+        drop(inner);
+        tx.send(stolen_rx.recv_timeout(TIMEOUT).is_ok()).unwrap();
+    });
+    // THEN
+    assert!(rx.recv_timeout(TIMEOUT).unwrap());
+}
+
+#[test]
+fn test_elastic_pool_grows_while_jobs_queue_up_and_shrinks_when_idle() {
+    // GIVEN
+    let pool: ThreadPool = thread_pool::Builder::new(1)
+        .max_workers(3)
+        .keep_alive(Duration::from_millis(200))
+        .build();
+    let (started_tx, started_rx) = mpsc::channel();
+    // WHEN
+    // Each job keeps its worker busy until its sender is dropped
+    let releases: Vec<mpsc::Sender<()>> = (0..3)
+        .map(|_| {
+            let (release_tx, release_rx) = mpsc::channel::<()>();
+            let started_tx = started_tx.clone();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                let _ = release_rx.recv();
+            });
+            release_tx
+        })
+        .collect();
+    // THEN
+    for _ in 0..3 {
+        started_rx.recv_timeout(TIMEOUT).expect("The pool did not grow");
+    }
+    assert_eq!(pool.worker_count(), 3);
+
+    drop(releases);
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    while pool.worker_count() > 1 {
+        assert!(Instant::now() < deadline, "Idle workers did not retire");
+        thread::sleep(Duration::from_millis(10));
+    }
+    // The core worker keeps running jobs
+    let (tx, rx) = mpsc::channel();
+    pool.execute(move || tx.send(()).unwrap());
+    assert!(rx.recv_timeout(TIMEOUT).is_ok());
+}
+
+#[test]
+fn test_spawned_jobs_return_results_and_panics() {
+    // GIVEN
+    let pool = ThreadPool::new(2);
+    // WHEN
+    let value = pool.spawn(|| 6 * 7);
+    let panicked = pool.spawn(|| -> u32 { panic!("boom") });
+    // THEN
+    assert_eq!(value.join().unwrap(), 42);
+    match panicked.join() {
+        Err(JobError::Panicked(payload)) => assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom")),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_jobs_can_be_cancelled_before_they_start() {
+    // GIVEN
+    let pool = ThreadPool::new(1);
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let running = pool.spawn(move || release_rx.recv_timeout(TIMEOUT).is_ok());
+    let ran = Arc::new(AtomicBool::new(false));
+    let job_ran: Arc<AtomicBool> = Arc::clone(&ran);
+    let queued = pool.spawn(move || job_ran.store(true, Ordering::SeqCst));
+    while pool.queue_depth() > 1 {
+        thread::yield_now();
+    }
+    // WHEN
+    let cancelled: bool = queued.cancel();
+    release_tx.send(()).unwrap();
+    // THEN
+    assert!(cancelled);
+    assert!(!running.cancel());
+    assert!(running.join().unwrap());
+    assert!(matches!(queued.join(), Err(JobError::Cancelled)));
+    pool.join();
+    assert!(!ran.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_scoped_jobs_borrow_local_data() {
+    // GIVEN
+    let pool = ThreadPool::new(2);
+    let numbers: Vec<u64> = (1..=100).collect();
+    let mut doubled: Vec<u64> = numbers.clone();
+    // WHEN
+    let sum: u64 = pool.scope(|scope| {
+        doubled.chunks_mut(10).for_each(|chunk| {
+            scope.spawn(move || chunk.iter_mut().for_each(|n| *n *= 2));
+        });
+        let handles: Vec<_> = numbers
+            .chunks(25)
+            .map(|chunk| scope.spawn(move || chunk.iter().sum::<u64>()))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).sum()
+    });
+    // THEN
+    assert_eq!(sum, 5050);
+    assert_eq!(doubled.iter().sum::<u64>(), 10100);
+}
+
+#[test]
+fn test_join_waits_for_all_jobs_and_shutdown_timeout_detaches_stuck_workers() {
+    // GIVEN
+    let pool = ThreadPool::new(2);
+    let finished = Arc::new(AtomicUsize::new(0));
+    for _ in 0..10 {
+        let finished: Arc<AtomicUsize> = Arc::clone(&finished);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(10));
+            finished.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    // WHEN
+    pool.join();
+    // THEN
+    assert_eq!(finished.load(Ordering::SeqCst), 10);
+
+    pool.execute(|| thread::sleep(Duration::from_millis(500)));
+    let start: Instant = Instant::now();
+    assert!(!pool.shutdown_timeout(Duration::from_millis(50)));
+    assert!(start.elapsed() < Duration::from_millis(500));
+
+    let pool = ThreadPool::new(1);
+    pool.execute(|| thread::sleep(Duration::from_millis(10)));
+    assert!(pool.shutdown_timeout(TIMEOUT));
+}
+
+#[test]
+fn test_busy_policy_rejects_connections_with_busy_response() {
+    // GIVEN
+    let addr: SocketAddr = start_server("busy", OverloadPolicy::Busy);
+    let _streams: Vec<TcpStream> = fill_server(addr);
+    // WHEN
+    let response: FileResponse = query_file(addr).unwrap();
+    // THEN
+    match response.response {
+        Some(Response::Error(details)) => assert_eq!(details.kind, Kind::ServerBusy as i32),
+        other => panic!("Unexpected response: {:?}", other),
+    }
+}
+
+#[test]
+fn test_caller_runs_policy_handles_connections_on_accepting_thread() {
+    // GIVEN
+    let addr: SocketAddr = start_server("caller-runs", OverloadPolicy::CallerRuns);
+    let _streams: Vec<TcpStream> = fill_server(addr);
+    // WHEN
+    let response: FileResponse = query_file(addr).unwrap();
+    // THEN
+    assert!(matches!(response.response, Some(Response::Metadata(_))));
+}
+
+#[test]
+fn test_server_starts_its_workers_once_it_serves() {
+    // GIVEN
+    let service = FileTransferService::new(env::temp_dir(), PROTOCOL_VERSION, CHUNK_SIZE);
+    let server = ThreadPoolTcpServer::new("127.0.0.1:0", service, 2)
+        .unwrap()
+        .with_queue_capacity(4, OverloadPolicy::Block)
+        .with_max_workers(4, Duration::from_secs(1));
+    let shutdown = server.shutdown_handle();
+    // WHEN
+    let idle_workers: usize = server.worker_count();
+    let serving_workers: usize = thread::scope(|scope| {
+        let handle = scope.spawn(|| server.serve());
+        let deadline: Instant = Instant::now() + TIMEOUT;
+        while server.worker_count() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let workers: usize = server.worker_count();
+        shutdown.trigger();
+        handle.join().unwrap().unwrap();
+        workers
+    });
+    // THEN
+    // Configuring the server does not spawn any workers
+    assert_eq!(idle_workers, 0);
+    assert_eq!(serving_workers, 2);
+}
+
+#[test]
+#[cfg(feature = "threadpool")]
+fn test_server_refuses_fewer_max_workers_than_workers() {
+    // GIVEN
+    let args: [&str; 4] = ["--workers", "4", "--max-workers", "2"];
+    // WHEN
+    let error: String = usage_error(&args);
+    // THEN
+    assert!(error.contains("--max-workers must not be less than --workers"), "{}", error);
+}
@@ -0,0 +1,150 @@
+//! Checks that every server model drops connections that miss their handshake,
+//! read-idle or total deadlines, and that well-behaved clients are not affected.
+use std::{io, thread};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+use tcp_server::core::{IterativeTcpServer, ThreadPoolTcpServer, EpollTcpServer};
+#[cfg(feature = "tokio")]
+use tcp_server::core::AsyncTcpServer;
+use tcp_server::service::ConnectionTimeouts;
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_service, encode_frame, read_frame, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+
+const TIMEOUT: Duration = Duration::from_millis(300);
+/// Delay between the bytes a slow-loris client sends
+const DRIP_INTERVAL: Duration = Duration::from_millis(50);
+
+// Starts every in-process server model with the given timeouts and returns their addresses
+fn start_servers(timeouts: ConnectionTimeouts) -> Vec<(&'static str, SocketAddr)> {
+    let service = || get_service().with_timeouts(timeouts);
+
+    let iterative = IterativeTcpServer::new("127.0.0.1:0", service()).unwrap();
+    let thread_pool = ThreadPoolTcpServer::new("127.0.0.1:0", service(), 2).unwrap();
+    let epoll = EpollTcpServer::new("127.0.0.1:0", service(), 1).unwrap();
+
+    #[allow(unused_mut)]
+    let mut servers = vec![
+        ("iterative", iterative.local_addr().unwrap()),
+        ("thread pool", thread_pool.local_addr().unwrap()),
+        ("epoll", epoll.local_addr().unwrap()),
+    ];
+    thread::spawn(move || iterative.serve());
+    thread::spawn(move || thread_pool.serve());
+    thread::spawn(move || epoll.serve());
+    #[cfg(feature = "tokio")]
+    {
+        let async_ = AsyncTcpServer::new("127.0.0.1:0", service()).unwrap();
+        servers.push(("async", async_.local_addr().unwrap()));
+        thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(async_.serve()));
+    }
+
+    servers
+}
+
+// Returns `true` once the server has closed the connection, waiting at most `wait`
+fn is_closed(stream: &mut TcpStream, wait: Duration) -> bool {
+    stream.set_read_timeout(Some(wait)).unwrap();
+    match stream.read(&mut [0; 1]) {
+        Ok(0) => true,
+        Err(e) => !matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+        Ok(_) => panic!("Unexpected data from the server"),
+    }
+}
+
+#[test]
+fn test_silent_client_is_dropped_after_handshake_timeout() {
+    // GIVEN
+    let servers = start_servers(ConnectionTimeouts {
+        handshake: Some(TIMEOUT),
+        ..Default::default()
+    });
+    let start: Instant = Instant::now();
+    // WHEN
+    let mut streams: Vec<TcpStream> = servers
+        .iter()
+        .map(|(_, addr)| TcpStream::connect(addr).unwrap())
+        .collect();
+    // THEN
+    for ((name, _), stream) in servers.iter().zip(&mut streams) {
+        assert!(
+            is_closed(stream, Duration::from_secs(3)),
+            "{} server kept a silent client",
+            name
+        );
+    }
+    assert!(start.elapsed() >= TIMEOUT);
+}
+
+#[test]
+fn test_slow_loris_is_dropped_after_read_idle_timeout() {
+    // GIVEN
+    let servers = start_servers(ConnectionTimeouts {
+        read_idle: Some(TIMEOUT),
+        ..Default::default()
+    });
+    // Takes far longer than the timeout to send at one byte per interval
+    let query: Vec<u8> = encode_frame(&FileQuery {
+        version: PROTOCOL_VERSION,
+        filename: "x".repeat(100),
+    });
+    // WHEN
+    for (name, addr) in servers {
+        let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+        let start: Instant = Instant::now();
+        let mut closed: bool = false;
+
+        for byte in &query {
+            // Every byte resets a plain socket timeout, but not the deadline for the message
+            if stream.write_all(&[*byte]).is_err() || is_closed(&mut stream, DRIP_INTERVAL) {
+                closed = true;
+                break;
+            }
+        }
+        // THEN
+        assert!(closed, "{} server kept a slow-loris client", name);
+        assert!(start.elapsed() >= TIMEOUT);
+        assert!(
+            start.elapsed() < 10 * TIMEOUT,
+            "{} server dropped the client late",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_transfer_within_deadlines_completes() {
+    // GIVEN
+    let servers = start_servers(ConnectionTimeouts {
+        handshake: Some(TIMEOUT),
+        read_idle: Some(TIMEOUT),
+        total: Some(Duration::from_secs(5)),
+    });
+    // WHEN
+    for (name, addr) in servers {
+        let mut stream: TcpStream = TcpStream::connect(addr).unwrap();
+        // Each pause stays below the timeouts, but together they outlast the handshake timeout
+        thread::sleep(TIMEOUT * 2 / 3);
+        let query = FileQuery {
+            version: PROTOCOL_VERSION,
+            filename: FILE_NAME.to_string(),
+        };
+        stream.write_all(&encode_frame(&query)).unwrap();
+        read_frame::<FileResponse>(&mut stream).unwrap();
+        thread::sleep(TIMEOUT * 2 / 3);
+        let ack = TransferAck {
+            status: AckStatus::Accepted as i32,
+        };
+        stream.write_all(&encode_frame(&ack)).unwrap();
+
+        let mut received: usize = 0;
+        while let Ok(chunk) = read_frame::<FileChunk>(&mut stream) {
+            received += chunk.data.len();
+        }
+        // THEN
+        assert_eq!(received, FILE_SIZE, "{} server cut the transfer short", name);
+    }
+}
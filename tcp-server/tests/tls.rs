@@ -0,0 +1,178 @@
+//! Checks file transfers over TLS: certificates issued by a test CA generated on the fly,
+//! certificate selection via SNI and client certificate verification (mutual TLS).
+#![cfg(feature = "tls")]
+use std::{fs, io, thread};
+use std::sync::Arc;
+use std::net::SocketAddr;
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+use tcp_server::core::ThreadPoolTcpServer;
+#[cfg(feature = "tokio")]
+use tcp_server::core::AsyncTcpServer;
+use tcp_server::service::FileTransferClient;
+use tcp_server::tls::{ClientTlsBuilder, ServerTlsBuilder};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, get_service, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+
+/// A certificate authority that issues the test certificates
+struct Ca {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let key: KeyPair = KeyPair::generate().unwrap();
+        let mut params: CertificateParams = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+
+        Self {
+            cert: params.self_signed(&key).unwrap(),
+            key,
+        }
+    }
+
+    fn root(&self) -> Vec<CertificateDer<'static>> {
+        vec![self.cert.der().clone()]
+    }
+
+    // Issues a certificate for `name` along with its private key
+    fn issue(
+        &self,
+        name: &str,
+        usage: ExtendedKeyUsagePurpose,
+    ) -> (Vec<CertificateDer<'static>>, PrivateKeyDer<'static>) {
+        let key: KeyPair = KeyPair::generate().unwrap();
+        let mut params: CertificateParams = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        let cert: rcgen::Certificate = params.signed_by(&key, &self.cert, &self.key).unwrap();
+
+        (
+            vec![cert.der().clone()],
+            PrivatePkcs8KeyDer::from(key.serialize_der()).into(),
+        )
+    }
+}
+
+// Starts the blocking and (if enabled) async servers with the given configuration
+fn start_servers(config: Arc<rustls::ServerConfig>) -> Vec<(&'static str, SocketAddr)> {
+    let service = || get_service().with_tls(Arc::clone(&config));
+
+    let thread_pool = ThreadPoolTcpServer::new("127.0.0.1:0", service(), 2).unwrap();
+    #[allow(unused_mut)]
+    let mut servers = vec![("thread pool", thread_pool.local_addr().unwrap())];
+    thread::spawn(move || thread_pool.serve());
+
+    #[cfg(feature = "tokio")]
+    {
+        let async_ = AsyncTcpServer::new("127.0.0.1:0", service()).unwrap();
+        servers.push(("async", async_.local_addr().unwrap()));
+        thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(async_.serve()));
+    }
+
+    servers
+}
+
+// Downloads the test file over TLS
+fn download(addr: SocketAddr, config: Arc<rustls::ClientConfig>, server_name: &str) -> io::Result<Vec<u8>> {
+    let mut client = FileTransferClient::connect_tls(addr, PROTOCOL_VERSION, config, server_name)?;
+
+    match client.request_file(FILE_NAME)?.response {
+        Some(Response::Metadata(metadata)) => assert_eq!(metadata.file_size, FILE_SIZE as u64),
+        other => panic!("Expected file metadata, got {:?}", other),
+    }
+    client.send_ack(AckStatus::Accepted)?;
+
+    let mut data: Vec<u8> = Vec::new();
+    client.receive_file(&mut data)?;
+
+    Ok(data)
+}
+
+#[test]
+fn test_tls_transfer_delivers_the_whole_file() {
+    // GIVEN
+    let ca: Ca = Ca::new();
+    let (chain, key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    let servers = start_servers(ServerTlsBuilder::new().certificate(chain, key).build().unwrap());
+    let client_config = ClientTlsBuilder::new()
+        .root_certificates(ca.root())
+        .build()
+        .unwrap();
+    let expected: Vec<u8> = fs::read(get_base_dir().join(FILE_NAME)).unwrap();
+    // WHEN
+    for (name, addr) in servers {
+        let data: Vec<u8> = download(addr, Arc::clone(&client_config), "localhost").unwrap();
+        // THEN
+        assert!(data == expected, "{} server sent different data", name);
+    }
+}
+
+#[test]
+fn test_sni_selects_the_certificate_for_the_requested_name() {
+    // GIVEN
+    let ca: Ca = Ca::new();
+    let (default_chain, default_key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    let (files_chain, files_key) = ca.issue("files.example", ExtendedKeyUsagePurpose::ServerAuth);
+    let config = ServerTlsBuilder::new()
+        .certificate(default_chain, default_key)
+        .sni_certificate("files.example", files_chain, files_key)
+        .build()
+        .unwrap();
+    let servers = start_servers(config);
+    let client_config = ClientTlsBuilder::new()
+        .root_certificates(ca.root())
+        .build()
+        .unwrap();
+    // WHEN
+    for (name, addr) in servers {
+        let by_name = download(addr, Arc::clone(&client_config), "files.example");
+        let default = download(addr, Arc::clone(&client_config), "localhost");
+        // Gets the default certificate, which is not valid for this name
+        let unknown = download(addr, Arc::clone(&client_config), "other.example");
+        // THEN
+        assert!(by_name.is_ok(), "{} server: {:?}", name, by_name.err());
+        assert!(default.is_ok(), "{} server: {:?}", name, default.err());
+        assert!(unknown.is_err(), "{} server accepted a wrong name", name);
+    }
+}
+
+#[test]
+fn test_mutual_tls_requires_a_trusted_client_certificate() {
+    // GIVEN
+    let ca: Ca = Ca::new();
+    let foreign_ca: Ca = Ca::new();
+    let (chain, key) = ca.issue("localhost", ExtendedKeyUsagePurpose::ServerAuth);
+    let config = ServerTlsBuilder::new()
+        .certificate(chain, key)
+        .client_auth(ca.root())
+        .build()
+        .unwrap();
+    let servers = start_servers(config);
+
+    let client = |identity: Option<&Ca>| {
+        let mut builder = ClientTlsBuilder::new().root_certificates(ca.root());
+        if let Some(issuer) = identity {
+            let (chain, key) = issuer.issue("client", ExtendedKeyUsagePurpose::ClientAuth);
+            builder = builder.certificate(chain, key);
+        }
+        builder.build().unwrap()
+    };
+    // WHEN
+    for (name, addr) in servers {
+        let trusted = download(addr, client(Some(&ca)), "localhost");
+        let foreign = download(addr, client(Some(&foreign_ca)), "localhost");
+        let anonymous = download(addr, client(None), "localhost");
+        // THEN
+        assert!(trusted.is_ok(), "{} server: {:?}", name, trusted.err());
+        assert!(foreign.is_err(), "{} server accepted a foreign certificate", name);
+        assert!(
+            anonymous.is_err(),
+            "{} server accepted a client without a certificate",
+            name
+        );
+    }
+}
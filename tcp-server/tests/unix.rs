@@ -0,0 +1,205 @@
+//! Checks that the servers listen on Unix sockets, both file system paths and abstract names,
+//! that services see the credentials of the peer process and that the client connects over them.
+use std::{env, fs, io, thread};
+use std::path::PathBuf;
+use std::io::Read;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use tcp_server::core::{EpollTcpServer, IterativeTcpServer, ThreadPoolTcpServer};
+#[cfg(feature = "tokio")]
+use tcp_server::core::AsyncTcpServer;
+use tcp_server::listener::{Listener, ToListener, UnixAddr};
+use tcp_server::service::{FileTransferClient, Service};
+use tcp_server::stream::{PeerAddr, PeerCred, Stream};
+use tcp_server::proto::prelude::*;
+
+mod common;
+use common::{get_base_dir, get_service, FILE_NAME, FILE_SIZE, PROTOCOL_VERSION};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Returns a socket path unique to this test run
+fn socket_path(name: &str) -> PathBuf {
+    get_base_dir().join(format!("{}.sock", name))
+}
+
+// Downloads the test file over a Unix socket
+fn download(addr: &UnixAddr) -> io::Result<Vec<u8>> {
+    let mut client = FileTransferClient::connect_unix(addr, PROTOCOL_VERSION)?;
+
+    match client.request_file(FILE_NAME)?.response {
+        Some(Response::Metadata(metadata)) => assert_eq!(metadata.file_size, FILE_SIZE as u64),
+        other => panic!("Expected file metadata, got {:?}", other),
+    }
+    client.send_ack(AckStatus::Accepted)?;
+
+    let mut data: Vec<u8> = Vec::new();
+    client.receive_file(&mut data)?;
+
+    Ok(data)
+}
+
+/// Replies with the credentials the server sees for the client
+struct CredentialsService;
+
+impl Service for CredentialsService {
+    fn handle_connection(&self, mut stream: impl Stream) -> io::Result<()> {
+        let reply: String = match stream.peer_cred()? {
+            Some(cred) => format!("{} {} {}", cred.pid, cred.uid, cred.gid),
+            None => "none".to_string(),
+        };
+        stream.write_all(reply.as_bytes())?;
+        stream.shutdown(Shutdown::Write)
+    }
+}
+
+#[test]
+fn test_every_server_model_serves_over_unix_socket() {
+    // GIVEN
+    let iterative = IterativeTcpServer::new(UnixAddr::path(socket_path("iterative")), get_service()).unwrap();
+    thread::spawn(move || iterative.serve());
+    let thread_pool =
+        ThreadPoolTcpServer::new(UnixAddr::path(socket_path("thread-pool")), get_service(), 2).unwrap();
+    thread::spawn(move || thread_pool.serve());
+    let epoll = EpollTcpServer::new(UnixAddr::path(socket_path("epoll")), get_service(), 1).unwrap();
+    thread::spawn(move || epoll.serve());
+    #[allow(unused_mut)]
+    let mut names: Vec<&str> = vec!["iterative", "thread-pool", "epoll"];
+
+    #[cfg(feature = "tokio")]
+    {
+        let async_ = AsyncTcpServer::new(UnixAddr::path(socket_path("async")), get_service()).unwrap();
+        thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(async_.serve()));
+        names.push("async");
+    }
+    let expected: Vec<u8> = fs::read(get_base_dir().join(FILE_NAME)).unwrap();
+    // WHEN
+    for name in names {
+        let data = download(&UnixAddr::path(socket_path(name)));
+        // THEN
+        assert!(data.unwrap() == expected, "{} server sent different data", name);
+    }
+}
+
+#[test]
+fn test_server_listens_on_abstract_socket() {
+    // GIVEN
+    let addr: UnixAddr = UnixAddr::abstract_name(format!("tcp-server-test-{}", std::process::id()));
+    let server = ThreadPoolTcpServer::new(addr.clone(), get_service(), 1).unwrap();
+    let local_addr = server.local_addr();
+    thread::spawn(move || server.serve());
+    // WHEN
+    let data: Vec<u8> = download(&addr).unwrap();
+    // THEN
+    assert_eq!(data.len(), FILE_SIZE);
+    assert_eq!(local_addr.unwrap_err().kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_services_see_peer_credentials() {
+    // GIVEN
+    let path: PathBuf = socket_path("credentials");
+    let server = IterativeTcpServer::new(UnixAddr::path(&path), CredentialsService).unwrap();
+    thread::spawn(move || server.serve());
+    let tcp_server = IterativeTcpServer::new("127.0.0.1:0", CredentialsService).unwrap();
+    let tcp_addr: SocketAddr = tcp_server.local_addr().unwrap();
+    thread::spawn(move || tcp_server.serve());
+    // WHEN
+    let mut reply: String = String::new();
+    UnixStream::connect(&path)
+        .unwrap()
+        .read_to_string(&mut reply)
+        .unwrap();
+    let mut tcp_reply: String = String::new();
+    TcpStream::connect(tcp_addr)
+        .unwrap()
+        .read_to_string(&mut tcp_reply)
+        .unwrap();
+    // THEN
+    let expected: PeerCred = PeerCred {
+        pid: std::process::id() as i32,
+        uid: unsafe { libc::getuid() },
+        gid: unsafe { libc::getgid() },
+    };
+    assert_eq!(
+        reply,
+        format!("{} {} {}", expected.pid, expected.uid, expected.gid)
+    );
+    assert_eq!(tcp_reply, "none");
+}
+
+#[test]
+fn test_unix_stream_reports_peer_address_and_credentials() {
+    // GIVEN
+    let path: PathBuf = socket_path("peer");
+    let listener: Listener = UnixAddr::path(&path).bind().unwrap();
+    // WHEN
+    let client: UnixStream = UnixStream::connect(&path).unwrap();
+    let (server, peer) = listener.accept().unwrap();
+    // THEN
+    assert_eq!(peer, PeerAddr::Unnamed);
+    assert_eq!(
+        Stream::peer_addr(&client).unwrap(),
+        PeerAddr::Unix(UnixAddr::path(&path))
+    );
+    assert_eq!(
+        server.peer_cred().unwrap().map(|cred| cred.pid),
+        Some(std::process::id() as i32)
+    );
+}
+
+#[test]
+fn test_stale_socket_file_is_replaced_but_live_one_is_not() {
+    // GIVEN
+    let stale: PathBuf = socket_path("stale");
+    drop(UnixListener::bind(&stale).unwrap());
+    let live: PathBuf = socket_path("live");
+    let _listener: UnixListener = UnixListener::bind(&live).unwrap();
+    // WHEN
+    let rebound = UnixAddr::path(&stale).bind();
+    let taken = UnixAddr::path(&live).bind();
+    // THEN
+    assert!(rebound.is_ok());
+    assert!(UnixStream::connect(&stale).is_ok());
+    assert_eq!(taken.unwrap_err().kind(), io::ErrorKind::AddrInUse);
+}
+
+#[test]
+fn test_unix_addr_parses_paths_and_abstract_names() {
+    // WHEN
+    let path: Result<UnixAddr, String> = "/run/tcp-server.sock".parse();
+    let name: Result<UnixAddr, String> = "@tcp-server".parse();
+    let empty: Result<UnixAddr, String> = "@".parse();
+    // THEN
+    assert_eq!(path, Ok(UnixAddr::path("/run/tcp-server.sock")));
+    assert_eq!(name, Ok(UnixAddr::abstract_name("tcp-server")));
+    assert!(empty.is_err());
+    assert_eq!(UnixAddr::abstract_name("tcp-server").to_string(), "@tcp-server");
+}
+
+#[test]
+fn test_server_binary_listens_on_unix_socket() {
+    // GIVEN
+    let path: PathBuf = socket_path("binary");
+    let mut server: Child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg("--unix")
+        .arg(&path)
+        .arg("--dir")
+        .arg(get_base_dir())
+        .spawn()
+        .unwrap();
+    let deadline: Instant = Instant::now() + TIMEOUT;
+    // Probing with a connection would keep the iterative server busy until the handshake times out
+    while !path.exists() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(20));
+    }
+    // WHEN
+    let data = download(&UnixAddr::path(&path));
+    // THEN
+    unsafe { libc::kill(server.id() as i32, libc::SIGTERM) };
+    assert!(server.wait().unwrap().success());
+    assert_eq!(data.unwrap().len(), FILE_SIZE);
+}